@@ -0,0 +1,10 @@
+;; Demonstrates assert and assert-eq. Running this file should print
+;; nothing.
+
+(let square (fn (x) (mul x x)))
+
+(assert (eq (square 3) 9))
+(assert (lt (square 2) 5) "squares of small numbers are small")
+
+(assert-eq (square 4) 16)
+(assert-eq (cons 1 '(2 3)) '(1 2 3))
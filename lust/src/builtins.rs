@@ -2,8 +2,17 @@
 
 use std::cell::RefCell;
 use std::rc::Rc;
+use std::sync::OnceLock;
 
-use crate::interpreter::{CallResult, ConsCell, Interpreter, LustData, LustEnv, LustFn};
+use crate::compile;
+use crate::ffi;
+use crate::intern::{intern, resolve_intern};
+use crate::interpreter::{
+    BuiltinFn, CallResult, ConsCell, Interpreter, LustData, LustEnv, LustFn, Map, Memo, Port,
+    PromiseState, Record, Test,
+};
+use crate::json;
+use crate::parser::Parser;
 
 /// Quotes its argument. The result of evaluating a quoted argument is
 /// the argument.
@@ -12,44 +21,614 @@ pub fn quote(args: &ConsCell, _env: Rc<RefCell<LustEnv>>) -> Result<CallResult,
     Ok(CallResult::Ret(args[0].clone()))
 }
 
-/// Returns the first item in a list or () if the list is empty.
+/// Returns the first item in a list. Errors if the list is empty.
 pub fn car(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
-    check_arg_len("car", 1, args)?;
-    let expr = Interpreter::eval_in_env(&args[0], env)?;
-    let c = LustData::expect_cons(&expr)?;
-    Ok(CallResult::Ret(match *c {
-        ConsCell::Nil => expr,
-        ConsCell::Cons(ref c) => c.data.clone(),
-    }))
+    call_primitive::<1>("car", args, env, car_values)
+}
+
+fn car_values(args: &[LustData]) -> Result<LustData, String> {
+    match **expect_list("car", &args[0])? {
+        ConsCell::Nil => Err("car expected a list that isn't empty, got ()".to_string()),
+        ConsCell::Cons(ref c) => Ok(c.data.clone()),
+    }
 }
 
 /// Takes a list and returns a new list containing all but the first
 /// item in the list.
 pub fn cdr(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
-    check_arg_len("cdr", 1, args)?;
-    let expr = Interpreter::eval_in_env(&args[0], env)?;
-    let c = LustData::expect_cons(&expr)?;
-    Ok(CallResult::Ret(match *c {
-        ConsCell::Nil => expr,
-        ConsCell::Cons(ref c) => LustData::Cons(c.next.clone()),
-    }))
+    call_primitive::<1>("cdr", args, env, cdr_values)
+}
+
+fn cdr_values(args: &[LustData]) -> Result<LustData, String> {
+    match **expect_list("cdr", &args[0])? {
+        ConsCell::Nil => Err("cdr expected a list that isn't empty, got ()".to_string()),
+        ConsCell::Cons(ref c) => Ok(LustData::Cons(c.next.clone())),
+    }
+}
+
+/// Returns the first item of the first item in a list.
+pub fn caar(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    call_primitive::<1>("caar", args, env, |args| walk_list("caar", "aa", &args[0]))
+}
+
+/// Returns the second item in a list.
+pub fn cadr(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    call_primitive::<1>("cadr", args, env, |args| walk_list("cadr", "ad", &args[0]))
+}
+
+/// Returns the first item in a list, which should be a list, without
+/// its first item.
+pub fn cdar(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    call_primitive::<1>("cdar", args, env, |args| walk_list("cdar", "da", &args[0]))
+}
+
+/// Returns a list without its first two items.
+pub fn cddr(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    call_primitive::<1>("cddr", args, env, |args| walk_list("cddr", "dd", &args[0]))
+}
+
+/// Returns the third item in a list.
+pub fn caddr(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    call_primitive::<1>("caddr", args, env, |args| {
+        walk_list("caddr", "add", &args[0])
+    })
+}
+
+/// Returns a list without its first three items.
+pub fn cdddr(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    call_primitive::<1>("cdddr", args, env, |args| {
+        walk_list("cdddr", "ddd", &args[0])
+    })
+}
+
+/// Returns the first item in a list. The same as `car`.
+pub fn first(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    call_primitive::<1>("first", args, env, |args| walk_list("first", "a", &args[0]))
+}
+
+/// Returns the second item in a list.
+pub fn second(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    call_primitive::<1>("second", args, env, |args| {
+        walk_list("second", "ad", &args[0])
+    })
+}
+
+/// Returns the third item in a list.
+pub fn third(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    call_primitive::<1>("third", args, env, |args| {
+        walk_list("third", "add", &args[0])
+    })
+}
+
+/// Returns a list without its first item. The same as `cdr`.
+pub fn rest(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    call_primitive::<1>("rest", args, env, |args| walk_list("rest", "d", &args[0]))
+}
+
+/// Applies `car` and `cdr` to LIST as PATH says, for the builtin
+/// called NAME. PATH is read right to left like the middle of the
+/// name `cadr`: `a` for `car` and `d` for `cdr`. Errors name the list
+/// the builtin was given.
+fn walk_list(name: &str, path: &str, list: &LustData) -> Result<LustData, String> {
+    let mut data = list.clone();
+    for step in path.chars().rev() {
+        let cell = match data {
+            LustData::Cons(ref c) => c.clone(),
+            _ => {
+                return Err(format!(
+                    "{} expected a list, got {} in {}",
+                    name, data, list
+                ))
+            }
+        };
+        data = match (&*cell, step) {
+            (ConsCell::Nil, _) => {
+                return Err(format!(
+                    "{} expected a list that isn't empty, got () in {}",
+                    name, list
+                ))
+            }
+            (ConsCell::Cons(c), 'a') => c.data.clone(),
+            (ConsCell::Cons(c), _) => LustData::Cons(c.next.clone()),
+        };
+    }
+    Ok(data)
+}
+
+/// Takes a list and returns its first item, or () if it is empty.
+pub fn car_p(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    call_primitive::<1>("car?", args, env, |args| {
+        Ok(match **expect_list("car?", &args[0])? {
+            ConsCell::Nil => LustData::get_empty_list(),
+            ConsCell::Cons(ref c) => c.data.clone(),
+        })
+    })
+}
+
+/// Takes an index and a list and returns the item at that index. The
+/// first item is at index 0. Errors if the list is too short.
+pub fn nth(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("nth", 2, args)?;
+    let n = eval_count("nth", &args[0], env.clone())?;
+    let list = Interpreter::eval_in_env(&args[1], env)?;
+    match expect_list("nth", &list)?.into_iter().nth(n) {
+        Some(item) => Ok(CallResult::Ret(item.clone())),
+        None => Err(format!(
+            "nth expected a list of more than {} items, got {}",
+            n, list
+        )),
+    }
+}
+
+/// Same as `nth` but returns () if the list is too short.
+pub fn nth_p(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("nth?", 2, args)?;
+    let n = eval_count("nth?", &args[0], env.clone())?;
+    let list = Interpreter::eval_in_env(&args[1], env)?;
+    let item = expect_list("nth?", &list)?.into_iter().nth(n).cloned();
+    Ok(CallResult::Ret(
+        item.unwrap_or_else(LustData::get_empty_list),
+    ))
+}
+
+/// Returns DATA as a list or an error naming NAME if it is not one.
+fn expect_list<'a>(name: &str, data: &'a LustData) -> Result<&'a Rc<ConsCell>, String> {
+    data.as_list()
+        .map_err(|_| format!("{} expected a list, got {}", name, data.type_name()))
 }
 
 /// Prepends its first argument to its second argument where the
-/// second argument is a list.
+/// second argument is a list. Lists are always proper, there are no
+/// dotted pairs, so the second argument must be a list. Use `pair`
+/// to put two values that aren't lists together.
 pub fn cons(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
-    check_arg_len("cons", 2, args)?;
-    let prepend = Interpreter::eval_in_env(&args[0], env.clone())?;
-    let expr = Interpreter::eval_in_env(&args[1], env)?;
-    let expr = if expr.is_imutable() {
-        expr.deep_clone(true)
+    call_primitive::<2>("cons", args, env, cons_values)
+}
+
+fn cons_values(args: &[LustData]) -> Result<LustData, String> {
+    let prepend = args[0].clone();
+    let expr = if args[1].is_imutable() {
+        args[1].deep_clone(true)
     } else {
-        expr
+        args[1].clone()
     };
-    let c = LustData::expect_cons(&expr)?;
-    Ok(CallResult::Ret(LustData::Cons(Rc::new(
-        ConsCell::push_front(c, prepend),
-    ))))
+    let c = expr
+        .as_list()
+        .map_err(|_| {
+            format!(
+                "cons expected a list as its second argument, got {}",
+                args[1].type_name()
+            )
+        })?
+        .clone();
+    Ok(LustData::Cons(Rc::new(ConsCell::push_front(c, prepend))))
+}
+
+/// Takes two values and returns a list of them. Where other lisps
+/// would make a dotted pair with `(cons a b)`, use `(pair a b)`.
+pub fn pair(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    call_primitive::<2>("pair", args, env, |args| Ok(LustData::list(args.to_vec())))
+}
+
+/// Takes a list and returns a list of its items with the items of the
+/// lists in it spliced in their place. Only one level of nesting is
+/// removed and strings are kept whole.
+pub fn flatten(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("flatten", 1, args)?;
+    let list = Interpreter::eval_in_env(&args[0], env)?;
+    let mut items = Vec::new();
+    for item in list.as_list()?.into_iter() {
+        match item {
+            LustData::Cons(_) if item.stringify().is_none() => {
+                items.extend(item.as_list()?.into_iter().cloned())
+            }
+            item => items.push(item.clone()),
+        }
+    }
+    Ok(CallResult::Ret(LustData::list(items)))
+}
+
+/// Takes a list and returns a list of the items in it and in the
+/// lists nested in it, in order. Strings are kept whole. An item that
+/// isn't a list is returned in a list of its own. Works without
+/// recursion so deeply nested lists don't overflow the stack.
+pub fn flatten_deep(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("flatten-deep", 1, args)?;
+    let val = Interpreter::eval_in_env(&args[0], env)?;
+    // The rests of the lists being flattened, innermost last.
+    let mut work = match val {
+        LustData::Cons(ref c) if val.stringify().is_none() => vec![c.clone()],
+        atom => return Ok(CallResult::Ret(LustData::list(vec![atom]))),
+    };
+    let mut items = Vec::new();
+    while let Some(list) = work.pop() {
+        if let ConsCell::Cons(ref cons) = *list {
+            work.push(cons.next.clone());
+            match cons.data {
+                LustData::Cons(ref c) if cons.data.stringify().is_none() => work.push(c.clone()),
+                ref item => items.push(item.clone()),
+            }
+        }
+    }
+    Ok(CallResult::Ret(LustData::list(items)))
+}
+
+/// Takes a list of lists and returns a list of their items, in
+/// order. Only one level of nesting is removed.
+pub fn concatenate(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("concatenate", 1, args)?;
+    let lists = Interpreter::eval_in_env(&args[0], env)?;
+    let mut items = Vec::new();
+    for list in lists.as_list()?.into_iter() {
+        items.extend(list.as_list()?.into_iter().cloned());
+    }
+    Ok(CallResult::Ret(LustData::list(items)))
+}
+
+/// Takes a function and a list, calls the function on each item in
+/// the list, and returns the items of the lists it returns, in order.
+pub fn flatten_map(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("flatten-map", 2, args)?;
+    let func = Interpreter::eval_in_env(&args[0], env.clone())?;
+    let list = Interpreter::eval_in_env(&args[1], env.clone())?;
+    let mut items = Vec::new();
+    for item in list.as_list()?.into_iter() {
+        let mapped = compile::apply(func.clone(), vec![item.clone()], &env)?;
+        items.extend(mapped.as_list()?.into_iter().cloned());
+    }
+    Ok(CallResult::Ret(LustData::list(items)))
+}
+
+/// Takes one or more lists and returns a list of lists where the
+/// first holds the first item of each list, the second the second
+/// items, and so on. Stops at the end of the shortest list.
+pub fn zip(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    if args.len() == 0 {
+        return Err("zip expected at least one list".to_string());
+    }
+    let mut lists = Vec::with_capacity(args.len());
+    for arg in args {
+        lists.push(Interpreter::eval_in_env(arg, env.clone())?.into_list()?);
+    }
+    let len = lists.iter().map(Vec::len).min().unwrap_or(0);
+    let zipped = (0..len)
+        .map(|i| LustData::list(lists.iter().map(|l| l[i].clone()).collect()))
+        .collect();
+    Ok(CallResult::Ret(LustData::list(zipped)))
+}
+
+/// Takes a list of pairs and returns a pair of lists. The first list
+/// holds the first item of each pair and the second the second.
+pub fn unzip(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("unzip", 1, args)?;
+    let (mut firsts, mut seconds) = (Vec::new(), Vec::new());
+    for pair in Interpreter::eval_in_env(&args[0], env)?.into_list()? {
+        match pair {
+            LustData::Cons(ref c) if c.len() == 2 => {
+                firsts.push(c[0].clone());
+                seconds.push(c[1].clone());
+            }
+            _ => return Err(format!("unzip expected a list of pairs, got {}", pair)),
+        }
+    }
+    Ok(CallResult::Ret(LustData::list(vec![
+        LustData::list(firsts),
+        LustData::list(seconds),
+    ])))
+}
+
+/// Takes a count and a value and returns a list of count copies of
+/// the value. The value is evaluated once.
+pub fn repeat(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("repeat", 2, args)?;
+    let count = eval_count("repeat", &args[0], env.clone())?;
+    let val = Interpreter::eval_in_env(&args[1], env)?;
+    Ok(CallResult::Ret(LustData::list(vec![val; count])))
+}
+
+/// Takes a count, and optionally a start and a step, and returns a
+/// list of count numbers starting at start, 0 by default, with each
+/// step, 1 by default, more than the one before.
+pub fn iota(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_range("iota", 1, 3, args)?;
+    let count = eval_count("iota", &args[0], env.clone())?;
+    let start = if args.len() > 1 {
+        eval_number("iota", &args[1], env.clone())?
+    } else {
+        0.0
+    };
+    let step = if args.len() > 2 {
+        eval_number("iota", &args[2], env)?
+    } else {
+        1.0
+    };
+    // Multiplying rather than adding up the steps keeps fractional
+    // steps from accumulating error.
+    let items = (0..count)
+        .map(|i| LustData::Number(start + i as f32 * step))
+        .collect();
+    Ok(CallResult::Ret(LustData::list(items)))
+}
+
+/// Takes a count and a list and returns the first count items of the
+/// list, or all of them if it is shorter.
+pub fn take(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("take", 2, args)?;
+    let n = eval_count("take", &args[0], env.clone())?;
+    let list = Interpreter::eval_in_env(&args[1], env)?;
+    let items = list.as_list()?.into_iter().take(n).cloned().collect();
+    Ok(CallResult::Ret(LustData::list(items)))
+}
+
+/// Takes a count and a list and returns the list without its first
+/// count items, or the empty list if it is shorter. The result shares
+/// its items with the list.
+pub fn drop_(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("drop", 2, args)?;
+    let n = eval_count("drop", &args[0], env.clone())?;
+    let list = Interpreter::eval_in_env(&args[1], env)?;
+    let mut rest = list.as_list()?.clone();
+    for _ in 0..n {
+        rest = match *rest {
+            ConsCell::Nil => break,
+            ConsCell::Cons(ref c) => c.next.clone(),
+        };
+    }
+    Ok(CallResult::Ret(LustData::Cons(rest)))
+}
+
+/// Takes a predicate and a list and returns the items at the start of
+/// the list that the predicate is true for.
+pub fn take_while(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("take-while", 2, args)?;
+    let pred = Interpreter::eval_in_env(&args[0], env.clone())?;
+    let list = Interpreter::eval_in_env(&args[1], env.clone())?;
+    let mut items = Vec::new();
+    for item in list.as_list()?.into_iter() {
+        if !truthy(&compile::apply(pred.clone(), vec![item.clone()], &env)?) {
+            break;
+        }
+        items.push(item.clone());
+    }
+    Ok(CallResult::Ret(LustData::list(items)))
+}
+
+/// Takes a predicate and a list and returns the list from the first
+/// item the predicate is false for. The result shares its items with
+/// the list.
+pub fn drop_while(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("drop-while", 2, args)?;
+    let pred = Interpreter::eval_in_env(&args[0], env.clone())?;
+    let list = Interpreter::eval_in_env(&args[1], env.clone())?;
+    let mut rest = list.as_list()?.clone();
+    while let ConsCell::Cons(ref c) = *rest.clone() {
+        if !truthy(&compile::apply(pred.clone(), vec![c.data.clone()], &env)?) {
+            break;
+        }
+        rest = c.next.clone();
+    }
+    Ok(CallResult::Ret(LustData::Cons(rest)))
+}
+
+/// Searches the list ARGS[1] evaluates to for the first item which
+/// the predicate ARGS[0] evaluates to is WANT for. Returns the item
+/// and its index.
+fn search(
+    name: &str,
+    args: &ConsCell,
+    env: Rc<RefCell<LustEnv>>,
+    want: bool,
+) -> Result<Option<(usize, LustData)>, String> {
+    check_arg_len(name, 2, args)?;
+    let pred = Interpreter::eval_in_env(&args[0], env.clone())?;
+    let list = Interpreter::eval_in_env(&args[1], env.clone())?;
+    for (i, item) in list.as_list()?.into_iter().enumerate() {
+        if truthy(&compile::apply(pred.clone(), vec![item.clone()], &env)?) == want {
+            return Ok(Some((i, item.clone())));
+        }
+    }
+    Ok(None)
+}
+
+/// Takes a predicate and a list and returns the first item the
+/// predicate is true for, or the empty list if there isn't one.
+pub fn find(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    let found = search("find", args, env, true)?;
+    Ok(CallResult::Ret(
+        found.map_or_else(LustData::get_empty_list, |(_, item)| item),
+    ))
+}
+
+/// Takes a predicate and a list and returns the index of the first
+/// item the predicate is true for, or the empty list if there isn't
+/// one.
+pub fn find_index(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    let found = search("find-index", args, env, true)?;
+    Ok(CallResult::Ret(
+        found.map_or_else(LustData::get_empty_list, |(i, _)| {
+            LustData::Number(i as f32)
+        }),
+    ))
+}
+
+/// Takes a predicate and a list and returns how many items in the
+/// list the predicate is true for.
+pub fn count(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    count_matching("count", args, env)
+}
+
+/// Same as `count`. Named after the Common Lisp function.
+pub fn count_if(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    count_matching("count-if", args, env)
+}
+
+fn count_matching(
+    name: &str,
+    args: &ConsCell,
+    env: Rc<RefCell<LustEnv>>,
+) -> Result<CallResult, String> {
+    check_arg_len(name, 2, args)?;
+    let pred = Interpreter::eval_in_env(&args[0], env.clone())?;
+    let list = Interpreter::eval_in_env(&args[1], env.clone())?;
+    let mut count = 0;
+    for item in list.as_list()?.into_iter() {
+        if truthy(&compile::apply(pred.clone(), vec![item.clone()], &env)?) {
+            count += 1;
+        }
+    }
+    Ok(CallResult::Ret(LustData::Number(count as f32)))
+}
+
+/// Takes a predicate and a list and returns true if the predicate is
+/// true for any item in the list. Stops at the first one it is.
+pub fn any_p(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    let found = search("any?", args, env, true)?;
+    Ok(CallResult::Ret(get_truthy_equiv(found.is_some())))
+}
+
+/// Takes a predicate and a list and returns true if the predicate is
+/// true for every item in the list. Stops at the first one it isn't.
+pub fn every_p(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    let found = search("every?", args, env, false)?;
+    Ok(CallResult::Ret(get_truthy_equiv(found.is_none())))
+}
+
+/// Same as `every?`.
+pub fn all_p(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    let found = search("all?", args, env, false)?;
+    Ok(CallResult::Ret(get_truthy_equiv(found.is_none())))
+}
+
+/// Takes a list of numbers and returns them sorted in ascending
+/// order.
+pub fn sort(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("sort", 1, args)?;
+    let mut numbers = Interpreter::eval_in_env(&args[0], env)?
+        .as_list()?
+        .into_iter()
+        .map(|item| expect_number("sort", item))
+        .collect::<Result<Vec<_>, _>>()?;
+    numbers.sort_by(f32::total_cmp);
+    Ok(CallResult::Ret(LustData::list(
+        numbers.into_iter().map(LustData::Number).collect(),
+    )))
+}
+
+/// Takes a comparator and a list and returns the list sorted by the
+/// comparator. The comparator is called with two items and returns
+/// either a number, which is negative if the first item goes first,
+/// or true if the first item goes first, as `lt` does. Items the
+/// comparator considers equal keep their order.
+pub fn sort_by(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("sort-by", 2, args)?;
+    let compare = Interpreter::eval_in_env(&args[0], env.clone())?;
+    let items = Interpreter::eval_in_env(&args[1], env.clone())?.into_list()?;
+    let mut before = |a: &LustData, b: &LustData| match compile::apply(
+        compare.clone(),
+        vec![a.clone(), b.clone()],
+        &env,
+    )? {
+        LustData::Number(n) => Ok(n < 0.0),
+        res => Ok(truthy(&res)),
+    };
+    Ok(CallResult::Ret(LustData::list(merge_sort(
+        items,
+        &mut before,
+    )?)))
+}
+
+/// Takes a predicate and a list and returns a list of two lists. The
+/// first holds the items the predicate returns true for and the
+/// second the rest. Items keep their order.
+///
+/// Given a count instead of a predicate, returns a list of lists of
+/// that many items from the list, in order. The last may be shorter.
+pub fn partition(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("partition", 2, args)?;
+    let pred = Interpreter::eval_in_env(&args[0], env.clone())?;
+    let items = Interpreter::eval_in_env(&args[1], env.clone())?.into_list()?;
+    if let LustData::Number(n) = pred {
+        if n < 1.0 || n.fract() != 0.0 {
+            return Err(format!(
+                "partition expected a count of one or more, got {}",
+                n
+            ));
+        }
+        return Ok(CallResult::Ret(LustData::list(
+            items
+                .chunks(n as usize)
+                .map(|chunk| LustData::list(chunk.to_vec()))
+                .collect(),
+        )));
+    }
+    let (mut matched, mut rest) = (Vec::new(), Vec::new());
+    for item in items {
+        if truthy(&compile::apply(pred.clone(), vec![item.clone()], &env)?) {
+            matched.push(item);
+        } else {
+            rest.push(item);
+        }
+    }
+    Ok(CallResult::Ret(LustData::list(vec![
+        LustData::list(matched),
+        LustData::list(rest),
+    ])))
+}
+
+/// Takes a key function and a list and groups the items by the key
+/// the function returns for them. Returns an association list with
+/// a list of each key followed by the items with that key. Keys are
+/// in the order they were first seen and items keep their order.
+pub fn group_by(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("group-by", 2, args)?;
+    let key_fn = Interpreter::eval_in_env(&args[0], env.clone())?;
+    let items = Interpreter::eval_in_env(&args[1], env.clone())?.into_list()?;
+    // Keys are compared with PartialEq as not all data can be hashed.
+    let mut groups: Vec<(LustData, Vec<LustData>)> = Vec::new();
+    for item in items {
+        let key = compile::apply(key_fn.clone(), vec![item.clone()], &env)?;
+        match groups.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, group)) => group.push(item),
+            None => groups.push((key, vec![item])),
+        }
+    }
+    Ok(CallResult::Ret(LustData::list(
+        groups
+            .into_iter()
+            .map(|(key, mut group)| {
+                group.insert(0, key);
+                LustData::list(group)
+            })
+            .collect(),
+    )))
+}
+
+/// Sorts ITEMS such that no item comes after an item BEFORE says it
+/// goes before. This is a stable merge sort. Unlike `Vec::sort_by` it
+/// can stop on an error and doesn't panic if BEFORE is inconsistent.
+fn merge_sort<F>(mut items: Vec<LustData>, before: &mut F) -> Result<Vec<LustData>, String>
+where
+    F: FnMut(&LustData, &LustData) -> Result<bool, String>,
+{
+    if items.len() < 2 {
+        return Ok(items);
+    }
+    let right = items.split_off(items.len() / 2);
+    let mut left = merge_sort(items, before)?.into_iter().peekable();
+    let mut right = merge_sort(right, before)?.into_iter().peekable();
+    let mut res = Vec::with_capacity(left.len() + right.len());
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        // Items from the left go first unless the right one must
+        // come before it, which keeps the sort stable.
+        if before(r, l)? {
+            res.extend(right.next());
+        } else {
+            res.extend(left.next());
+        }
+    }
+    res.extend(left);
+    res.extend(right);
+    Ok(res)
 }
 
 /// Takes arguments COND THEN ELSE. If COND is true evaluates and
@@ -65,6 +644,138 @@ pub fn if_(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, Str
     })
 }
 
+/// Takes a value and clauses of the form (pattern result). Evaluates
+/// the value and then the result of the first clause whose pattern
+/// matches it. Patterns are:
+///
+/// - Numbers and quoted data, which match values equal to them.
+/// - `_`, which matches anything.
+/// - Other symbols, which match anything and bind the value to the
+///   symbol while the result is evaluated.
+/// - Lists of patterns, which match lists whose items match them. A
+///   pattern after `&` matches the rest of the list, as in `(x & xs)`.
+/// - `(list patterns...)`, which is the same as `(patterns...)`.
+/// - `(cons head tail)`, which matches a list that isn't empty if its
+///   first item matches HEAD and the rest of it matches TAIL.
+///
+/// Errors if no clause matches.
+pub fn match_(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    if args.len() == 0 {
+        return Err("match expected a value".to_string());
+    }
+    let val = Interpreter::eval_in_env(&args[0], env.clone())?;
+    match_clauses("match", &val, args.nth_item(1), env)?
+        .ok_or_else(|| format!("match found no clause matching {}", val))
+}
+
+/// Finds the first of CLAUSES, each a (pattern result) list, whose
+/// pattern matches VAL. Returns a call evaluating its result in a
+/// child of ENV holding the pattern's bindings, or None if no clause
+/// matches. NAME is the builtin reported in errors.
+fn match_clauses(
+    name: &str,
+    val: &LustData,
+    clauses: &ConsCell,
+    env: Rc<RefCell<LustEnv>>,
+) -> Result<Option<CallResult>, String> {
+    for clause in clauses {
+        let (pattern, result) = match clause {
+            LustData::Cons(ref c) if c.len() == 2 => (&c[0], &c[1]),
+            _ => {
+                return Err(format!(
+                    "{} expected a clause like (pattern result), got {}",
+                    name, clause
+                ))
+            }
+        };
+        let mut bindings = Vec::new();
+        if match_pattern(pattern, val, &mut bindings)? {
+            let scope = LustEnv::new_child(env);
+            for (name, val) in bindings {
+                scope.borrow_mut().insert_symbol(name, val);
+            }
+            return Ok(Some(CallResult::Call(scope, result.clone())));
+        }
+    }
+    Ok(None)
+}
+
+/// Determines if VAL matches PATTERN, a `match` pattern. Adds the
+/// symbols the pattern binds and their values to BINDINGS.
+fn match_pattern(
+    pattern: &LustData,
+    val: &LustData,
+    bindings: &mut Vec<(usize, LustData)>,
+) -> Result<bool, String> {
+    let items = match pattern {
+        LustData::Symbol(s) => {
+            if resolve_intern(*s) != "_" {
+                bindings.push((*s, val.clone()));
+            }
+            return Ok(true);
+        }
+        LustData::Cons(ref c) => c,
+        _ => return Ok(pattern == val),
+    };
+    let (head, tail) = match **items {
+        ConsCell::Cons(ref c) => match c.data {
+            LustData::Symbol(s) => (resolve_intern(s), c.next.clone()),
+            _ => ("", c.next.clone()),
+        },
+        ConsCell::Nil => ("", items.clone()),
+    };
+    match (head, items.len()) {
+        ("quote", 2) => return Ok(&items[1] == val),
+        ("list", _) => return match_pattern(&LustData::Cons(tail), val, bindings),
+        ("cons", 3) => {
+            return Ok(match val {
+                LustData::Cons(ref c) => match **c {
+                    ConsCell::Cons(ref c) => {
+                        match_pattern(&items[1], &c.data, bindings)?
+                            && match_pattern(&items[2], &LustData::Cons(c.next.clone()), bindings)?
+                    }
+                    ConsCell::Nil => false,
+                },
+                _ => false,
+            })
+        }
+        ("cons", _) => {
+            return Err(format!(
+                "match expected a pattern like (cons head tail), got {}",
+                pattern
+            ))
+        }
+        _ => {}
+    }
+    let mut rest = match val {
+        LustData::Cons(ref c) => c.clone(),
+        _ => return Ok(false),
+    };
+    let mut patterns = items.into_iter();
+    while let Some(pattern) = patterns.next() {
+        if matches!(pattern, LustData::Symbol(s) if resolve_intern(*s) == "&") {
+            return match (patterns.next(), patterns.next()) {
+                (Some(pattern), None) => match_pattern(pattern, &LustData::Cons(rest), bindings),
+                _ => Err(format!(
+                    "match expected one pattern after & in {}",
+                    LustData::Cons(items.clone())
+                )),
+            };
+        }
+        let next = match *rest {
+            ConsCell::Nil => return Ok(false),
+            ConsCell::Cons(ref c) => {
+                if !match_pattern(pattern, &c.data, bindings)? {
+                    return Ok(false);
+                }
+                c.next.clone()
+            }
+        };
+        rest = next;
+    }
+    Ok(rest.len() == 0)
+}
+
 /// Calls back into the interpreter to evaluate its argument.
 pub fn eval(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
     check_arg_len("eval", 1, args)?;
@@ -72,16 +783,228 @@ pub fn eval(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, St
     Ok(CallResult::Ret(Interpreter::eval_in_env(&arg, env)?))
 }
 
+/// Evaluates its argument and returns true if the call to `tail-call?`
+/// was in tail position. Calls that aren't in tail position are on
+/// top of the call stack while they are evaluated. Useful for checking
+/// that a recursive call won't grow the stack.
+pub fn tail_call_p(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("tail-call?", 1, args)?;
+    Interpreter::eval_in_env(&args[0], env)?;
+    Ok(CallResult::Ret(get_truthy_equiv(
+        !Interpreter::is_innermost_call(args),
+    )))
+}
+
 /// Same as set above but binds the value in the local enviroment.
+/// This is how names are defined, so the binding outlives the `let`.
+/// Given a list of bindings instead of a name, as in `(let ((x 1) (y
+/// 2)) body...)`, binds them in a new enviroment that only the body
+/// sees. See `scoped_let`.
 pub fn let_(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    if args.len() > 0 && matches!(args[0], LustData::Cons(_)) {
+        return scoped_let(args, env);
+    }
     check_arg_len("let", 2, args)?;
     // let target = Interpreter::eval_in_env(&args[0], env.clone())?;
     let target = LustData::expect_symbol(&args[0])?;
+    Interpreter::check_shadow(target, &env)?;
+    let mut val = Interpreter::eval_in_env(&args[1], env.clone())?;
+    name_fn(target, &mut val);
+    env.borrow_mut().insert(target.to_string(), val.clone());
+    Ok(CallResult::Ret(val))
+}
+
+/// Takes a name and a value and binds the name to the value like
+/// `let` does. The name becomes a dynamic variable which `binding` can
+/// give a different value while it evaluates some code.
+pub fn defdynamic(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("defdynamic", 2, args)?;
+    let target = args[0].expect_symbol()?;
+    Interpreter::check_shadow(target, &env)?;
     let val = Interpreter::eval_in_env(&args[1], env.clone())?;
-    env.borrow_mut().insert(target.clone(), val.clone());
+    let id = intern(target);
+    env.borrow_mut().insert_symbol(id, val.clone());
+    Interpreter::declare_dynamic(id, env);
     Ok(CallResult::Ret(val))
 }
 
+/// Takes a list of bindings of dynamic variables to values, as in
+/// `(binding ((*width* 120)) body...)`, and a body. The values are
+/// evaluated and then each variable is set to its value while the
+/// body is evaluated, including in the functions the body calls.
+/// Afterwards the variables are set back, even if the body errors.
+/// Returns the value of the last expression in the body.
+pub fn binding(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    if args.len() < 2 {
+        return Err("binding expected a list of bindings and a body".to_string());
+    }
+    let mut bindings = Vec::new();
+    for binding in args[0].as_list()?.into_iter() {
+        let (name, value) = match binding {
+            LustData::Cons(ref c) if c.len() == 2 => (c[0].expect_symbol()?, &c[1]),
+            _ => {
+                return Err(format!(
+                    "binding expected a binding like (name value), got {}",
+                    binding
+                ))
+            }
+        };
+        let id = intern(name);
+        let declared_in = Interpreter::dynamic_env(id)
+            .ok_or_else(|| format!("binding expected a dynamic variable, got {}", name))?;
+        bindings.push((
+            id,
+            declared_in,
+            Interpreter::eval_in_env(value, env.clone())?,
+        ));
+    }
+    // Variables are set in the enviroment they were declared in, so
+    // everything that sees them sees the new values. The old values
+    // are swapped back in afterwards.
+    let swap = |bindings: &mut Vec<(usize, Rc<RefCell<LustEnv>>, LustData)>| {
+        for (id, declared_in, val) in bindings.iter_mut() {
+            let old = declared_in.borrow().resolve_symbol(*id)?;
+            declared_in
+                .borrow_mut()
+                .insert_symbol(*id, std::mem::replace(val, old));
+        }
+        Ok::<(), String>(())
+    };
+    swap(&mut bindings)?;
+    let mut res = Ok(LustData::get_empty_list());
+    for expr in args.into_iter().skip(1) {
+        res = Interpreter::eval_in_env(expr, env.clone());
+        if res.is_err() {
+            break;
+        }
+    }
+    bindings.reverse();
+    swap(&mut bindings)?;
+    Ok(CallResult::Ret(res?))
+}
+
+/// Evaluates a `let` with a list of bindings. Each binding is a list
+/// of a name and a value. The values are evaluated in order in a
+/// child of ENV, so later values can refer to earlier names. The body
+/// expressions are then evaluated in that enviroment and the value of
+/// the last is returned. Nothing is bound in ENV.
+fn scoped_let(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    if args.len() < 2 {
+        return Err("let expected a list of bindings and a body".to_string());
+    }
+    let scope = LustEnv::new_child(env);
+    for binding in args[0].as_list()?.into_iter() {
+        let (name, value) = match binding {
+            LustData::Cons(ref c) if c.len() == 2 => (c[0].expect_symbol()?, &c[1]),
+            _ => {
+                return Err(format!(
+                    "let expected a binding like (name value), got {}",
+                    binding
+                ))
+            }
+        };
+        Interpreter::check_shadow(name, &scope)?;
+        let mut val = Interpreter::eval_in_env(value, scope.clone())?;
+        name_fn(name, &mut val);
+        scope.borrow_mut().insert(name.to_string(), val);
+    }
+    let body = args.nth_item(1);
+    let last = body.len() - 1;
+    for expr in body.into_iter().take(last) {
+        Interpreter::eval_in_env(expr, scope.clone())?;
+    }
+    Ok(CallResult::Call(scope, body[last].clone()))
+}
+
+/// The name `loop` binds to the function that runs its body. It is
+/// bound as the function's last parameter, so `recur` can find the
+/// nearest loop, without the function's enviroment holding the
+/// function.
+const LOOP: &str = "#loop";
+
+/// Takes a list of bindings like `let` does and a body, as in
+///
+/// ```lisp
+/// (loop ((n 5) (acc 1))
+///   (if (eq n 0) acc (recur (sub n 1) (mul acc n))))
+/// ```
+///
+/// Evaluates the body with the names bound to their values and
+/// returns the value of the last expression. Calling `recur` in the
+/// body evaluates it again with the names bound to its arguments.
+/// When `recur` is in tail position this doesn't grow the stack.
+pub fn loop_(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    if args.len() < 2 {
+        return Err("loop expected a list of bindings and a body".to_string());
+    }
+    // As with let, later values can refer to earlier names.
+    let scope = LustEnv::new_child(env.clone());
+    let mut params = Vec::new();
+    let mut values = Vec::new();
+    for binding in args[0].as_list()?.into_iter() {
+        let (name, value) = match binding {
+            LustData::Cons(ref c) if c.len() == 2 => (c[0].expect_symbol()?, &c[1]),
+            _ => {
+                return Err(format!(
+                    "loop expected a binding like (name value), got {}",
+                    binding
+                ))
+            }
+        };
+        Interpreter::check_shadow(name, &scope)?;
+        let val = Interpreter::eval_in_env(value, scope.clone())?;
+        scope.borrow_mut().insert(name.to_string(), val.clone());
+        params.push(name.to_string());
+        values.push(val);
+    }
+    params.push(LOOP.to_string());
+    let mut func = LustFn::new(params, fn_body(args.nth_item(1).into_iter()), env.clone());
+    func.name = Some("loop".to_string());
+    func.defined_at = Interpreter::current_location();
+    let func = LustData::Fn(Rc::new(func));
+    Ok(CallResult::Call(env, loop_call(func, values)))
+}
+
+/// Builds a call of FUNC, the function made by `loop`, with VALUES.
+fn loop_call(func: LustData, values: Vec<LustData>) -> LustData {
+    let quoted = |v| LustData::list(vec![LustData::symbol("quote"), v]);
+    let mut items = vec![func.clone()];
+    items.extend(values.into_iter().map(quoted));
+    items.push(func);
+    LustData::list(items)
+}
+
+/// Evaluates the body of the nearest `loop` again with its names bound
+/// to the arguments. Errors outside of a loop.
+pub fn recur(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    let func = match env.borrow().get(LOOP) {
+        Some(LustData::Fn(func)) => func,
+        _ => return Err("recur must be called inside a loop".to_string()),
+    };
+    let arity = func.params.len() - 1;
+    check_arg_len("recur", arity, args)?;
+    let mut values = Vec::with_capacity(arity);
+    for arg in args {
+        values.push(Interpreter::eval_in_env(arg, env.clone())?);
+    }
+    Ok(CallResult::Call(env, loop_call(LustData::Fn(func), values)))
+}
+
+/// Names VAL after TARGET if it is a function or macro without a
+/// name. Functions are named after the first binding they are given
+/// so that errors can refer to them. A function that is already
+/// shared is left unnamed, as naming a copy would make it a different
+/// function to `eq`.
+pub(crate) fn name_fn(target: &str, val: &mut LustData) {
+    if let LustData::Fn(ref mut f) | LustData::Mac(ref mut f) = val {
+        if let Some(f) = Rc::get_mut(f) {
+            if f.name.is_none() {
+                f.name = Some(target.to_string());
+            }
+        }
+    }
+}
+
 /// Takes two arguments PARAMS and BODY. PARAMS is a list of symbols
 /// that will be bound to arguments when the function is called and
 /// BODY is an expression to evaluate and return the result of when
@@ -90,30 +1013,168 @@ pub fn let_(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, St
 /// called the additional argument will be bound to a list containing
 /// any remaining arguments after the first arguments have been bound
 /// to values.
+///
+/// Functions can instead be given clauses of the form (params body),
+/// as in `(fn ((0) 1) ((n) (mul n (f (sub n 1)))))`. A call evaluates
+/// the body of the first clause whose parameter list matches the
+/// arguments as a `match` pattern would, so parameters can be
+/// literals and clauses can take different numbers of arguments.
 pub fn fn_(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
-    check_arg_len("fn", 2, args)?;
-    let params = collect_param_list(&args[0])?;
-    // Function bodies shouldn't be modified after creation.
-    let body = args[1].deep_clone(false);
-    Ok(CallResult::Ret(LustData::Fn(Box::new(LustFn {
-        params,
-        body,
+    if is_fn_clauses(args) {
+        return Ok(CallResult::Ret(LustData::Fn(Rc::new(make_clause_fn(
+            "fn", args, env,
+        )?))));
+    }
+    Ok(CallResult::Ret(LustData::Fn(Rc::new(make_fn(
+        "fn", args, env,
+    )?))))
+}
+
+/// Are ARGS to `fn` a list of clauses rather than a parameter list
+/// and a body? Clauses start with a list where a parameter list
+/// starts with a symbol.
+fn is_fn_clauses(args: &ConsCell) -> bool {
+    args.len() > 0
+        && args.into_iter().all(|clause| match clause {
+            LustData::Cons(ref c) => {
+                matches!(**c, ConsCell::Cons(ref c) if matches!(c.data, LustData::Cons(_)))
+            }
+            _ => false,
+        })
+}
+
+/// The name the arguments to a function with clauses are bound to.
+const CLAUSE_ARGS: &str = "#fn-args";
+
+/// Takes clauses of the form (params body) and returns a function
+/// made from them the same way `fn` does.
+pub fn case_lambda(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    Ok(CallResult::Ret(LustData::Fn(Rc::new(make_clause_fn(
+        "case-lambda",
+        args,
         env,
-    }))))
+    )?))))
+}
+
+/// Builds a function from the clauses given to the builtin called
+/// NAME. The function takes any number of arguments and matches their
+/// list against the clauses with the `fn-clauses` builtin.
+fn make_clause_fn(
+    name: &str,
+    args: &ConsCell,
+    env: Rc<RefCell<LustEnv>>,
+) -> Result<LustFn, String> {
+    for clause in args {
+        if !matches!(clause, LustData::Cons(ref c) if c.len() == 2 && matches!(c[0], LustData::Cons(_)))
+        {
+            return Err(format!(
+                "{} expected a clause like (params body), got {}",
+                name, clause
+            ));
+        }
+    }
+    let mut body = vec![LustData::Builtin(FN_CLAUSES), LustData::symbol(CLAUSE_ARGS)];
+    body.extend(args.into_iter().map(|clause| clause.deep_clone(false)));
+    let params = vec!["&".to_string(), CLAUSE_ARGS.to_string()];
+    let mut func = LustFn::new(params, LustData::list(body), env);
+    func.defined_at = Interpreter::current_location();
+    Ok(func)
+}
+
+const FN_CLAUSES: BuiltinFn = BuiltinFn {
+    name: "fn-clauses",
+    signature: "args & clauses",
+    func: fn_clauses,
+};
+
+/// Evaluates the body of the first clause matching the arguments to a
+/// function made by `make_clause_fn`.
+fn fn_clauses(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    let val = Interpreter::eval_in_env(&args[0], env.clone())?;
+    match_clauses("fn", &val, args.nth_item(1), env)?
+        .ok_or_else(|| format!("fn found no clause matching the arguments {}", val))
 }
 
 /// Declares a macro. This has the same syntax and semantics as
 /// declaring a function but the evaluation rules are the same as Lisp
 /// macros.
 pub fn macro_(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
-    check_arg_len("macro", 2, args)?;
+    Ok(CallResult::Ret(LustData::Mac(Rc::new(make_fn(
+        "macro", args, env,
+    )?))))
+}
+
+/// Builds a function or macro from the arguments to the builtin
+/// called NAME. These are a parameter list, an optional docstring,
+/// and one or more body expressions. A string followed by more
+/// expressions is the docstring. A body of more than one expression is
+/// wrapped in a call to `BODY`.
+pub(crate) fn make_fn(
+    name: &str,
+    args: &ConsCell,
+    env: Rc<RefCell<LustEnv>>,
+) -> Result<LustFn, String> {
+    if args.len() < 2 {
+        return Err(format!(
+            "{} expected a parameter list and a body but got {} arguments",
+            name,
+            args.len()
+        ));
+    }
+    let doc = match args.len() {
+        2 => None,
+        _ => docstring(&args[1]),
+    };
     let params = collect_param_list(&args[0])?;
-    let body = args[1].deep_clone(false);
-    Ok(CallResult::Ret(LustData::Mac(Box::new(LustFn {
-        params,
-        body,
-        env,
-    }))))
+    let skip = if doc.is_some() { 2 } else { 1 };
+    let mut func = LustFn::new(params, fn_body(args.into_iter().skip(skip)), env);
+    func.doc = doc;
+    func.defined_at = Interpreter::current_location();
+    Ok(func)
+}
+
+/// Makes the body of a function from FORMS, which should not be empty.
+fn fn_body<'a>(forms: impl Iterator<Item = &'a LustData>) -> LustData {
+    // Function bodies shouldn't be modified after creation.
+    let mut forms: Vec<LustData> = forms.map(|form| form.deep_clone(false)).collect();
+    if forms.len() == 1 {
+        forms.pop().unwrap()
+    } else {
+        forms.insert(0, LustData::Builtin(BODY));
+        LustData::list(forms)
+    }
+}
+
+/// Wraps the expressions in the body of a function with more than
+/// one. It isn't bound to a name so rebinding `do` doesn't change what
+/// function bodies mean.
+pub(crate) const BODY: BuiltinFn = BuiltinFn {
+    name: "body",
+    signature: "& exprs",
+    func: body,
+};
+
+/// Evaluates its arguments in order and returns the value of the last
+/// one, which is in tail position.
+fn body(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    let last = match args.len() {
+        0 => return Ok(CallResult::Ret(LustData::get_empty_list())),
+        n => n - 1,
+    };
+    for expr in args.into_iter().take(last) {
+        Interpreter::eval_in_env(expr, env.clone())?;
+    }
+    Ok(CallResult::Call(env, args[last].clone()))
+}
+
+/// Gets the string in EXPR if it is a string literal. String literals
+/// are read as quoted lists of characters.
+fn docstring(expr: &LustData) -> Option<String> {
+    let c = expr.as_list().ok()?;
+    if c.len() != 2 || c[0] != LustData::symbol("quote") {
+        return None;
+    }
+    c[1].stringify()
 }
 
 /// Expands but does not evaluate a macro. Very useful for debugging
@@ -126,6 +1187,16 @@ pub fn macroexpand(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallRes
     )?))
 }
 
+/// Like `macroexpand` but only expands the outermost macro call
+/// once.
+pub fn macroexpand_1(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("macroexpand-1", 1, args)?;
+    Ok(CallResult::Ret(Interpreter::macroexpand_1(
+        args[0].clone(),
+        env,
+    )?))
+}
+
 pub fn error(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
     check_arg_len("error", 1, args)?;
     let message = Interpreter::eval_in_env(&args[0], env)?;
@@ -141,7 +1212,7 @@ pub fn println_(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult
     check_arg_len("println", 1, args)?;
     let val = Interpreter::eval_in_env(&args[0], env)?;
     let stringify = format!("{}", val);
-    println!("{}", strip_quotes(&stringify));
+    Interpreter::write_output(&format!("{}\n", strip_quotes(&stringify)))?;
     Ok(CallResult::Ret(LustData::get_empty_list()))
 }
 
@@ -149,10 +1220,44 @@ pub fn print_(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult,
     check_arg_len("print", 1, args)?;
     let val = Interpreter::eval_in_env(&args[0], env)?;
     let stringify = format!("{}", val);
-    print!("{}", strip_quotes(&stringify));
+    Interpreter::write_output(strip_quotes(&stringify))?;
+    Ok(CallResult::Ret(LustData::get_empty_list()))
+}
+
+/// Prints a value with each item of a list that has lists in it on a
+/// line of its own, indented by how deeply it is nested. Lists without
+/// lists in them are printed on one line. For debugging large data.
+pub fn pp(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("pretty-print", 1, args)?;
+    let val = Interpreter::eval_in_env(&args[0], env)?;
+    let mut out = String::new();
+    pretty(&val, 0, &mut out);
+    out.push('\n');
+    Interpreter::write_output(&out)?;
     Ok(CallResult::Ret(LustData::get_empty_list()))
 }
 
+/// Writes VAL to OUT as `pretty-print` shows it. VAL is nested DEPTH
+/// lists deep.
+fn pretty(val: &LustData, depth: usize, out: &mut String) {
+    let is_list = |item: &LustData| {
+        matches!(item, LustData::Cons(_)) && !item.is_empty_list() && item.stringify().is_none()
+    };
+    let items = match val {
+        LustData::Cons(c) if is_list(val) && c.into_iter().any(is_list) => c,
+        _ => return out.push_str(&val.to_string()),
+    };
+    out.push('(');
+    for (i, item) in items.into_iter().enumerate() {
+        if i > 0 {
+            out.push('\n');
+            out.push_str(&"  ".repeat(depth + 1));
+        }
+        pretty(item, depth + 1, out);
+    }
+    out.push(')');
+}
+
 /// Evaluates and imports the global symbol table from another
 /// file. For example, to add the stdlib to a project: `(import
 /// 'std)`. Takes the relative path to the file as an argument and
@@ -160,7 +1265,7 @@ pub fn print_(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult,
 pub fn import(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
     check_arg_len("import", 1, args)?;
     let target = Interpreter::eval_in_env(&args[0], env.clone())?;
-    let mut target = LustData::expect_symbol(&target)?.clone();
+    let mut target = LustData::expect_symbol(&target)?.to_string();
     target.push_str(".lisp");
 
     let evaluator = match crate::interpret_file(&target) {
@@ -177,139 +1282,1703 @@ pub fn import(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult,
     Ok(CallResult::Ret(LustData::get_empty_list()))
 }
 
+/// Takes a name and a body. The body is evaluated in a new
+/// enviroment and the names it binds are then bound in the calling
+/// enviroment prefixed with the module's name, so `(module math (let
+/// square ...))` binds `math/square`. If the first expression in the
+/// body is `(export names...)` only those names are exported and the
+/// rest stay private to the module. Returns the module's name.
+pub fn module(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    if args.len() < 1 {
+        return Err("module expected a name and a body".to_string());
+    }
+    let name = args[0].expect_symbol()?;
+    let mut body = args.nth_item(1);
+    let exports = match body {
+        ConsCell::Cons(ref c) if is_export(&c.data) => {
+            body = &c.next;
+            Some(
+                c.data
+                    .as_list()?
+                    .into_iter()
+                    .skip(1)
+                    .map(|e| e.expect_symbol().map(str::to_string))
+                    .collect::<Result<Vec<_>, _>>()?,
+            )
+        }
+        _ => None,
+    };
+    let scope = LustEnv::new_child(env.clone());
+    for expr in body {
+        Interpreter::eval_in_env(expr, scope.clone())?;
+    }
+    let bindings = scope.borrow().bindings();
+    if let Some(ref exports) = exports {
+        if let Some(missing) = exports
+            .iter()
+            .find(|e| !bindings.iter().any(|(b, _)| b == *e))
+        {
+            return Err(format!(
+                "module {} exports {} but does not define it",
+                name, missing
+            ));
+        }
+    }
+    for (binding, val) in bindings {
+        if exports.as_ref().is_none_or(|e| e.contains(&binding)) {
+            let target = format!("{}/{}", name, binding);
+            Interpreter::check_shadow(&target, &env)?;
+            env.borrow_mut().insert(target, val);
+        }
+    }
+    Ok(CallResult::Ret(LustData::symbol(name)))
+}
+
+/// Is EXPR an `(export names...)` form?
+fn is_export(expr: &LustData) -> bool {
+    match expr {
+        LustData::Cons(ref c) => {
+            matches!(**c, ConsCell::Cons(ref c) if c.data == LustData::symbol("export"))
+        }
+        _ => false,
+    }
+}
+
 /// Takes one numeric argument and negates it.
 pub fn negate(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
-    check_arg_len("negate", 1, args)?;
-    let val = Interpreter::eval_in_env(&args[0], env)?;
-    let val = LustData::expect_num(&val)?;
-    Ok(CallResult::Ret(LustData::Number(-val)))
+    call_primitive::<1>("negate", args, env, negate_values)
+}
+
+fn negate_values(args: &[LustData]) -> Result<LustData, String> {
+    Ok(LustData::Number(-expect_number("negate", &args[0])?))
 }
 
 /// Takes two arguments and adds them together.
 pub fn add(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
-    check_arg_len("add", 2, args)?;
-    let l = Interpreter::eval_in_env(&args[0], env.clone())?;
-    let l = LustData::expect_num(&l)?;
-    let r = Interpreter::eval_in_env(&args[1], env.clone())?;
-    let r = LustData::expect_num(&r)?;
-    Ok(CallResult::Ret(LustData::Number(l + r)))
+    call_primitive::<2>("add", args, env, add_values)
+}
+
+fn add_values(args: &[LustData]) -> Result<LustData, String> {
+    let (l, r) = expect_numbers("add", args)?;
+    Ok(LustData::Number(l + r))
 }
 
 /// Takes two arguments and subtracts the second from the first.
 pub fn sub(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
-    check_arg_len("sub", 2, args)?;
-    let l = Interpreter::eval_in_env(&args[0], env.clone())?;
-    let l = LustData::expect_num(&l)?;
-    let r = Interpreter::eval_in_env(&args[1], env.clone())?;
-    let r = LustData::expect_num(&r)?;
-    Ok(CallResult::Ret(LustData::Number(l - r)))
+    call_primitive::<2>("sub", args, env, sub_values)
+}
+
+fn sub_values(args: &[LustData]) -> Result<LustData, String> {
+    let (l, r) = expect_numbers("sub", args)?;
+    Ok(LustData::Number(l - r))
 }
 
 /// Takes two arguments and multiplies them together.
 pub fn mul(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
-    check_arg_len("mul", 2, args)?;
-    let l = Interpreter::eval_in_env(&args[0], env.clone())?;
-    let l = LustData::expect_num(&l)?;
-    let r = Interpreter::eval_in_env(&args[1], env.clone())?;
-    let r = LustData::expect_num(&r)?;
-    Ok(CallResult::Ret(LustData::Number(l * r)))
+    call_primitive::<2>("mul", args, env, mul_values)
+}
+
+fn mul_values(args: &[LustData]) -> Result<LustData, String> {
+    let (l, r) = expect_numbers("mul", args)?;
+    Ok(LustData::Number(l * r))
 }
 
 /// Takes two arguments and divides the first by the second.
 pub fn div(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
-    check_arg_len("div", 2, args)?;
-    let l = Interpreter::eval_in_env(&args[0], env.clone())?;
-    let l = LustData::expect_num(&l)?;
-    let r = Interpreter::eval_in_env(&args[1], env.clone())?;
-    let r = LustData::expect_num(&r)?;
-    Ok(CallResult::Ret(LustData::Number(l / r)))
+    call_primitive::<2>("div", args, env, div_values)
+}
+
+fn div_values(args: &[LustData]) -> Result<LustData, String> {
+    let (l, r) = expect_numbers("div", args)?;
+    if r == 0.0 {
+        return Err(format!("div expected a non-zero divisor, got {}", r));
+    }
+    Ok(LustData::Number(l / r))
+}
+
+/// Takes two arguments and returns the remainder of dividing the
+/// first by the second. The remainder has the same sign as the
+/// divisor, so `(mod -1 3)` is 2.
+pub fn mod_(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    call_primitive::<2>("mod", args, env, mod_values)
+}
+
+fn mod_values(args: &[LustData]) -> Result<LustData, String> {
+    let (l, r) = expect_numbers("mod", args)?;
+    if r == 0.0 {
+        return Err(format!("mod expected a non-zero divisor, got {}", r));
+    }
+    let rem = l % r;
+    if rem != 0.0 && (rem < 0.0) != (r < 0.0) {
+        Ok(LustData::Number(rem + r))
+    } else {
+        Ok(LustData::Number(rem))
+    }
+}
+
+/// Takes one argument and returns if it is NaN, the number that
+/// results from arithmetic like subtracting infinity from itself.
+/// NaN is not equal to anything, including itself.
+pub fn nan_p(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    call_primitive::<1>("nan?", args, env, nan_p_values)
+}
+
+fn nan_p_values(args: &[LustData]) -> Result<LustData, String> {
+    Ok(get_truthy_equiv(expect_number("nan?", &args[0])?.is_nan()))
+}
+
+/// Takes one argument and returns if it is positive or negative
+/// infinity.
+pub fn inf_p(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    call_primitive::<1>("inf?", args, env, inf_p_values)
+}
+
+fn inf_p_values(args: &[LustData]) -> Result<LustData, String> {
+    Ok(get_truthy_equiv(
+        expect_number("inf?", &args[0])?.is_infinite(),
+    ))
 }
 
 /// Takes two numeric arguments LEFT and RIGHT and returns if LEFT is
 /// less than RIGHT.
 pub fn lt(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
-    check_arg_len("lt", 2, args)?;
-    let l = Interpreter::eval_in_env(&args[0], env.clone())?;
-    let l = LustData::expect_num(&l)?;
-    let r = Interpreter::eval_in_env(&args[1], env.clone())?;
-    let r = LustData::expect_num(&r)?;
-    Ok(CallResult::Ret(get_truthy_equiv(l < r)))
+    call_primitive::<2>("lt", args, env, lt_values)
+}
+
+fn lt_values(args: &[LustData]) -> Result<LustData, String> {
+    let (l, r) = expect_numbers("lt", args)?;
+    Ok(get_truthy_equiv(l < r))
 }
 
 /// Takes two numeric arguments LEFT and RIGHT and returns if LEFT is
 /// greater than RIGHT.
 pub fn gt(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
-    check_arg_len("gt", 2, args)?;
-    let l = Interpreter::eval_in_env(&args[0], env.clone())?;
-    let l = LustData::expect_num(&l)?;
-    let r = Interpreter::eval_in_env(&args[1], env.clone())?;
-    let r = LustData::expect_num(&r)?;
-    Ok(CallResult::Ret(get_truthy_equiv(l > r)))
+    call_primitive::<2>("gt", args, env, gt_values)
+}
+
+fn gt_values(args: &[LustData]) -> Result<LustData, String> {
+    let (l, r) = expect_numbers("gt", args)?;
+    Ok(get_truthy_equiv(l > r))
 }
 
 /// Takes two numeric arguments LEFT and RIGHT and returns if LEFT is
 /// equal to RIGHT.
 pub fn eq(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
-    check_arg_len("gt", 2, args)?;
-    let l = Interpreter::eval_in_env(&args[0], env.clone())?;
-    let r = Interpreter::eval_in_env(&args[1], env.clone())?;
-    Ok(CallResult::Ret(get_truthy_equiv(l == r)))
+    call_primitive::<2>("eq", args, env, eq_values)
+}
+
+fn eq_values(args: &[LustData]) -> Result<LustData, String> {
+    Ok(get_truthy_equiv(args[0] == args[1]))
+}
+
+/// Returns true if its two arguments are the same value. Lists are
+/// only identical to themselves, however equal their items are.
+pub fn identical_p(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    call_primitive::<2>("identical?", args, env, identical_values)
+}
+
+fn identical_values(args: &[LustData]) -> Result<LustData, String> {
+    Ok(get_truthy_equiv(args[0].identical(&args[1])))
+}
+
+/// The Scheme name for `eq`. Lists and strings are equal if their
+/// items are.
+pub fn equal_p(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    call_primitive::<2>("equal?", args, env, eq_values)
+}
+
+/// The Scheme name for `identical?`.
+pub fn eq_p(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    call_primitive::<2>("eq?", args, env, identical_values)
+}
+
+/// Same as `eq?`. Scheme's `eqv?` differs from `eq?` only in
+/// comparing numbers and characters by value, which `identical?`
+/// already does.
+pub fn eqv_p(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    call_primitive::<2>("eqv?", args, env, identical_values)
 }
 
 // Evaluate each argument in a comma expression, ignore all others.
 pub fn quaziquote(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
     check_arg_len("quaziquote", 1, args)?;
-    let c = args[0].expect_cons()?;
-    Ok(CallResult::Ret(LustData::Cons(Rc::new(
-        c.transform_fallible(|item: &LustData| eval_commas(&item, env.clone()))?,
-    ))))
+    Ok(CallResult::Ret(eval_commas(&args[0], env, 1)?))
+}
+
+/// Takes a template and returns it without evaluating it except for
+/// the lists of the form `(unquote expr)`, which are replaced by the
+/// value of EXPR. The same as the `` ` `` and `,` reader syntax.
+pub fn quasiquote(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("quasiquote", 1, args)?;
+    Ok(CallResult::Ret(eval_commas(&args[0], env, 1)?))
 }
 
-fn eval_commas(data: &LustData, env: Rc<RefCell<LustEnv>>) -> Result<LustData, String> {
+/// Evaluates the commas in DATA that are DEPTH quasiquotes deep. Each
+/// nested quasiquote adds one to the depth and each comma takes one
+/// away, so only the commas belonging to the outermost quasiquote are
+/// evaluated.
+fn eval_commas(
+    data: &LustData,
+    env: Rc<RefCell<LustEnv>>,
+    depth: usize,
+) -> Result<LustData, String> {
     // If it's a comma, evaluate and return its argument. If it's a
     // non-list type return it. If it's a list return a new list that
     // is the result of calling eval_commas on each of its items.
-    match data {
-        LustData::Cons(ref c) => {
-            if is_comma(&*c) {
-                // We know that we have at least one element because
-                // is_comma returned true.
-                eval_comma(&*c, env)
-            } else {
-                Ok(LustData::Cons(Rc::new(c.transform_fallible(
-                    |item: &LustData| eval_commas(&item, env.clone()),
-                )?)))
+    let c = match data {
+        LustData::Cons(ref c) => c,
+        _ => return Ok(data.clone()),
+    };
+    let depth = match quote_marker(c) {
+        Some(true) if depth == 1 => return Interpreter::eval_in_env(&c[1], env),
+        Some(true) => depth - 1,
+        Some(false) => depth + 1,
+        None => depth,
+    };
+    Ok(LustData::Cons(Rc::new(c.transform_fallible(
+        |item: &LustData| eval_commas(item, env.clone(), depth),
+    )?)))
+}
+
+/// Determines if DATA is a comma, `(comma expr)` or `(unquote expr)`,
+/// in which case this returns true, or a nested quasiquote, in which
+/// case this returns false.
+fn quote_marker(data: &ConsCell) -> Option<bool> {
+    if data.len() != 2 {
+        return None;
+    }
+    match data[0] {
+        LustData::Symbol(s) => match resolve_intern(s) {
+            "comma" | "unquote" => Some(true),
+            "quaziquote" | "quasiquote" => Some(false),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Takes a name and any number of expressions and registers them as a
+/// test to be run by `run-tests`. The name may be a symbol or a
+/// string. For example:
+///
+/// ```lisp
+/// (deftest addition
+///   (assert-eq (add 1 2) 3))
+/// ```
+pub fn deftest(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    if args.len() == 0 {
+        return Err("deftest expected a name".to_string());
+    }
+    let name = match args[0].expect_symbol() {
+        Ok(s) => s.to_string(),
+        Err(_) => expect_string("deftest", &Interpreter::eval_in_env(&args[0], env.clone())?)?,
+    };
+    let body = args.into_iter().skip(1).cloned().collect();
+    Interpreter::register_test(Test { name, body, env });
+    Ok(CallResult::Ret(LustData::get_empty_list()))
+}
+
+/// Runs the tests registered by `deftest`, printing the result of
+/// each and a summary. Takes an optional string and only runs the
+/// tests whose names start with it. Returns the number of tests that
+/// failed.
+pub fn run_tests(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_range("run-tests", 0, 1, args)?;
+    let prefix = if args.len() == 1 {
+        expect_string("run-tests", &Interpreter::eval_in_env(&args[0], env)?)?
+    } else {
+        String::new()
+    };
+
+    let (mut passed, mut failed) = (0, 0);
+    for test in Interpreter::registered_tests() {
+        if !test.name.starts_with(&prefix) {
+            continue;
+        }
+        let testenv = LustEnv::new_child(test.env.clone());
+        let res = test
+            .body
+            .iter()
+            .try_for_each(|expr| Interpreter::eval_in_env(expr, testenv.clone()).map(|_| ()));
+        match res {
+            Ok(()) => {
+                passed += 1;
+                Interpreter::write_output(&format!("test {} ... ok\n", test.name))?;
+            }
+            Err(e) => {
+                failed += 1;
+                Interpreter::forget_error_trace();
+                Interpreter::write_output(&format!("test {} ... FAILED: {}\n", test.name, e))?;
+            }
+        }
+    }
+    Interpreter::write_output(&format!("{} passed, {} failed\n", passed, failed))?;
+    Ok(CallResult::Ret(LustData::Number(failed as f32)))
+}
+
+/// Takes a function, macro, or builtin and returns its documentation
+/// as a string. Returns the empty list if it has none.
+pub fn doc(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("doc", 1, args)?;
+    let doc = match Interpreter::eval_in_env(&args[0], env)? {
+        LustData::Fn(f) | LustData::Mac(f) => f.doc.clone(),
+        LustData::Builtin(b) => builtin_doc(b.name).map(str::to_string),
+        _ => None,
+    };
+    Ok(CallResult::Ret(match doc {
+        Some(doc) => LustData::string_value(&doc),
+        None => LustData::get_empty_list(),
+    }))
+}
+
+/// Takes a function, macro, or list and a value and returns a copy
+/// with the value attached as metadata. Metadata doesn't change how
+/// the copy prints or compares.
+pub fn with_meta(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("with-meta", 2, args)?;
+    let val = Interpreter::eval_in_env(&args[0], env.clone())?;
+    let meta = Interpreter::eval_in_env(&args[1], env)?;
+    Ok(CallResult::Ret(val.with_meta(meta)?))
+}
+
+/// Returns the metadata attached to a value with `with-meta` or the
+/// empty list if there is none.
+pub fn meta(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("meta", 1, args)?;
+    let val = Interpreter::eval_in_env(&args[0], env)?;
+    Ok(CallResult::Ret(
+        val.meta().unwrap_or_else(LustData::get_empty_list),
+    ))
+}
+
+/// Prints a sorted list of the builtins, functions, and macros that
+/// are available along with their parameters. Takes an optional
+/// symbol and prints only that entry and its documentation.
+pub fn help(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_range("help", 0, 1, args)?;
+    if args.len() == 1 {
+        let name = Interpreter::eval_in_env(&args[0], env.clone())?;
+        let name = name.expect_symbol()?;
+        let val = env.borrow().resolve(name)?;
+        let mut out =
+            help_entry(name, &val).ok_or_else(|| format!("{} is not a function or macro", name))?;
+        let doc = match val {
+            LustData::Fn(f) | LustData::Mac(f) => f.doc.clone(),
+            LustData::Builtin(b) => builtin_doc(b.name).map(str::to_string),
+            _ => None,
+        };
+        if let Some(doc) = doc {
+            out.push_str(&format!("\n  {}", doc));
+        }
+        Interpreter::write_output(&format!("{}\n", out))?;
+    } else {
+        let names = env.borrow().completions("");
+        for name in names {
+            let val = env.borrow().resolve(&name)?;
+            if let Some(entry) = help_entry(&name, &val) {
+                Interpreter::write_output(&format!("{}\n", entry))?;
             }
         }
-        _ => Ok(data.clone()),
     }
+    Ok(CallResult::Ret(LustData::get_empty_list()))
+}
+
+/// Describes VAL, which is bound to NAME, for `help`. For example,
+/// `(cons item list)  builtin`. Returns None if VAL isn't callable.
+fn help_entry(name: &str, val: &LustData) -> Option<String> {
+    let (params, kind) = match val {
+        LustData::Builtin(b) => (b.signature.to_string(), "builtin"),
+        LustData::NativeFn(_) => ("& args".to_string(), "native fn"),
+        LustData::Fn(f) => (f.params.join(" "), "fn"),
+        LustData::Mac(f) => (f.params.join(" "), "macro"),
+        _ => return None,
+    };
+    let signature = if params.is_empty() {
+        format!("({})", name)
+    } else {
+        format!("({} {})", name, params)
+    };
+    Some(format!("{:<32} {}", signature, kind))
+}
+
+/// Gets the documentation for the builtin called NAME.
+fn builtin_doc(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "quote" => "(quote expr) returns EXPR without evaluating it.",
+        "quaziquote" => "(quaziquote expr) returns EXPR without evaluating it except for the parts preceded by a comma.",
+        "quasiquote" => "(quasiquote expr) returns EXPR without evaluating it except for the parts in an (unquote expr).",
+        "car" => "(car list) returns the first item in LIST.",
+        "cdr" => "(cdr list) returns LIST without its first item.",
+        "cons" => "(cons item list) returns LIST with ITEM added to the front.",
+        "caar" | "cadr" | "cdar" | "cddr" | "caddr" | "cdddr" => "(cxr list) composes car and cdr as the letters between c and r say, so cadr is the car of the cdr.",
+        "first" => "(first list) returns the first item in LIST.",
+        "second" => "(second list) returns the second item in LIST.",
+        "third" => "(third list) returns the third item in LIST.",
+        "rest" => "(rest list) returns LIST without its first item.",
+        "car?" => "(car? list) returns the first item in LIST or () if it is empty.",
+        "nth" => "(nth n list) returns the item at index N in LIST, counting from 0.",
+        "nth?" => "(nth? n list) returns the item at index N in LIST or () if it is too short.",
+        "pair" => "(pair a b) returns a list of A and B.",
+        "take" => "(take n list) returns the first N items of LIST.",
+        "drop" => "(drop n list) returns LIST without its first N items.",
+        "iota" => "(iota count [start step]) returns a list of COUNT numbers from START, 0 by default, going up by STEP, 1 by default.",
+        "repeat" => "(repeat n value) returns a list of N copies of VALUE.",
+        "take-while" => "(take-while pred list) returns the items at the start of LIST that PRED is true for.",
+        "drop-while" => "(drop-while pred list) returns LIST from the first item PRED is false for.",
+        "find" => "(find pred list) returns the first item in LIST that PRED is true for, or () if there is none.",
+        "find-index" => "(find-index pred list) returns the index of the first item in LIST that PRED is true for, or () if there is none.",
+        "count" => "(count pred list) returns how many items in LIST PRED is true for.",
+        "any?" => "(any? pred list) returns true if PRED is true for any item in LIST.",
+        "every?" => "(every? pred list) returns true if PRED is true for every item in LIST.",
+        "count-if" => "(count-if pred list) returns how many items in LIST PRED is true for.",
+        "all?" => "(all? pred list) returns true if PRED is true for every item in LIST.",
+        "concatenate" => "(concatenate lists) returns a list of the items in each list in LISTS.",
+        "flatten-map" => "(flatten-map f list) calls F on each item in LIST and returns the items of the lists it returns.",
+        "module" => "(module name & body) evaluates BODY and binds the names it defines, or those in an (export names...) at its start, as NAME/name.",
+        "zip" => "(zip list & lists) returns a list of lists of the items at each position in the LISTS, as long as the shortest.",
+        "unzip" => "(unzip pairs) returns a list of the first items of PAIRS and a list of the second items.",
+        "sort" => "(sort list) returns LIST, a list of numbers, sorted in ascending order.",
+        "sort-by" => "(sort-by compare list) returns LIST sorted so that items COMPARE says go first come first.",
+        "partition" => "(partition pred list) returns a list of the items in LIST that PRED is true for and a list of the rest. (partition n list) returns the items in LIST in lists of N.",
+        "group-by" => "(group-by key list) returns a list of (k items...) for each key K that KEY returns for items in LIST.",
+        "flatten" => "(flatten list) returns the items in LIST with the items of the lists in it in their place.",
+        "flatten-deep" => "(flatten-deep list) returns the items in LIST and the lists nested in it as one list.",
+        "if" => "(if cond then else) evaluates THEN if COND is true and ELSE otherwise.",
+        "case-lambda" => "(case-lambda (params body)...) returns a function that evaluates the body of the first clause whose PARAMS match its arguments.",
+        "match" => "(match value (pattern result)...) evaluates the RESULT of the first clause whose PATTERN matches VALUE.",
+        "eval" => "(eval expr) evaluates the value of EXPR.",
+        "tail-call?" => "(tail-call? expr) evaluates EXPR and returns true if the call to tail-call? is in tail position.",
+        "defdynamic" => "(defdynamic name value) binds NAME to VALUE as a dynamic variable that binding can rebind.",
+        "binding" => "(binding ((name value)...) body...) evaluates BODY with the dynamic variables NAME set to VALUE.",
+        "let" => "(let name value) binds NAME to VALUE in the current enviroment. (let ((name value) ...) body...) binds the names only while evaluating BODY.",
+        "fn" => "(fn (params...) [doc] body) makes a function. A parameter list ending in `& rest` binds any remaining arguments to REST.",
+        "error" => "(error message) raises an error with MESSAGE.",
+        "macro" => "(macro (params...) [doc] body) makes a macro. Its arguments are not evaluated and its result is evaluated in their place.",
+        "macroexpand" => "(macroexpand expr) fully expands the macro call EXPR without evaluating the result.",
+        "macroexpand-1" => "(macroexpand-1 expr) expands the macro call EXPR once without evaluating the result.",
+        "println" => "(println value) prints VALUE followed by a newline.",
+        "pretty-print" => "(pretty-print value) prints VALUE with nested lists on indented lines.",
+        "print" => "(print value) prints VALUE.",
+        "import" => "(import 'name) evaluates name.lisp and adds its definitions to the current enviroment. Files are looked for in the current directory and then LUSTPATH.",
+        "negate" => "(negate n) returns -N.",
+        "add" => "(add a b) returns A + B.",
+        "sub" => "(sub a b) returns A - B.",
+        "mul" => "(mul a b) returns A * B.",
+        "div" => "(div a b) returns A / B. Errors if B is zero.",
+        "mod" => "(mod a b) returns the remainder of A / B, with the sign of B. Errors if B is zero.",
+        "nan?" => "(nan? n) returns true if N is NaN.",
+        "inf?" => "(inf? n) returns true if N is positive or negative infinity.",
+        "lt" => "(lt a b) returns true if A is less than B.",
+        "gt" => "(gt a b) returns true if A is greater than B.",
+        "eq" => "(eq a b) returns true if A and B are equal. Lists are equal if their items are, functions only if they are the same function.",
+        "identical?" => "(identical? a b) returns true if A and B are the same value. Lists are only identical to themselves.",
+        "equal?" => "(equal? a b) returns true if A and B are equal. Same as eq.",
+        "eq?" => "(eq? a b) returns true if A and B are the same value. Same as identical?.",
+        "eqv?" => "(eqv? a b) returns true if A and B are the same value. Same as identical?.",
+        "assert" => "(assert expr [message]) raises an error showing EXPR if it is false.",
+        "assert-eq" => "(assert-eq a b) raises an error showing both values if A and B are not equal.",
+        "deftest" => "(deftest name body...) registers a test to be run by run-tests.",
+        "run-tests" => "(run-tests [prefix]) runs the registered tests whose names start with PREFIX and returns the number that failed.",
+        "delay" => "(delay expr) returns a promise to evaluate EXPR when it is forced.",
+        "force" => "(force promise) evaluates PROMISE if it hasn't been already and returns its value.",
+        "promise?" => "(promise? value) returns true if VALUE is a promise.",
+        "defrecord" => "(defrecord name (fields...)) defines a constructor NAME, a predicate NAME?, and an accessor NAME-FIELD for each field.",
+        "loop" => "(loop ((name value)...) body...) evaluates BODY with the names bound to the values. recur in BODY evaluates it again with new values.",
+        "recur" => "(recur values...) evaluates the body of the nearest loop again with its names bound to VALUES.",
+        "record-set" => "(record-set record field value) returns a copy of RECORD with FIELD set to VALUE.",
+        "define-record-type" => "(define-record-type name (constructor fields...) predicate (field accessor [modifier])...) defines a record type in the style of SRFI-9.",
+        "memoize" => "(memoize f [limit]) returns F made to remember the results of at most LIMIT calls.",
+        "make-hash-table" => "(make-hash-table [#:comparator f]) returns an empty hash table whose keys are compared with F, or eq.",
+        "hash-table-set!" => "(hash-table-set! table key value) binds KEY to VALUE in TABLE.",
+        "hash-table-ref" => "(hash-table-ref table key [default]) returns the value of KEY in TABLE, or DEFAULT if it has none.",
+        "hash-table-comparator" => "(hash-table-comparator table) returns the comparator TABLE was made with, or ().",
+        "hash-table-equivalence-function" => "(hash-table-equivalence-function table) returns the function TABLE compares keys with.",
+        "with-meta" => "(with-meta value meta) returns a copy of VALUE, a fn, macro, or list, with META attached.",
+        "meta" => "(meta value) returns the metadata attached to VALUE or () if there is none.",
+        "doc" => "(doc value) returns the documentation for a function, macro, or builtin.",
+        "help" => "(help ['name]) lists what is available or describes NAME.",
+        "open-input-string" => "(open-input-string s) returns a port that reads from the string S.",
+        "open-output-string" => "(open-output-string) returns a port that collects what is written to it.",
+        "get-output-string" => "(get-output-string port) returns everything written to PORT so far.",
+        "read-char" => "(read-char port) reads a character from PORT or returns () at the end.",
+        "read-line" => "(read-line port) reads a line from PORT or returns () at the end.",
+        "read" => "(read source) parses the first expression in the string or port SOURCE.",
+        "write" => "(write value [port]) writes VALUE as the repl would print it.",
+        "display" => "(display value [port]) writes VALUE with strings and characters unquoted.",
+        "from-json" => "(from-json s) parses the JSON string S. Objects become lists of the form (object (key value)...).",
+        "to-json" => "(to-json value) converts VALUE to a JSON string.",
+        "clock" => "(clock) returns monotonic time in seconds.",
+        "time" => "(time expr) evaluates EXPR, prints how long it took to standard error and returns its value.",
+        "now" => "(now) returns the seconds since the unix epoch.",
+        "random" => "(random) returns a random number in [0, 1).",
+        "random-int" => "(random-int n) returns a random whole number in [0, N).",
+        "random-seed" => "(random-seed s) seeds the random number generator.",
+        "exit" => "(exit [status]) stops the program with STATUS, 0 by default.",
+        "getenv" => "(getenv name) returns the value of the enviroment variable NAME or ().",
+        "ffi-open" => "(ffi-open path) opens the shared library at PATH for ffi-sym.",
+        "ffi-sym" => "(ffi-sym library name) returns the C function called NAME in LIBRARY for ffi-call.",
+        "ffi-call" => "(ffi-call func ret [type value]...) calls the C function FUNC, converting each VALUE to TYPE and the result from RET. Types are int, double, and ptr.",
+        "shell" => "(shell command) runs COMMAND with the system shell and returns (status stdout stderr).",
+        _ => return None,
+    })
+}
+
+/// Takes one expression and returns a promise to evaluate it later
+/// in the current enviroment. The expression is not evaluated until
+/// the promise is passed to `force`.
+pub fn delay(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("delay", 1, args)?;
+    Ok(CallResult::Ret(LustData::Promise(Rc::new(RefCell::new(
+        PromiseState::Unevaluated(args[0].clone(), env),
+    )))))
+}
+
+/// Takes a promise and returns the value of its expression. The
+/// expression is evaluated the first time the promise is forced and
+/// the result is remembered for later calls. Values which aren't
+/// promises are returned as is.
+pub fn force(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("force", 1, args)?;
+    let promise = match Interpreter::eval_in_env(&args[0], env)? {
+        LustData::Promise(p) => p,
+        val => return Ok(CallResult::Ret(val)),
+    };
+    let (expr, env) = match *promise.borrow() {
+        PromiseState::Forced(ref val) => return Ok(CallResult::Ret(val.clone())),
+        PromiseState::Unevaluated(ref expr, ref env) => (expr.clone(), env.clone()),
+    };
+    let val = Interpreter::eval_in_env(&expr, env)?;
+    // Evaluating the expression may have forced this promise
+    // already in which case the first value wins.
+    let mut state = promise.borrow_mut();
+    if let PromiseState::Forced(ref first) = *state {
+        return Ok(CallResult::Ret(first.clone()));
+    }
+    *state = PromiseState::Forced(val.clone());
+    Ok(CallResult::Ret(val))
+}
+
+/// Returns true if its argument is a promise made by `delay`.
+pub fn promise_p(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("promise?", 1, args)?;
+    let val = Interpreter::eval_in_env(&args[0], env)?;
+    Ok(CallResult::Ret(get_truthy_equiv(matches!(
+        val,
+        LustData::Promise(_)
+    ))))
+}
+
+/// Declares a record type. Takes a name and a list of field names,
+/// for example `(defrecord point (x y))`, and binds in the current
+/// enviroment:
+///
+/// - `point`, which makes a point from values for its fields.
+/// - `point?`, which returns true if its argument is a point.
+/// - `point-x` and `point-y`, which get a point's fields.
+///
+/// Returns the name of the record type.
+pub fn defrecord(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("defrecord", 2, args)?;
+    let name = args[0].expect_symbol()?;
+    let fields = args[1]
+        .as_list()?
+        .into_iter()
+        .map(|f| f.expect_symbol().map(str::to_string))
+        .collect::<Result<Vec<_>, _>>()?;
+    let fns = RecordFns {
+        constructor: (name.to_string(), fields.clone()),
+        predicate: format!("{}?", name),
+        accessors: fields
+            .iter()
+            .enumerate()
+            .map(|(i, field)| (i, format!("{}-{}", name, field), None))
+            .collect(),
+    };
+    define_record(name, &fields, fns, &env)?;
+    Ok(CallResult::Ret(LustData::symbol(name)))
 }
 
-fn is_comma(data: &ConsCell) -> bool {
+/// Declares a record type in the style of SRFI-9. For example,
+///
+/// ```lisp
+/// (define-record-type point (make-point x y) point?
+///   (x point-x set-point-x!)
+///   (y point-y))
+/// ```
+///
+/// binds `make-point`, `point?`, `point-x`, `set-point-x!`, and
+/// `point-y` in the current enviroment. The constructor may take only
+/// some of the fields, the others start as (). A constructor given
+/// as a name alone takes every field. Modifiers change the record
+/// they are given and return the new value. Returns the name of the
+/// record type.
+pub fn define_record_type(
+    args: &ConsCell,
+    env: Rc<RefCell<LustEnv>>,
+) -> Result<CallResult, String> {
+    if args.len() < 3 {
+        return Err(format!(
+            "define-record-type expected a name, a constructor, and a predicate but got {} arguments",
+            args.len()
+        ));
+    }
+    let name = args[0].expect_symbol()?;
+    let predicate = args[2].expect_symbol()?.to_string();
+    let mut fields = Vec::new();
+    let mut accessors = Vec::new();
+    for spec in args.into_iter().skip(3) {
+        let spec = spec
+            .as_list()
+            .ok()
+            .filter(|s| s.len() == 2 || s.len() == 3)
+            .and_then(|s| {
+                s.into_iter()
+                    .map(|n| n.expect_symbol().map(str::to_string).ok())
+                    .collect::<Option<Vec<_>>>()
+            })
+            .ok_or_else(|| {
+                format!(
+                    "define-record-type expected a field like (name accessor [modifier]), got {}",
+                    spec
+                )
+            })?;
+        let mut spec = spec.into_iter();
+        fields.push(spec.next().unwrap());
+        accessors.push((fields.len() - 1, spec.next().unwrap(), spec.next()));
+    }
+    let constructor = match &args[1] {
+        LustData::Symbol(_) => (args[1].expect_symbol()?.to_string(), fields.clone()),
+        other => {
+            let mut names = other.as_list()?.into_iter();
+            let ctor = match names.next() {
+                Some(n) => n.expect_symbol()?.to_string(),
+                None => {
+                    return Err("define-record-type expected a constructor name, got ()".to_string())
+                }
+            };
+            let params = names
+                .map(|f| f.expect_symbol().map(str::to_string))
+                .collect::<Result<Vec<_>, _>>()?;
+            if let Some(p) = params.iter().find(|p| !fields.contains(p)) {
+                return Err(format!(
+                    "define-record-type constructor {} takes {} which is not a field",
+                    ctor, p
+                ));
+            }
+            (ctor, params)
+        }
+    };
+    let fns = RecordFns {
+        constructor,
+        predicate,
+        accessors,
+    };
+    define_record(name, &fields, fns, &env)?;
+    Ok(CallResult::Ret(LustData::symbol(name)))
+}
+
+/// The names of the functions to define for a record type.
+struct RecordFns {
+    /// The name of the constructor and the fields it takes, in order.
+    constructor: (String, Vec<String>),
+    predicate: String,
+    /// The index of a field, the name of its accessor, and the name of
+    /// its modifier if it has one.
+    accessors: Vec<(usize, String, Option<String>)>,
+}
+
+/// Binds the functions in FNS for the record type NAME with FIELDS in
+/// ENV.
+fn define_record(
+    name: &str,
+    fields: &[String],
+    fns: RecordFns,
+    env: &Rc<RefCell<LustEnv>>,
+) -> Result<(), String> {
+    // The generated functions call these builtins, which are not
+    // bound to any name, with the record type's name and fields.
+    let quoted = |data: LustData| LustData::list(vec![LustData::symbol("quote"), data]);
+    let kind = [
+        quoted(LustData::symbol(name)),
+        quoted(LustData::list(
+            fields.iter().map(|f| LustData::symbol(f)).collect(),
+        )),
+    ];
+    let call = |func: BuiltinFn, args: Vec<LustData>| {
+        let mut items = vec![LustData::Builtin(func)];
+        items.extend(kind.iter().cloned());
+        items.extend(args);
+        LustData::list(items)
+    };
+    let define = |fn_name: String, params: Vec<String>, body: LustData, doc: String| {
+        Interpreter::check_shadow(&fn_name, env)?;
+        let mut func = LustFn::new(params, body, env.clone());
+        func.name = Some(fn_name.clone());
+        func.doc = Some(doc);
+        func.defined_at = Interpreter::current_location();
+        env.borrow_mut()
+            .insert(fn_name, LustData::Fn(Rc::new(func)));
+        Ok::<(), String>(())
+    };
+
+    let (constructor, params) = fns.constructor;
+    let args = fields
+        .iter()
+        .map(|f| match params.contains(f) {
+            true => LustData::symbol(f),
+            false => quoted(LustData::get_empty_list()),
+        })
+        .collect();
+    define(
+        constructor,
+        params,
+        call(RECORD_NEW, args),
+        format!("Makes a {} from its fields.", name),
+    )?;
+    define(
+        fns.predicate,
+        vec!["value".to_string()],
+        call(RECORD_P, vec![LustData::symbol("value")]),
+        format!("Returns true if VALUE is a {}.", name),
+    )?;
+    for (i, accessor, modifier) in fns.accessors {
+        let field = &fields[i];
+        let args = vec![LustData::Number(i as f32), LustData::symbol("record")];
+        define(
+            accessor,
+            vec!["record".to_string()],
+            call(RECORD_GET, args),
+            format!("Gets the {} of a {}.", field, name),
+        )?;
+        if let Some(modifier) = modifier {
+            let args = vec![
+                LustData::Number(i as f32),
+                LustData::symbol("record"),
+                LustData::symbol("value"),
+            ];
+            define(
+                modifier,
+                vec!["record".to_string(), "value".to_string()],
+                call(RECORD_MODIFY, args),
+                format!("Sets the {} of a {} to VALUE.", field, name),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+const RECORD_NEW: BuiltinFn = BuiltinFn {
+    name: "record-new",
+    signature: "name fields & values",
+    func: record_new,
+};
+
+const RECORD_P: BuiltinFn = BuiltinFn {
+    name: "record?",
+    signature: "name fields value",
+    func: record_p,
+};
+
+const RECORD_GET: BuiltinFn = BuiltinFn {
+    name: "record-get",
+    signature: "name fields index record",
+    func: record_get,
+};
+
+const RECORD_MODIFY: BuiltinFn = BuiltinFn {
+    name: "record-modify",
+    signature: "name fields index record value",
+    func: record_modify,
+};
+
+/// Evaluates the arguments to one of the builtins that functions made
+/// by `defrecord` call. These start with the name and fields of a
+/// record type. Returns a record of that type holding the rest of the
+/// arguments.
+fn record_args(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<Record, String> {
+    let mut values = Vec::with_capacity(args.len());
+    for arg in args {
+        values.push(Interpreter::eval_in_env(arg, env.clone())?);
+    }
+    let mut values = values.into_iter();
+    let name = values.next().unwrap().expect_symbol()?.to_string();
+    let fields = values
+        .next()
+        .unwrap()
+        .into_list()?
+        .iter()
+        .map(|f| f.expect_symbol().map(str::to_string))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Record {
+        name,
+        fields: Rc::new(fields),
+        values: RefCell::new(values.collect()),
+    })
+}
+
+/// Determines if DATA is a record of the same type as KIND.
+fn is_record_of(data: &LustData, kind: &Record) -> bool {
+    matches!(data, LustData::Record(r) if r.name == kind.name && r.fields == kind.fields)
+}
+
+/// Makes a record. Called by the constructor `defrecord` makes.
+fn record_new(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    let record = record_args(args, env)?;
+    if record.values.borrow().len() != record.fields.len() {
+        return Err(format!(
+            "{} expected {} fields but got {}",
+            record.name,
+            record.fields.len(),
+            record.values.borrow().len()
+        ));
+    }
+    Ok(CallResult::Ret(LustData::Record(Rc::new(record))))
+}
+
+/// Determines if a value is a record of a type. Called by the
+/// predicate `defrecord` makes.
+fn record_p(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    let kind = record_args(args, env)?;
+    let value = &kind.values.borrow()[0];
+    Ok(CallResult::Ret(get_truthy_equiv(is_record_of(
+        value, &kind,
+    ))))
+}
+
+/// Gets a field of a record. Called by the accessors `defrecord`
+/// makes.
+fn record_get(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    let kind = record_args(args, env)?;
+    let (index, record) = record_of_kind(&kind)?;
+    let value = record.values.borrow()[index].clone();
+    Ok(CallResult::Ret(value))
+}
+
+/// Sets a field of a record. Called by the modifiers
+/// `define-record-type` makes.
+fn record_modify(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    let kind = record_args(args, env)?;
+    let (index, record) = record_of_kind(&kind)?;
+    let value = kind.values.borrow()[2].clone();
+    record.values.borrow_mut()[index] = value.clone();
+    Ok(CallResult::Ret(value))
+}
+
+/// Takes the arguments to `record-get` or `record-modify`, which are
+/// an index and a record after the record type's name and fields.
+/// Returns the index and the record if it is of that type.
+fn record_of_kind(kind: &Record) -> Result<(usize, Rc<Record>), String> {
+    let values = kind.values.borrow();
+    let index = expect_number("record-get", &values[0])? as usize;
+    match values[1] {
+        LustData::Record(ref r) if is_record_of(&values[1], kind) => Ok((index, r.clone())),
+        ref other => Err(format!(
+            "{}-{} expected a {}, got {}",
+            kind.name,
+            kind.fields[index],
+            kind.name,
+            describe_type(other)
+        )),
+    }
+}
+
+/// Takes a record, a field name, and a value and returns a copy of the
+/// record with the field set to the value.
+pub fn record_set(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("record-set", 3, args)?;
+    let record = Interpreter::eval_in_env(&args[0], env.clone())?;
+    let field = Interpreter::eval_in_env(&args[1], env.clone())?;
+    let value = Interpreter::eval_in_env(&args[2], env)?;
+    let mut record = match record {
+        LustData::Record(r) => (*r).clone(),
+        other => {
+            return Err(format!(
+                "record-set expected a record, got {}",
+                other.type_name()
+            ))
+        }
+    };
+    let field = field.expect_symbol()?;
+    match record.fields.iter().position(|f| f == field) {
+        Some(i) => record.values.get_mut()[i] = value,
+        None => return Err(format!("{} has no field {}", record.name, field)),
+    }
+    Ok(CallResult::Ret(LustData::Record(Rc::new(record))))
+}
+
+/// Names the type of DATA for errors. Records are described by the
+/// name of their type.
+fn describe_type(data: &LustData) -> String {
     match data {
-        ConsCell::Nil => false,
-        ConsCell::Cons(ref c) => {
-            if let Ok(s) = c.data.expect_symbol() {
-                s == "comma"
-            } else {
-                false
+        LustData::Record(r) => r.name.clone(),
+        _ => data.type_name().to_string(),
+    }
+}
+
+/// Takes a function and returns one that remembers its results.
+/// Calling it again with arguments equal to those of an earlier call
+/// returns the earlier result without calling the function. Takes an
+/// optional limit on how many results are remembered after which the
+/// oldest is forgotten.
+pub fn memoize(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_range("memoize", 1, 2, args)?;
+    let func = Interpreter::eval_in_env(&args[0], env.clone())?;
+    if !matches!(
+        func,
+        LustData::Fn(_) | LustData::Builtin(_) | LustData::Memo(_) | LustData::NativeFn(_)
+    ) {
+        return Err(format!("memoize expected a fn, got {}", func.type_name()));
+    }
+    let limit = if args.len() == 2 {
+        let n = eval_number("memoize", &args[1], env)?;
+        if n < 1.0 || n.fract() != 0.0 {
+            return Err(format!(
+                "memoize expected a positive whole number, got {}",
+                n
+            ));
+        }
+        Some(n as usize)
+    } else {
+        None
+    };
+    Ok(CallResult::Ret(LustData::Memo(Rc::new(Memo::new(
+        func, limit,
+    )))))
+}
+
+/// Makes an empty hash table. Keys are compared with `eq` unless a
+/// comparator is given as in `(make-hash-table #:comparator f)`, in
+/// which case keys are the same if F returns true for them.
+pub fn make_hash_table(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    let comparator = match args.len() {
+        0 => None,
+        2 if args[0] == LustData::symbol("#:comparator") => {
+            Some(Interpreter::eval_in_env(&args[1], env)?)
+        }
+        _ => {
+            return Err(format!(
+                "make-hash-table expected no arguments or #:comparator and a fn, got {}",
+                args
+            ))
+        }
+    };
+    let map = Map {
+        entries: Vec::new(),
+        comparator,
+    };
+    Ok(CallResult::Ret(LustData::Map(Rc::new(RefCell::new(map)))))
+}
+
+/// Takes a hash table, a key, and a value and binds the key to the
+/// value in the table. Returns the value.
+pub fn hash_table_set(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("hash-table-set!", 3, args)?;
+    let map = Interpreter::eval_in_env(&args[0], env.clone())?;
+    let map = expect_map("hash-table-set!", &map)?;
+    let key = Interpreter::eval_in_env(&args[1], env.clone())?;
+    let val = Interpreter::eval_in_env(&args[2], env.clone())?;
+    let found = map_position(&map, &key, &env)?;
+    let mut map = map.borrow_mut();
+    match found {
+        Some(i) => map.entries[i].1 = val.clone(),
+        None => map.entries.push((key, val.clone())),
+    }
+    Ok(CallResult::Ret(val))
+}
+
+/// Takes a hash table, a key, and optionally a default and returns
+/// the value bound to the key in the table. If the key isn't bound
+/// the default is returned, or an error raised if there isn't one.
+pub fn hash_table_ref(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_range("hash-table-ref", 2, 3, args)?;
+    let map = Interpreter::eval_in_env(&args[0], env.clone())?;
+    let map = expect_map("hash-table-ref", &map)?;
+    let key = Interpreter::eval_in_env(&args[1], env.clone())?;
+    match map_position(&map, &key, &env)? {
+        Some(i) => Ok(CallResult::Ret(map.borrow().entries[i].1.clone())),
+        None if args.len() == 3 => Ok(CallResult::Ret(Interpreter::eval_in_env(&args[2], env)?)),
+        None => Err(format!("hash-table-ref found no value for {}", key)),
+    }
+}
+
+/// Returns the comparator given to `make-hash-table` for a hash
+/// table, or the empty list if it wasn't given one.
+pub fn hash_table_comparator(
+    args: &ConsCell,
+    env: Rc<RefCell<LustEnv>>,
+) -> Result<CallResult, String> {
+    check_arg_len("hash-table-comparator", 1, args)?;
+    let map = Interpreter::eval_in_env(&args[0], env)?;
+    let map = expect_map("hash-table-comparator", &map)?;
+    let comparator = map.borrow().comparator.clone();
+    Ok(CallResult::Ret(
+        comparator.unwrap_or_else(LustData::get_empty_list),
+    ))
+}
+
+/// Returns the function a hash table compares its keys with. This is
+/// the `eq` builtin for tables made without a comparator.
+pub fn hash_table_equivalence_function(
+    args: &ConsCell,
+    env: Rc<RefCell<LustEnv>>,
+) -> Result<CallResult, String> {
+    check_arg_len("hash-table-equivalence-function", 1, args)?;
+    let map = Interpreter::eval_in_env(&args[0], env)?;
+    let map = expect_map("hash-table-equivalence-function", &map)?;
+    let comparator = map.borrow().comparator.clone();
+    Ok(CallResult::Ret(comparator.unwrap_or(LustData::Builtin(
+        BuiltinFn {
+            name: "eq",
+            signature: "a b",
+            func: eq,
+        },
+    ))))
+}
+
+/// Finds the index of the entry for KEY in MAP. The table isn't
+/// borrowed while the comparator runs so it may use the table.
+fn map_position(
+    map: &Rc<RefCell<Map>>,
+    key: &LustData,
+    env: &Rc<RefCell<LustEnv>>,
+) -> Result<Option<usize>, String> {
+    let comparator = map.borrow().comparator.clone();
+    let Some(comparator) = comparator else {
+        return Ok(map.borrow().entries.iter().position(|(k, _)| k == key));
+    };
+    let keys: Vec<LustData> = map
+        .borrow()
+        .entries
+        .iter()
+        .map(|(k, _)| k.clone())
+        .collect();
+    for (i, k) in keys.into_iter().enumerate() {
+        if truthy(&compile::apply(
+            comparator.clone(),
+            vec![k, key.clone()],
+            env,
+        )?) {
+            return Ok(Some(i));
+        }
+    }
+    Ok(None)
+}
+
+fn expect_map(name: &str, data: &LustData) -> Result<Rc<RefCell<Map>>, String> {
+    match data {
+        LustData::Map(m) => Ok(m.clone()),
+        _ => Err(format!(
+            "{} expected a hash table, got {}",
+            name,
+            data.type_name()
+        )),
+    }
+}
+
+/// Takes an expression and an optional message and errors if the
+/// expression evaluates to false. The error shows the expression as
+/// written.
+pub fn assert(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_range("assert", 1, 2, args)?;
+    if truthy(&Interpreter::eval_in_env(&args[0], env.clone())?) {
+        return Ok(CallResult::Ret(LustData::get_empty_list()));
+    }
+    let mut message = format!("assertion failed: {}", args[0]);
+    if args.len() == 2 {
+        let extra = Interpreter::eval_in_env(&args[1], env)?;
+        message.push_str(": ");
+        message.push_str(&extra.stringify().unwrap_or_else(|| extra.to_string()));
+    }
+    Err(message)
+}
+
+/// Takes two expressions and errors if they are not equal. The error
+/// shows both expressions and what they evaluated to.
+pub fn assert_eq(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("assert-eq", 2, args)?;
+    let l = Interpreter::eval_in_env(&args[0], env.clone())?;
+    let r = Interpreter::eval_in_env(&args[1], env)?;
+    if l == r {
+        Ok(CallResult::Ret(LustData::get_empty_list()))
+    } else {
+        Err(format!(
+            "assertion failed: {} == {} (left: {}, right: {})",
+            args[0], args[1], l, r
+        ))
+    }
+}
+
+/// Takes a string and returns an input port that reads from it.
+pub fn open_input_string(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("open-input-string", 1, args)?;
+    let s = Interpreter::eval_in_env(&args[0], env)?;
+    let chars = expect_string("open-input-string", &s)?.chars().collect();
+    let port = Port::Input { chars, pos: 0 };
+    Ok(CallResult::Ret(LustData::Port(Rc::new(RefCell::new(port)))))
+}
+
+/// Takes no arguments and returns an output port that collects what
+/// is written to it. See `get-output-string`.
+pub fn open_output_string(
+    args: &ConsCell,
+    _env: Rc<RefCell<LustEnv>>,
+) -> Result<CallResult, String> {
+    check_arg_len("open-output-string", 0, args)?;
+    let port = Port::Output(String::new());
+    Ok(CallResult::Ret(LustData::Port(Rc::new(RefCell::new(port)))))
+}
+
+/// Takes an output port and returns everything written to it so far.
+pub fn get_output_string(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("get-output-string", 1, args)?;
+    let port = Interpreter::eval_in_env(&args[0], env)?;
+    let port = expect_port("get-output-string", &port)?;
+    let res = match *port.borrow() {
+        Port::Output(ref s) => LustData::string_value(s),
+        Port::Input { .. } => {
+            return Err("get-output-string expected an output port, got an input port".to_string())
+        }
+    };
+    Ok(CallResult::Ret(res))
+}
+
+/// Takes an input port and reads a character from it. Returns the
+/// empty list once there is nothing left to read.
+pub fn read_char(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("read-char", 1, args)?;
+    let port = Interpreter::eval_in_env(&args[0], env)?;
+    let port = expect_port("read-char", &port)?;
+    let res = match *port.borrow_mut() {
+        Port::Input {
+            ref chars,
+            ref mut pos,
+        } => match chars.get(*pos) {
+            Some(c) => {
+                *pos += 1;
+                LustData::Char(*c)
+            }
+            None => LustData::get_empty_list(),
+        },
+        Port::Output(_) => {
+            return Err("read-char expected an input port, got an output port".to_string())
+        }
+    };
+    Ok(CallResult::Ret(res))
+}
+
+/// Takes an input port and reads up to the end of the current line
+/// from it. The newline is not included in the returned
+/// string. Returns the empty list once there is nothing left to read.
+pub fn read_line(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("read-line", 1, args)?;
+    let port = Interpreter::eval_in_env(&args[0], env)?;
+    let port = expect_port("read-line", &port)?;
+    let res = match *port.borrow_mut() {
+        Port::Input {
+            ref chars,
+            ref mut pos,
+        } => {
+            if *pos >= chars.len() {
+                return Ok(CallResult::Ret(LustData::get_empty_list()));
             }
+            let rest = &chars[*pos..];
+            let len = rest.iter().position(|c| *c == '\n').unwrap_or(rest.len());
+            let line: String = rest[..len].iter().collect();
+            *pos = (*pos + len + 1).min(chars.len());
+            LustData::string_value(line.trim_end_matches('\r'))
         }
+        Port::Output(_) => {
+            return Err("read-line expected an input port, got an output port".to_string())
+        }
+    };
+    Ok(CallResult::Ret(res))
+}
+
+/// Takes a string or an input port and parses the first expression
+/// in it. Reading from a port consumes the expression so the next
+/// read gets the one after it. Returns the empty list if there is
+/// nothing to read.
+pub fn read(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("read", 1, args)?;
+    let source = Interpreter::eval_in_env(&args[0], env)?;
+    let res = match source {
+        LustData::Port(ref port) => match *port.borrow_mut() {
+            Port::Input {
+                ref chars,
+                ref mut pos,
+            } => {
+                let text: String = chars[*pos..].iter().collect();
+                let (data, used) = read_data(&text)?;
+                *pos += used;
+                data
+            }
+            Port::Output(_) => {
+                return Err("read expected an input port, got an output port".to_string())
+            }
+        },
+        _ => read_data(&expect_string("read", &source)?)?.0,
+    };
+    Ok(CallResult::Ret(res))
+}
+
+/// Parses the first expression in TEXT. Returns it as data along with
+/// the number of characters it took up.
+fn read_data(text: &str) -> Result<(LustData, usize), String> {
+    let mut parser = Parser::new(text);
+    if !parser.has_more() {
+        return Ok((LustData::get_empty_list(), text.chars().count()));
+    }
+    let res = parser.parse_expr();
+    if let Some(e) = res.errors.first() {
+        return Err(format!("read failed: {}", e.what));
     }
+    let expr = res.expr.unwrap();
+    let used = Parser::first_expr_len(text);
+    Ok((expr.to_data()?, used))
 }
 
-fn eval_comma(commalist: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<LustData, String> {
-    Ok(Interpreter::eval_in_env(&commalist[1], env)?)
+/// Takes a value and an optional output port and writes the value to
+/// the port as it would be printed by the repl. Writes to the
+/// interpreter's output if no port is given.
+pub fn write(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_range("write", 1, 2, args)?;
+    let val = Interpreter::eval_in_env(&args[0], env.clone())?;
+    write_port("write", args, env, &val.to_string())?;
+    Ok(CallResult::Ret(LustData::get_empty_list()))
+}
+
+/// Like `write` but strings and characters are written without
+/// quotes.
+pub fn display(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_range("display", 1, 2, args)?;
+    let val = Interpreter::eval_in_env(&args[0], env.clone())?;
+    let s = match val {
+        LustData::Char(c) => c.to_string(),
+        _ => val.stringify().unwrap_or_else(|| val.to_string()),
+    };
+    write_port("display", args, env, &s)?;
+    Ok(CallResult::Ret(LustData::get_empty_list()))
+}
+
+/// Writes S to the port given as the second of ARGS or to the
+/// interpreter's output if there is no second argument.
+fn write_port(
+    name: &str,
+    args: &ConsCell,
+    env: Rc<RefCell<LustEnv>>,
+    s: &str,
+) -> Result<(), String> {
+    if args.len() < 2 {
+        return Interpreter::write_output(s);
+    }
+    let port = Interpreter::eval_in_env(&args[1], env)?;
+    let port = expect_port(name, &port)?;
+    let mut port = port.borrow_mut();
+    match *port {
+        Port::Output(ref mut out) => {
+            out.push_str(s);
+            Ok(())
+        }
+        Port::Input { .. } => Err(format!(
+            "{} expected an output port, got an input port",
+            name
+        )),
+    }
+}
+
+/// Extracts a port from DATA or errors on behalf of the function
+/// called NAME.
+fn expect_port(name: &str, data: &LustData) -> Result<Rc<RefCell<Port>>, String> {
+    match data {
+        LustData::Port(p) => Ok(p.clone()),
+        _ => Err(format!(
+            "{} expected a port, got {}",
+            name,
+            data.type_name()
+        )),
+    }
+}
+
+/// Takes a string of JSON and returns it as Lust data. See the `json`
+/// module for how JSON values are represented.
+pub fn from_json(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("from-json", 1, args)?;
+    let s = Interpreter::eval_in_env(&args[0], env)?;
+    Ok(CallResult::Ret(json::from_json(&expect_string(
+        "from-json",
+        &s,
+    )?)?))
+}
+
+/// Takes a value and returns it as a string of JSON.
+pub fn to_json(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("to-json", 1, args)?;
+    let val = Interpreter::eval_in_env(&args[0], env)?;
+    Ok(CallResult::Ret(LustData::string_value(&json::to_json(
+        &val,
+    )?)))
+}
+
+/// Takes no arguments and returns the interpreter's monotonic clock
+/// in seconds. Useful for timing code.
+pub fn clock(args: &ConsCell, _env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("clock", 0, args)?;
+    Ok(CallResult::Ret(LustData::Number(
+        Interpreter::clock() as f32
+    )))
+}
+
+/// Evaluates its argument, prints how long that took to the
+/// interpreter's diagnostics and returns the argument's value. The argument is not
+/// evaluated in tail position so tail calls made through `time` use
+/// stack space.
+pub fn time(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("time", 1, args)?;
+    let start = Interpreter::clock();
+    let res = Interpreter::eval_in_env(&args[0], env)?;
+    let elapsed = (Interpreter::clock() - start) * 1000.0;
+    Interpreter::write_diagnostic(&format!("elapsed: {} ms\n", elapsed))?;
+    Ok(CallResult::Ret(res))
+}
+
+/// Takes no arguments and returns the wall clock time as seconds
+/// since the unix epoch. Numbers are single precision so this is only
+/// accurate to a couple of minutes.
+pub fn now(args: &ConsCell, _env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("now", 0, args)?;
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|e| format!("system clock is before the unix epoch: {}", e))?
+        .as_secs_f64();
+    Ok(CallResult::Ret(LustData::Number(secs as f32)))
+}
+
+/// Takes no arguments and returns a random number in [0, 1).
+pub fn random(args: &ConsCell, _env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("random", 0, args)?;
+    // Numbers have 24 bits of precision so use that many random bits.
+    let bits = Interpreter::next_random() >> 40;
+    Ok(CallResult::Ret(LustData::Number(
+        bits as f32 / (1u32 << 24) as f32,
+    )))
+}
+
+/// Takes a positive whole number N and returns a random whole number
+/// in [0, N).
+pub fn random_int(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("random-int", 1, args)?;
+    let n = eval_number("random-int", &args[0], env)?;
+    if n < 1.0 || n.fract() != 0.0 {
+        return Err(format!(
+            "random-int expected a positive whole number, got {}",
+            n
+        ));
+    }
+    let i = Interpreter::next_random() % n as u64;
+    Ok(CallResult::Ret(LustData::Number(i as f32)))
+}
+
+/// Takes a number and seeds the random number generator with it so
+/// that the numbers that follow are reproducible.
+pub fn random_seed(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("random-seed", 1, args)?;
+    let seed = eval_number("random-seed", &args[0], env)?;
+    Interpreter::seed_random(seed.to_bits() as u64);
+    Ok(CallResult::Ret(LustData::get_empty_list()))
+}
+
+/// Prefix of the error returned by `exit`. Programs running the
+/// interpreter use `exit_status` to recognize it and stop with the
+/// requested status.
+pub const EXIT_PREFIX: &str = "exit requested with status ";
+
+/// Returns the status requested by `exit` if ERROR was produced by
+/// it.
+pub fn exit_status(error: &str) -> Option<i32> {
+    let first = error.lines().next()?;
+    first.strip_prefix(EXIT_PREFIX)?.parse().ok()
+}
+
+/// Takes an optional numeric status, zero by default, and stops
+/// evaluation. Rather than exiting the process this returns an error
+/// that embedders can intercept with `exit_status`.
+pub fn exit(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    let status = match args.len() {
+        0 => 0,
+        1 => eval_number("exit", &args[0], env)? as i32,
+        n => return Err(format!("exit expected 0 or 1 arguments but got {}", n)),
+    };
+    Err(format!("{}{}", EXIT_PREFIX, status))
+}
+
+/// Takes the name of an enviroment variable and returns its value as
+/// a string or the empty list if it is not set.
+pub fn getenv(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("getenv", 1, args)?;
+    let name = expect_string("getenv", &Interpreter::eval_in_env(&args[0], env)?)?;
+    Ok(CallResult::Ret(match std::env::var(name) {
+        Ok(val) => LustData::string_value(&val),
+        Err(_) => LustData::get_empty_list(),
+    }))
+}
+
+/// Runs a command with the system shell and returns a list of its
+/// exit status, stdout, and stderr. For example, `(shell "echo hi")`
+/// returns `(0 "hi\n" "")`. A status of -1 means the command was
+/// killed by a signal.
+pub fn shell(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("shell", 1, args)?;
+    let command = expect_string("shell", &Interpreter::eval_in_env(&args[0], env)?)?;
+    let output = if cfg!(windows) {
+        std::process::Command::new("cmd")
+            .args(["/C", &command])
+            .output()
+    } else {
+        std::process::Command::new("sh")
+            .args(["-c", &command])
+            .output()
+    }
+    .map_err(|e| format!("failed to run shell command {}: {}", command, e))?;
+
+    Ok(CallResult::Ret(LustData::list(vec![
+        LustData::Number(output.status.code().unwrap_or(-1) as f32),
+        LustData::string_value(&String::from_utf8_lossy(&output.stdout)),
+        LustData::string_value(&String::from_utf8_lossy(&output.stderr)),
+    ])))
+}
+
+/// Takes the path to a shared library and returns a handle to it for
+/// `ffi-sym`. See the `ffi` module.
+pub fn ffi_open(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("ffi-open", 1, args)?;
+    let path = expect_string("ffi-open", &Interpreter::eval_in_env(&args[0], env)?)?;
+    Ok(CallResult::Ret(ffi::open(&path)?))
+}
+
+/// Takes a library from `ffi-open` and the name of a C function in it
+/// and returns the function for `ffi-call`.
+pub fn ffi_sym(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    check_arg_len("ffi-sym", 2, args)?;
+    let library = Interpreter::eval_in_env(&args[0], env.clone())?;
+    let name = expect_string("ffi-sym", &Interpreter::eval_in_env(&args[1], env)?)?;
+    Ok(CallResult::Ret(ffi::sym(&library, &name)?))
+}
+
+/// Takes a C function from `ffi-sym`, the type it returns, and a type
+/// and a value for each of its arguments, and calls it. For example,
+/// `(ffi-call pow 'double 'double 2 'double 10)`.
+pub fn ffi_call(args: &ConsCell, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
+    if args.len() < 2 || !args.len().is_multiple_of(2) {
+        return Err(
+            "ffi-call expected a function, a return type, and a type and a value for each argument"
+                .to_string(),
+        );
+    }
+    let mut values = Vec::with_capacity(args.len());
+    for arg in args {
+        values.push(Interpreter::eval_in_env(arg, env.clone())?);
+    }
+    let ret = ffi::CType::from_name(values[1].expect_symbol()?)?;
+    let c_args = values[2..]
+        .chunks(2)
+        .map(|pair| {
+            Ok((
+                ffi::CType::from_name(pair[0].expect_symbol()?)?,
+                pair[1].clone(),
+            ))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    Ok(CallResult::Ret(ffi::call(&values[0], ret, &c_args)?))
+}
+
+/// Extracts a number from DATA or errors on behalf of the function
+/// called NAME.
+fn expect_number(name: &str, data: &LustData) -> Result<f32, String> {
+    match data {
+        LustData::Number(f) => Ok(*f),
+        _ => Err(format!(
+            "{} expected a number, got {}",
+            name,
+            data.type_name()
+        )),
+    }
+}
+
+/// Extracts the two numbers in ARGS on behalf of the function called
+/// NAME.
+fn expect_numbers(name: &str, args: &[LustData]) -> Result<(f32, f32), String> {
+    Ok((
+        expect_number(name, &args[0])?,
+        expect_number(name, &args[1])?,
+    ))
+}
+
+/// Evaluates EXPR and extracts a number from the result on behalf of
+/// the function called NAME.
+fn eval_number(name: &str, expr: &LustData, env: Rc<RefCell<LustEnv>>) -> Result<f32, String> {
+    expect_number(name, &Interpreter::eval_in_env(expr, env)?)
+}
+
+/// Evaluates EXPR and extracts a count, a whole number that isn't
+/// negative, from the result on behalf of the function called NAME.
+fn eval_count(name: &str, expr: &LustData, env: Rc<RefCell<LustEnv>>) -> Result<usize, String> {
+    let n = eval_number(name, expr, env)?;
+    if n < 0.0 || n.fract() != 0.0 {
+        return Err(format!(
+            "{} expected a count of zero or more, got {}",
+            name, n
+        ));
+    }
+    Ok(n as usize)
+}
+
+/// Gets the string value of DATA or errors on behalf of the function
+/// called NAME.
+fn expect_string(name: &str, data: &LustData) -> Result<String, String> {
+    // The empty string and the empty list are the same thing.
+    if data.is_empty_list() {
+        return Ok(String::new());
+    }
+    data.stringify()
+        .ok_or_else(|| format!("{} expected a string, got {}", name, data.type_name()))
+}
+
+/// A builtin that evaluates each of its arguments and is implemented
+/// on their values. Compiled code calls these with the values it has
+/// without building a list of arguments to evaluate.
+pub(crate) struct Primitive {
+    pub(crate) name: &'static str,
+    pub(crate) arity: usize,
+    pub(crate) func: fn(&[LustData]) -> Result<LustData, String>,
+}
+
+const PRIMITIVES: &[Primitive] = &[
+    Primitive {
+        name: "car",
+        arity: 1,
+        func: car_values,
+    },
+    Primitive {
+        name: "cdr",
+        arity: 1,
+        func: cdr_values,
+    },
+    Primitive {
+        name: "cons",
+        arity: 2,
+        func: cons_values,
+    },
+    Primitive {
+        name: "negate",
+        arity: 1,
+        func: negate_values,
+    },
+    Primitive {
+        name: "add",
+        arity: 2,
+        func: add_values,
+    },
+    Primitive {
+        name: "sub",
+        arity: 2,
+        func: sub_values,
+    },
+    Primitive {
+        name: "mul",
+        arity: 2,
+        func: mul_values,
+    },
+    Primitive {
+        name: "div",
+        arity: 2,
+        func: div_values,
+    },
+    Primitive {
+        name: "mod",
+        arity: 2,
+        func: mod_values,
+    },
+    Primitive {
+        name: "nan?",
+        arity: 1,
+        func: nan_p_values,
+    },
+    Primitive {
+        name: "inf?",
+        arity: 1,
+        func: inf_p_values,
+    },
+    Primitive {
+        name: "lt",
+        arity: 2,
+        func: lt_values,
+    },
+    Primitive {
+        name: "gt",
+        arity: 2,
+        func: gt_values,
+    },
+    Primitive {
+        name: "eq",
+        arity: 2,
+        func: eq_values,
+    },
+];
+
+/// Gets the primitive for the builtin called NAME if it has one.
+pub(crate) fn primitive(name: &str) -> Option<&'static Primitive> {
+    PRIMITIVES.iter().find(|p| p.name == name)
+}
+
+impl Primitive {
+    /// Calls the primitive with the values of its arguments.
+    pub(crate) fn call(&self, args: &[LustData]) -> Result<LustData, String> {
+        if args.len() != self.arity {
+            return Err(arg_len_error(self.name, self.arity, args.len()));
+        }
+        (self.func)(args)
+    }
+}
+
+/// Evaluates the N arguments to the builtin called NAME and calls
+/// FUNC, its primitive, with their values.
+fn call_primitive<const N: usize>(
+    name: &str,
+    args: &ConsCell,
+    env: Rc<RefCell<LustEnv>>,
+    func: fn(&[LustData]) -> Result<LustData, String>,
+) -> Result<CallResult, String> {
+    check_arg_len(name, N, args)?;
+    let mut values: [LustData; N] = std::array::from_fn(|_| LustData::default());
+    for (value, arg) in values.iter_mut().zip(args) {
+        *value = Interpreter::eval_in_env(arg, env.clone())?;
+    }
+    Ok(CallResult::Ret(func(&values)?))
 }
 
 /// Verifies that the function called NAME has received the expected
 /// number of arguments.
 fn check_arg_len(name: &str, expected: usize, args: &ConsCell) -> Result<(), String> {
     if args.len() != expected {
+        Err(arg_len_error(name, expected, args.len()))
+    } else {
+        Ok(())
+    }
+}
+
+fn arg_len_error(name: &str, expected: usize, got: usize) -> String {
+    format!("{} expected {} arguments but got {}", name, expected, got)
+}
+
+/// Verifies that the function called NAME has received between MIN
+/// and MAX arguments.
+fn check_arg_range(name: &str, min: usize, max: usize, args: &ConsCell) -> Result<(), String> {
+    if args.len() < min || args.len() > max {
         Err(format!(
-            "{} expected {} arguments but got {}",
+            "{} expected {} or {} arguments but got {}",
             name,
-            expected,
+            min,
+            max,
             args.len()
         ))
     } else {
@@ -319,36 +2988,1660 @@ fn check_arg_len(name: &str, expected: usize, args: &ConsCell) -> Result<(), Str
 
 /// Get's the Lust truthy equivalent to Rust boolean value.
 fn get_truthy_equiv(cond: bool) -> LustData {
+    // Interning takes a lock so the symbol is only looked up once.
+    static TRUE: OnceLock<usize> = OnceLock::new();
     if cond {
-        LustData::Symbol(Box::new("#t".to_string()))
+        LustData::Symbol(*TRUE.get_or_init(|| intern("#t")))
     } else {
         LustData::get_empty_list()
     }
 }
 
 /// Collects a list of function paramaters or errors.
-fn collect_param_list(expr: &LustData) -> Result<Vec<String>, String> {
+pub(crate) fn collect_param_list(expr: &LustData) -> Result<Vec<String>, String> {
     let v = LustData::expect_cons(expr)?;
     let mut res = Vec::with_capacity(v.len());
     for (i, e) in v.into_iter().enumerate() {
         let name = LustData::expect_symbol(e)?;
-        res.push(name.clone());
-        if name == "&" {
-            if i + 2 != v.len() {
-                return Err(
-                    "invalid varadic function. & symbol must occur before last argument"
-                        .to_string(),
-                );
-            }
+        res.push(name.to_string());
+        // Calls bind the parameter after & without checking that
+        // there is one.
+        if name == "&" && i + 2 != v.len() {
+            return Err(format!(
+                "invalid varadic function. & must be followed by exactly one parameter, got {}",
+                expr
+            ));
         }
     }
     Ok(res)
 }
 
 /// Converts some data to a Rust boolean.
-fn truthy(expr: &LustData) -> bool {
-    match LustData::expect_cons(expr) {
-        Ok(ref v) => !(v.len() == 0),
-        Err(_) => true,
+pub(crate) fn truthy(expr: &LustData) -> bool {
+    // The empty list is the only false value.
+    !expr.is_empty_list()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::each_engine;
+
+    /// Evaluates each expression in SOURCE and returns the value of
+    /// the last one.
+    fn eval(evaluator: &mut Interpreter, source: &str) -> Result<LustData, String> {
+        let mut parser = Parser::new(source);
+        let mut res = LustData::get_empty_list();
+        while parser.has_more() {
+            res = evaluator.eval_to_data(&parser.parse_expr().expr.unwrap())?;
+        }
+        Ok(res)
+    }
+
+    /// Evaluates SOURCE like `eval` and shows the result.
+    fn shown(evaluator: &mut Interpreter, source: &str) -> Result<String, String> {
+        eval(evaluator, source).map(|res| res.to_string())
+    }
+
+    /// Evaluates SOURCE with the system builtins enabled.
+    fn eval_system(source: &str) -> Result<LustData, String> {
+        let mut evaluator = Interpreter::new();
+        evaluator.enable_system_builtins();
+        eval(&mut evaluator, source)
+    }
+
+    #[test]
+    fn non_number_argument() {
+        let e = eval_system("(add 1 'a)").unwrap_err();
+        assert!(e.starts_with("add expected a number, got symbol"));
+        let e = eval_system("(lt \"a\" 1)").unwrap_err();
+        assert!(e.starts_with("lt expected a number, got string"));
+    }
+
+    #[test]
+    fn car_cdr_and_cons() {
+        let mut evaluator = Interpreter::new();
+        assert_eq!(shown(&mut evaluator, "(car '(1 2))"), Ok("1".to_string()));
+        assert_eq!(shown(&mut evaluator, "(cdr '(1 2))"), Ok("(2)".to_string()));
+        assert_eq!(shown(&mut evaluator, "(cdr '(1))"), Ok("()".to_string()));
+        assert_eq!(shown(&mut evaluator, "(cons 1 ())"), Ok("(1)".to_string()));
+        assert_eq!(
+            shown(&mut evaluator, "(cons '(1) '(2))"),
+            Ok("((1) 2)".to_string())
+        );
+        assert_eq!(shown(&mut evaluator, "(pair 1 2)"), Ok("(1 2)".to_string()));
+        assert_eq!(
+            shown(&mut evaluator, "(pair 1 ())"),
+            Ok("(1 ())".to_string())
+        );
+
+        for (source, expected) in [
+            ("(car '())", "car expected a list that isn't empty, got ()"),
+            ("(cdr '())", "cdr expected a list that isn't empty, got ()"),
+            ("(car 1)", "car expected a list, got number"),
+            ("(cdr 'a)", "cdr expected a list, got symbol"),
+            (
+                "(cons 1 2)",
+                "cons expected a list as its second argument, got number",
+            ),
+        ] {
+            let e = shown(&mut evaluator, source).unwrap_err();
+            assert!(e.starts_with(expected), "{}: {}", source, e);
+        }
+    }
+
+    #[test]
+    fn list_accessors() {
+        let mut evaluator = Interpreter::new();
+        eval(&mut evaluator, "(let tree '((1 2) (3 (4 5)) 6))").unwrap();
+        for (source, expected) in [
+            ("(caar tree)", "1"),
+            ("(cadr tree)", "(3 (4 5))"),
+            ("(cdar tree)", "(2)"),
+            ("(cddr tree)", "(6)"),
+            ("(caddr tree)", "6"),
+            ("(cdddr tree)", "()"),
+            ("(first tree)", "(1 2)"),
+            ("(second tree)", "(3 (4 5))"),
+            ("(third tree)", "6"),
+            ("(rest tree)", "((3 (4 5)) 6)"),
+            ("(second (second (second tree)))", "5"),
+            ("(car? tree)", "(1 2)"),
+            ("(car? ())", "()"),
+            ("(nth 0 tree)", "(1 2)"),
+            ("(nth 2 tree)", "6"),
+            ("(nth? 1 tree)", "(3 (4 5))"),
+            ("(nth? 3 tree)", "()"),
+            ("(nth? 0 ())", "()"),
+            ("(car? (nth? 5 tree))", "()"),
+        ] {
+            assert_eq!(
+                shown(&mut evaluator, source),
+                Ok(expected.to_string()),
+                "{}",
+                source
+            );
+        }
+
+        for (source, expected) in [
+            (
+                "(caar ())",
+                "caar expected a list that isn't empty, got () in ()",
+            ),
+            ("(caar '(1))", "caar expected a list, got 1 in (1)"),
+            (
+                "(caar '(()))",
+                "caar expected a list that isn't empty, got () in (())",
+            ),
+            (
+                "(cadr '(1))",
+                "cadr expected a list that isn't empty, got () in (1)",
+            ),
+            (
+                "(cdar '(()))",
+                "cdar expected a list that isn't empty, got () in (())",
+            ),
+            (
+                "(cddr '(1))",
+                "cddr expected a list that isn't empty, got () in (1)",
+            ),
+            (
+                "(caddr '(1 2))",
+                "caddr expected a list that isn't empty, got () in (1 2)",
+            ),
+            (
+                "(cdddr '(1 2))",
+                "cdddr expected a list that isn't empty, got () in (1 2)",
+            ),
+            (
+                "(first ())",
+                "first expected a list that isn't empty, got () in ()",
+            ),
+            (
+                "(second '(1))",
+                "second expected a list that isn't empty, got () in (1)",
+            ),
+            (
+                "(third '(1 2))",
+                "third expected a list that isn't empty, got () in (1 2)",
+            ),
+            (
+                "(rest ())",
+                "rest expected a list that isn't empty, got () in ()",
+            ),
+            ("(first 1)", "first expected a list, got 1 in 1"),
+            (
+                "(nth 2 '(1 2))",
+                "nth expected a list of more than 2 items, got (1 2)",
+            ),
+            (
+                "(nth -1 '(1 2))",
+                "nth expected a count of zero or more, got -1",
+            ),
+            ("(car? 1)", "car? expected a list, got number"),
+            ("(nth? 0 1)", "nth? expected a list, got number"),
+        ] {
+            let e = shown(&mut evaluator, source).unwrap_err();
+            assert!(e.starts_with(expected), "{}: {}", source, e);
+        }
+    }
+
+    #[test]
+    fn flatten() {
+        let mut evaluator = Interpreter::new();
+        let source = "(flatten '(1 (2 (3 ()) 4) ((5))))";
+        assert_eq!(
+            eval(&mut evaluator, source).unwrap().to_string(),
+            "(1 2 (3 ()) 4 (5))"
+        );
+        let source = "(flatten (cons \"ab\" (cons '(c) ())))";
+        assert_eq!(
+            eval(&mut evaluator, source).unwrap().to_string(),
+            "(\"ab\" c)"
+        );
+        assert!(eval(&mut evaluator, "(flatten ())")
+            .unwrap()
+            .is_empty_list());
+        assert!(eval(&mut evaluator, "(flatten '(() ()))")
+            .unwrap()
+            .is_empty_list());
+        let e = eval(&mut evaluator, "(flatten 1)").unwrap_err();
+        assert!(e.starts_with("expected list, got 1"));
+    }
+
+    #[test]
+    fn flatten_deep() {
+        let mut evaluator = Interpreter::new();
+        let source = "(flatten-deep '(1 (2 (3 ()) 4) ((5))))";
+        assert_eq!(
+            eval(&mut evaluator, source).unwrap().to_string(),
+            "(1 2 3 4 5)"
+        );
+        let source = "(flatten-deep (cons \"ab\" (cons '(c) ())))";
+        assert_eq!(
+            eval(&mut evaluator, source).unwrap().to_string(),
+            "(\"ab\" c)"
+        );
+        assert_eq!(
+            eval(&mut evaluator, "(flatten-deep 1)")
+                .unwrap()
+                .to_string(),
+            "(1)"
+        );
+        assert!(eval(&mut evaluator, "(flatten-deep ())")
+            .unwrap()
+            .is_empty_list());
+        assert!(eval(&mut evaluator, "(flatten-deep '(() (()) ((() ()))))")
+            .unwrap()
+            .is_empty_list());
+        let source = "(flatten-deep '(() 1 (() 2 (())) ()))";
+        assert_eq!(eval(&mut evaluator, source).unwrap().to_string(), "(1 2)");
+    }
+
+    #[test]
+    fn flatten_deeply_nested() {
+        // Flattening doesn't recurse but dropping the nested list
+        // does, so this runs with a stack as big as the one lust runs
+        // on.
+        let runner = std::thread::Builder::new()
+            .stack_size(256 * 1024 * 1024)
+            .spawn(|| {
+                let mut evaluator = Interpreter::new();
+                let nest =
+                    "(let nest (fn (n l) (if (eq n 0) l (nest (sub n 1) (cons l (cons n ()))))))";
+                eval(&mut evaluator, nest).unwrap();
+                for depth in [1, 100, 10_000] {
+                    let source = format!("(flatten-deep (nest {} '(0)))", depth);
+                    let flat = eval(&mut evaluator, &source).unwrap();
+                    let flat = flat.as_list().unwrap();
+                    assert_eq!(flat.len(), depth + 1);
+                    assert_eq!(flat[depth], LustData::Number(1.0));
+                }
+            })
+            .unwrap();
+        runner.join().unwrap();
+    }
+
+    #[test]
+    fn sort() {
+        let mut evaluator = Interpreter::new();
+        let sorted = |evaluator: &mut Interpreter, source: &str| {
+            eval(evaluator, source).map(|res| res.to_string())
+        };
+        assert_eq!(
+            sorted(
+                &mut evaluator,
+                "(sort (cons 3 (cons (negate 1) '(2.5 0 2))))"
+            ),
+            Ok("(-1 0 2 2.5 3)".to_string())
+        );
+        assert_eq!(sorted(&mut evaluator, "(sort ())"), Ok("()".to_string()));
+        let e = sorted(&mut evaluator, "(sort '(1 a))").unwrap_err();
+        assert!(e.starts_with("sort expected a number, got symbol"));
+
+        assert_eq!(
+            sorted(&mut evaluator, "(sort-by gt '(3 1 2))"),
+            Ok("(3 2 1)".to_string())
+        );
+        assert_eq!(
+            sorted(&mut evaluator, "(sort-by (fn (a b) (sub a b)) '(3 1 2))"),
+            Ok("(1 2 3)".to_string())
+        );
+        // Items that compare equal keep their order.
+        let source = "(sort-by (fn (a b) (lt (car a) (car b))) '((2 a) (1 b) (2 c) (1 d)))";
+        assert_eq!(
+            sorted(&mut evaluator, source),
+            Ok("((1 b) (1 d) (2 a) (2 c))".to_string())
+        );
+        // Comparators that aren't consistent don't crash.
+        assert!(sorted(&mut evaluator, "(sort-by (fn (a b) 'yes) '(1 2 3 4 5))").is_ok());
+        let e = sorted(&mut evaluator, "(sort-by add '(1 a))").unwrap_err();
+        assert!(e.starts_with("add expected a number"));
+    }
+
+    #[test]
+    fn identity_and_equality() {
+        each_engine(|mut evaluator| {
+            eval(
+                &mut evaluator,
+                "(let both (fn (a b) (cons a (cons b ()))))
+                 (let l '(1 2))
+                 (let same l)
+                 (let adder (fn (n) (fn (x) (add x n))))
+                 (let add1 (adder 1))
+                 (let id1 (fn (x) x))
+                 (let id2 (fn (x) x))
+                 (let fns (cons (fn () 1) ()))
+                 (let g (car fns))",
+            )
+            .unwrap();
+            let cases = [
+                ("(both (eq 1 1) (identical? 1 1))", "(#t #t)"),
+                ("(both (eq 'a 'a) (identical? 'a 'b))", "(#t ())"),
+                ("(both (eq l '(1 2)) (identical? l '(1 2)))", "(#t ())"),
+                ("(both (eq l same) (identical? l same))", "(#t #t)"),
+                ("(both (eq () ()) (identical? () ()))", "(#t #t)"),
+                ("(both (eq car car) (identical? car car))", "(#t #t)"),
+                ("(both (eq car cdr) (identical? car cdr))", "(() ())"),
+                ("(both (eq add1 add1) (identical? add1 add1))", "(#t #t)"),
+                // Closures with the same code aren't the same function.
+                ("(eq add1 (adder 2))", "()"),
+                ("(eq add1 (adder 1))", "()"),
+                ("(eq id1 id2)", "()"),
+                // Binding a function that is already shared doesn't
+                // copy it to name it.
+                (
+                    "(both (eq g (car fns)) (identical? g (car fns)))",
+                    "(#t #t)",
+                ),
+                // The Scheme names.
+                ("(both (equal? l '(1 2)) (eq? l '(1 2)))", "(#t ())"),
+                (
+                    "(both (equal? '(1 (2 \"ab\")) '(1 (2 \"ab\"))) (eqv? l '(1 2)))",
+                    "(#t ())",
+                ),
+                ("(both (equal? \"ab\" \"ac\") (eq? l same))", "(() #t)"),
+                ("(both (eqv? 1 1) (eqv? l same))", "(#t #t)"),
+                (
+                    "(both (eq? (car \"a\") (car \"a\")) (equal? id1 id1))",
+                    "(#t #t)",
+                ),
+            ];
+            for (source, expected) in cases {
+                assert_eq!(
+                    shown(&mut evaluator, source),
+                    Ok(expected.to_string()),
+                    "{}",
+                    source
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn find_and_count() {
+        let mut evaluator = Interpreter::new();
+        eval(&mut evaluator, "(let big (fn (n) (gt n 2)))").unwrap();
+        let cases = [
+            ("(find big '(1 3 4))", "3"),
+            ("(find big '(1 2))", "()"),
+            ("(find big ())", "()"),
+            ("(find-index big '(1 2 4))", "2"),
+            ("(find-index big '(3))", "0"),
+            ("(find-index big '(1 2))", "()"),
+            ("(count big '(1 3 4 2))", "2"),
+            ("(count big ())", "0"),
+            ("(any? big '(1 3))", "#t"),
+            ("(any? big '(1 2))", "()"),
+            ("(any? big ())", "()"),
+            ("(every? big '(3 4))", "#t"),
+            ("(every? big '(3 1))", "()"),
+            ("(every? big ())", "#t"),
+            ("(count-if (fn (x) (gt x 0)) '(0 2 0 3))", "2"),
+            ("(count-if (fn (x) (gt x 0)) ())", "0"),
+            ("(all? (fn (x) (gt x 0)) '(1 2))", "#t"),
+            ("(all? (fn (x) (gt x 0)) '(1 0))", "()"),
+            ("(all? (fn (x) (gt x 0)) ())", "#t"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(
+                shown(&mut evaluator, source),
+                Ok(expected.to_string()),
+                "{}",
+                source
+            );
+        }
+        // Searching stops once the answer is known.
+        eval(
+            &mut evaluator,
+            "(let checked (fn (n) (if (eq n 4) (error \"called\") (big n))))",
+        )
+        .unwrap();
+        for source in [
+            "(find checked '(3 4))",
+            "(any? checked '(3 4))",
+            "(every? checked '(1 4))",
+            "(all? checked '(1 4))",
+        ] {
+            assert!(shown(&mut evaluator, source).is_ok(), "{}", source);
+        }
+        let e = shown(&mut evaluator, "(count big 1)").unwrap_err();
+        assert!(e.starts_with("expected list, got 1"));
+        let e = shown(&mut evaluator, "(count-if big)").unwrap_err();
+        assert!(e.starts_with("count-if expected 2 arguments"), "{}", e);
+        assert!(shown(&mut evaluator, "(find big)").is_err());
+    }
+
+    #[test]
+    fn non_finite_arithmetic() {
+        each_engine(|mut evaluator| {
+            eval(
+                &mut evaluator,
+                "(let big 100000000000000000000)
+                 (let inf (mul big big))
+                 (let nan (sub inf inf))",
+            )
+            .unwrap();
+            let cases = [
+                ("(mod 7 3)", "1"),
+                ("(mod (negate 7) 3)", "2"),
+                ("(mod 7 (negate 3))", "-2"),
+                ("(mod 1.5 1)", "0.5"),
+                ("(div 0 2)", "0"),
+                ("(inf? inf)", "#t"),
+                ("(inf? (negate inf))", "#t"),
+                ("(inf? big)", "()"),
+                ("(nan? nan)", "#t"),
+                ("(nan? inf)", "()"),
+                ("(div 1 inf)", "0"),
+                ("(nan? (mod inf 2))", "#t"),
+                ("(nan? (add nan 1))", "#t"),
+                // NaN doesn't equal anything, even itself, and isn't
+                // ordered.
+                ("(eq nan nan)", "()"),
+                ("(eq nan 1)", "()"),
+                ("(lt nan 1)", "()"),
+                ("(gt nan 1)", "()"),
+                ("(eq inf inf)", "#t"),
+            ];
+            for (source, expected) in cases {
+                assert_eq!(
+                    shown(&mut evaluator, source),
+                    Ok(expected.to_string()),
+                    "{}",
+                    source
+                );
+            }
+            for (source, err) in [
+                ("(div 1 0)", "div expected a non-zero divisor, got 0"),
+                (
+                    "(div inf (negate 0))",
+                    "div expected a non-zero divisor, got -0",
+                ),
+                ("(div nan 0)", "div expected a non-zero divisor, got 0"),
+                ("(mod 1 0)", "mod expected a non-zero divisor, got 0"),
+                ("(mod inf 0)", "mod expected a non-zero divisor, got 0"),
+            ] {
+                let e = shown(&mut evaluator, source).unwrap_err();
+                assert!(e.starts_with(err), "{}: {}", source, e);
+            }
+        });
+    }
+
+    #[test]
+    fn iota() {
+        let mut evaluator = Interpreter::new();
+        let cases = [
+            ("(iota 3)", "(0 1 2)"),
+            ("(iota 0)", "()"),
+            ("(iota 3 1)", "(1 2 3)"),
+            ("(iota 4 0 0.5)", "(0 0.5 1 1.5)"),
+            ("(iota 3 5 (negate 1))", "(5 4 3)"),
+            ("(iota 3 (negate 1) 0)", "(-1 -1 -1)"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(
+                eval(&mut evaluator, source).map(|res| res.to_string()),
+                Ok(expected.to_string()),
+                "{}",
+                source
+            );
+        }
+        let e = eval(&mut evaluator, "(iota (negate 1))").unwrap_err();
+        assert!(e.starts_with("iota expected a count of zero or more, got -1"));
+        assert!(eval(&mut evaluator, "(iota 1 2 3 4)").is_err());
+    }
+
+    #[test]
+    fn pretty_print() {
+        let mut evaluator = Interpreter::new();
+        let output = Rc::new(RefCell::new(Vec::new()));
+        evaluator.set_output(output.clone());
+        let res = eval(
+            &mut evaluator,
+            "(pretty-print '(define (f x) (g (h x) 1) y))",
+        );
+        assert!(res.unwrap().is_empty_list());
+        let expected = "(define\n  (f x)\n  (g\n    (h x)\n    1)\n  y)\n";
+        assert_eq!(String::from_utf8(output.take()).unwrap(), expected);
+
+        for (source, expected) in [("'(1 2 ())", "(1 2 ())"), ("\"ab\"", "\"ab\""), ("1", "1")] {
+            eval(&mut evaluator, &format!("(pretty-print {})", source)).unwrap();
+            assert_eq!(
+                String::from_utf8(output.take()).unwrap(),
+                format!("{}\n", expected)
+            );
+        }
+    }
+
+    #[test]
+    fn repeat() {
+        let mut evaluator = Interpreter::new();
+        let output = Rc::new(RefCell::new(Vec::new()));
+        evaluator.set_output(output.clone());
+        let cases = [
+            ("(repeat 3 'x)", "(x x x)"),
+            ("(repeat 0 'x)", "()"),
+            ("(repeat 2 (cons 1 ()))", "((1) (1))"),
+            // The value is only evaluated once.
+            ("(repeat 3 (add (if (println 'ran) 0 1) 1))", "(2 2 2)"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(
+                eval(&mut evaluator, source).map(|res| res.to_string()),
+                Ok(expected.to_string()),
+                "{}",
+                source
+            );
+        }
+        assert_eq!(output.borrow().as_slice(), b"ran\n");
+        let e = eval(&mut evaluator, "(repeat 1.5 'x)").unwrap_err();
+        assert!(e.starts_with("repeat expected a count of zero or more, got 1.5"));
+        let e = eval(&mut evaluator, "(repeat (negate 1) 'x)").unwrap_err();
+        assert!(e.starts_with("repeat expected a count of zero or more, got -1"));
+    }
+
+    #[test]
+    fn take_and_drop() {
+        let mut evaluator = Interpreter::new();
+        eval(&mut evaluator, "(let small (fn (n) (lt n 3)))").unwrap();
+        let cases = [
+            ("(take 2 '(1 2 3))", "(1 2)"),
+            ("(take 0 '(1 2 3))", "()"),
+            ("(take 5 '(1 2 3))", "(1 2 3)"),
+            ("(take 2 ())", "()"),
+            ("(drop 2 '(1 2 3))", "(3)"),
+            ("(drop 0 '(1 2 3))", "(1 2 3)"),
+            ("(drop 5 '(1 2 3))", "()"),
+            ("(drop 1 ())", "()"),
+            ("(take-while small '(1 2 3 1))", "(1 2)"),
+            ("(take-while small '(3 1))", "()"),
+            ("(take-while small '(1 2))", "(1 2)"),
+            ("(take-while small ())", "()"),
+            ("(drop-while small '(1 2 3 1))", "(3 1)"),
+            ("(drop-while small '(3 1))", "(3 1)"),
+            ("(drop-while small '(1 2))", "()"),
+            ("(drop-while small ())", "()"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(
+                shown(&mut evaluator, source),
+                Ok(expected.to_string()),
+                "{}",
+                source
+            );
+        }
+        let e = shown(&mut evaluator, "(take (negate 1) '(1))").unwrap_err();
+        assert!(e.starts_with("take expected a count of zero or more, got -1"));
+        let e = shown(&mut evaluator, "(drop 1.5 '(1))").unwrap_err();
+        assert!(e.starts_with("drop expected a count of zero or more, got 1.5"));
+        let e = shown(&mut evaluator, "(take-while small 1)").unwrap_err();
+        assert!(e.starts_with("expected list, got 1"));
+        // The predicate is only called until it is false.
+        let source = "(drop-while (fn (n) (if (eq n 4) (error \"called\") (lt n 3))) '(1 3 4))";
+        assert_eq!(shown(&mut evaluator, source), Ok("(3 4)".to_string()));
+    }
+
+    #[test]
+    fn concatenate_and_flatten_map() {
+        let mut evaluator = Interpreter::new();
+        eval(&mut evaluator, "(let twice (fn (x) (cons x (cons x ()))))").unwrap();
+        let cases = [
+            ("(concatenate '((1 2) () (3) ((4))))", "(1 2 3 (4))"),
+            ("(concatenate ())", "()"),
+            ("(flatten-map twice '(1 2))", "(1 1 2 2)"),
+            ("(flatten-map twice ())", "()"),
+            ("(flatten-map (fn (x) ()) '(1 2))", "()"),
+            ("(flatten-map (fn (x) (cons x ())) '((1) (2)))", "((1) (2))"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(
+                shown(&mut evaluator, source),
+                Ok(expected.to_string()),
+                "{}",
+                source
+            );
+        }
+        let e = shown(&mut evaluator, "(concatenate '((1) 2))").unwrap_err();
+        assert!(e.starts_with("expected list, got 2"));
+        let e = shown(&mut evaluator, "(flatten-map (fn (x) x) '(1))").unwrap_err();
+        assert!(e.starts_with("expected list, got 1"));
+    }
+
+    #[test]
+    fn module() {
+        let mut evaluator = Interpreter::new();
+        let source = "(module math
+                        (export square)
+                        (let helper (fn (x) (mul x x)))
+                        (let square (fn (x) (helper x))))";
+        assert_eq!(eval(&mut evaluator, source), Ok(LustData::symbol("math")));
+        assert_eq!(
+            eval(&mut evaluator, "(math/square 3)"),
+            Ok(LustData::Number(9.0))
+        );
+        let e = eval(&mut evaluator, "(math/helper 3)").unwrap_err();
+        assert!(e.starts_with("failed to resolve identifier math/helper"));
+        let e = eval(&mut evaluator, "(helper 3)").unwrap_err();
+        assert!(e.starts_with("failed to resolve identifier helper"));
+
+        // Without an export list everything is exported.
+        eval(&mut evaluator, "(module m (let a 1) (let b (add a 1)))").unwrap();
+        assert_eq!(
+            eval(&mut evaluator, "(add m/a m/b)"),
+            Ok(LustData::Number(3.0))
+        );
+
+        let e = eval(&mut evaluator, "(module m (export c) (let a 1))").unwrap_err();
+        assert!(e.starts_with("module m exports c but does not define it"));
+    }
+
+    #[test]
+    fn zip_and_unzip() {
+        let mut evaluator = Interpreter::new();
+        let cases = [
+            ("(zip '(1 2 3) '(a b c))", "((1 a) (2 b) (3 c))"),
+            ("(zip '(1 2 3) '(a) '(x y))", "((1 a x))"),
+            ("(zip '(1 2))", "((1) (2))"),
+            ("(zip () '(1 2))", "()"),
+            ("(unzip '((1 a) (2 b)))", "((1 2) (a b))"),
+            ("(unzip ())", "(() ())"),
+            ("(unzip (zip '(1 2) '(3 4)))", "((1 2) (3 4))"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(shown(&mut evaluator, source), Ok(expected.to_string()));
+        }
+        let e = shown(&mut evaluator, "(zip)").unwrap_err();
+        assert!(e.starts_with("zip expected at least one list"));
+        let e = shown(&mut evaluator, "(unzip '((1 2) (3)))").unwrap_err();
+        assert!(e.starts_with("unzip expected a list of pairs, got (3)"));
+    }
+
+    #[test]
+    fn partition_and_group_by() {
+        let mut evaluator = Interpreter::new();
+        assert_eq!(
+            shown(&mut evaluator, "(partition (fn (n) (lt n 3)) '(1 4 2 5 3))"),
+            Ok("((1 2) (4 5 3))".to_string())
+        );
+        assert_eq!(
+            shown(&mut evaluator, "(partition (fn (n) n) ())"),
+            Ok("(() ())".to_string())
+        );
+
+        let source = "(group-by (fn (n) (gt n 2)) '(1 3 2 4))";
+        assert_eq!(
+            shown(&mut evaluator, source),
+            Ok("((() 1 2) (#t 3 4))".to_string())
+        );
+        // Keys are compared by value so strings work.
+        let source = "(group-by car (cons \"ab\" (cons \"cd\" (cons \"ae\" ()))))";
+        assert_eq!(
+            shown(&mut evaluator, source),
+            Ok("(('a' \"ab\" \"ae\") ('c' \"cd\"))".to_string())
+        );
+        let source = "(group-by cdr (cons \"xa\" (cons \"ya\" ())))";
+        assert_eq!(
+            shown(&mut evaluator, source),
+            Ok("((\"a\" \"xa\" \"ya\"))".to_string())
+        );
+        assert_eq!(
+            shown(&mut evaluator, "(group-by car ())"),
+            Ok("()".to_string())
+        );
+        let e = shown(&mut evaluator, "(partition car 1)").unwrap_err();
+        assert!(e.starts_with("expected list, got 1"));
+
+        assert_eq!(
+            shown(&mut evaluator, "(partition 2 '(1 2 3 4 5))"),
+            Ok("((1 2) (3 4) (5))".to_string())
+        );
+        assert_eq!(
+            shown(&mut evaluator, "(partition 3 '(1 2 3))"),
+            Ok("((1 2 3))".to_string())
+        );
+        assert_eq!(
+            shown(&mut evaluator, "(partition 2 ())"),
+            Ok("()".to_string())
+        );
+        for n in ["0", "-1", "1.5"] {
+            let e = shown(&mut evaluator, &format!("(partition {} '(1 2))", n)).unwrap_err();
+            assert!(e.starts_with(&format!(
+                "partition expected a count of one or more, got {}",
+                n
+            )));
+        }
+    }
+
+    #[test]
+    fn malformed_varadic_params() {
+        let mut evaluator = Interpreter::new();
+        for params in ["(&)", "(a &)", "(& a b)", "(a & & b)"] {
+            for form in ["fn", "macro"] {
+                let source = format!("({} {} 1)", form, params);
+                let e = eval(&mut evaluator, &source).unwrap_err();
+                let expected = format!(
+                    "invalid varadic function. & must be followed by exactly one parameter, got {}",
+                    params
+                );
+                assert!(e.starts_with(&expected), "{}", e);
+            }
+        }
+        let source = "((fn (& rest) rest) 1 2)";
+        assert_eq!(eval(&mut evaluator, source).unwrap().to_string(), "(1 2)");
+    }
+
+    #[test]
+    fn defrecord() {
+        each_engine(|mut evaluator| {
+            let source = "(defrecord point (x y)) (let p (point 1 (add 1 1))) p";
+            assert_eq!(
+                shown(&mut evaluator, source),
+                Ok("#point{x 1 y 2}".to_string())
+            );
+            assert_eq!(shown(&mut evaluator, "(point-y p)"), Ok("2".to_string()));
+            assert_eq!(shown(&mut evaluator, "(point? p)"), Ok("#t".to_string()));
+            assert_eq!(
+                shown(&mut evaluator, "(point? '(1 2))"),
+                Ok("()".to_string())
+            );
+            assert_eq!(
+                shown(&mut evaluator, "(eq p (point 1 2))"),
+                Ok("#t".to_string())
+            );
+            assert_eq!(
+                shown(&mut evaluator, "(eq p (point 2 1))"),
+                Ok("()".to_string())
+            );
+
+            let source = "(let q (record-set p 'x 5)) (cons (point-x p) (cons q ()))";
+            assert_eq!(
+                shown(&mut evaluator, source),
+                Ok("(1 #point{x 5 y 2})".to_string())
+            );
+            let e = shown(&mut evaluator, "(record-set p 'z 5)").unwrap_err();
+            assert!(e.starts_with("point has no field z"));
+
+            let e = shown(&mut evaluator, "(point 1 2 3)").unwrap_err();
+            assert!(
+                e.starts_with("wrong number of arguments for point"),
+                "{}",
+                e
+            );
+            let source = "(defrecord size (x y)) (point-x (size 1 2))";
+            let e = shown(&mut evaluator, source).unwrap_err();
+            assert!(e.starts_with("point-x expected a point, got size"), "{}", e);
+            let e = shown(&mut evaluator, "(point-x 1)").unwrap_err();
+            assert!(
+                e.starts_with("point-x expected a point, got number"),
+                "{}",
+                e
+            );
+        });
+    }
+
+    #[test]
+    fn define_record_type() {
+        each_engine(|mut evaluator| {
+            let source = "(define-record-type pare (kons x y) pare?
+                            (x kar set-kar!)
+                            (y kdr))
+                          (let p (kons 1 2))
+                          p";
+            assert_eq!(
+                shown(&mut evaluator, source),
+                Ok("#pare{x 1 y 2}".to_string())
+            );
+            assert_eq!(shown(&mut evaluator, "(kdr p)"), Ok("2".to_string()));
+            assert_eq!(shown(&mut evaluator, "(pare? p)"), Ok("#t".to_string()));
+            assert_eq!(shown(&mut evaluator, "(pare? 1)"), Ok("()".to_string()));
+            // Modifiers change the record in place.
+            let source = "(let q p) (set-kar! p 3) (kar q)";
+            assert_eq!(shown(&mut evaluator, source), Ok("3".to_string()));
+            assert_eq!(
+                shown(&mut evaluator, "(eq p (kons 3 2))"),
+                Ok("#t".to_string())
+            );
+
+            let source = "(define-record-type cell (make-cell value) cell?
+                            (value cell-value)
+                            (count cell-count set-cell-count!))
+                          (make-cell 1)";
+            assert_eq!(
+                shown(&mut evaluator, source),
+                Ok("#cell{value 1 count ()}".to_string())
+            );
+            let source =
+                "(define-record-type node make-node node? (left node-left) (right node-right))
+                          (node-right (make-node 1 2))";
+            assert_eq!(shown(&mut evaluator, source), Ok("2".to_string()));
+
+            let e = shown(&mut evaluator, "(set-kar! (make-cell 1) 2)").unwrap_err();
+            assert!(e.starts_with("pare-x expected a pare, got cell"), "{}", e);
+            let source = "(define-record-type bad (make-bad z) bad? (x bad-x))";
+            let e = shown(&mut evaluator, source).unwrap_err();
+            assert!(
+                e.starts_with(
+                    "define-record-type constructor make-bad takes z which is not a field"
+                ),
+                "{}",
+                e
+            );
+            let source = "(define-record-type bad make-bad bad? (x))";
+            let e = shown(&mut evaluator, source).unwrap_err();
+            assert!(
+                e.starts_with(
+                    "define-record-type expected a field like (name accessor [modifier]), got (x)"
+                ),
+                "{}",
+                e
+            );
+        });
+    }
+
+    #[test]
+    fn metadata() {
+        each_engine(|mut evaluator| {
+            let source = "(let f (with-meta (fn (x) x) '(deprecated)))
+                          (let pass (fn (g) g))
+                          (meta (pass f))";
+            assert_eq!(
+                shown(&mut evaluator, source),
+                Ok("(deprecated)".to_string())
+            );
+            assert_eq!(shown(&mut evaluator, "(f 3)"), Ok("3".to_string()));
+
+            let source = "(let l (with-meta '(1 2) '(source test)))
+                          (cons (meta l) (cons l ()))";
+            assert_eq!(
+                shown(&mut evaluator, source),
+                Ok("((source test) (1 2))".to_string())
+            );
+            assert_eq!(shown(&mut evaluator, "(eq l '(1 2))"), Ok("#t".to_string()));
+            assert_eq!(
+                shown(&mut evaluator, "(eq l (with-meta '(1 2) 'other))"),
+                Ok("#t".to_string())
+            );
+            assert_eq!(shown(&mut evaluator, "(meta '(1 2))"), Ok("()".to_string()));
+            assert_eq!(shown(&mut evaluator, "(meta 1)"), Ok("()".to_string()));
+            let e = shown(&mut evaluator, "(with-meta 1 'a)").unwrap_err();
+            assert!(e.starts_with("with-meta expected a fn, macro, or list, got number"));
+        });
+    }
+
+    #[test]
+    fn dynamic_variables() {
+        each_engine(|mut evaluator| {
+            eval(
+                &mut evaluator,
+                "(defdynamic *width* 80)
+                 (let width (fn () *width*))
+                 (let both (fn () (cons (width) (cons *width* ()))))",
+            )
+            .unwrap();
+            let source = "(binding ((*width* 120))
+                            (cons (both) (cons (binding ((*width* 40)) (both)) (cons (width) ()))))";
+            assert_eq!(
+                shown(&mut evaluator, source),
+                Ok("((120 120) (40 40) 120)".to_string())
+            );
+            assert_eq!(shown(&mut evaluator, "(width)"), Ok("80".to_string()));
+
+            // Bindings are undone when the body errors.
+            let source = "(binding ((*width* 1)) (width) (error \"failed\"))";
+            assert!(shown(&mut evaluator, source).is_err());
+            assert_eq!(shown(&mut evaluator, "(width)"), Ok("80".to_string()));
+
+            // Lexical variables aren't dynamic, and shadow dynamic ones.
+            let e = shown(&mut evaluator, "(let x 1) (binding ((x 2)) x)").unwrap_err();
+            assert!(e.starts_with("binding expected a dynamic variable, got x"));
+            let source = "(let local (fn (*width*) (binding ((*width* 2)) *width*))) (local 3)";
+            assert_eq!(shown(&mut evaluator, source), Ok("3".to_string()));
+            assert_eq!(shown(&mut evaluator, "*width*"), Ok("80".to_string()));
+        });
+    }
+
+    #[test]
+    fn scoped_let() {
+        each_engine(|mut evaluator| {
+            let source = "(let ((x 1) (y (add x 1))) (add x 100) (add x y))";
+            assert_eq!(eval(&mut evaluator, source), Ok(LustData::Number(3.0)));
+            let e = eval(&mut evaluator, "x").unwrap_err();
+            assert!(e.starts_with("failed to resolve identifier x"));
+
+            // Outer bindings are shadowed and then seen again.
+            let source = "(let x 1) (let f (fn (n) (add (let ((x n)) x) x))) (f 5)";
+            assert_eq!(eval(&mut evaluator, source), Ok(LustData::Number(6.0)));
+            assert_eq!(eval(&mut evaluator, "x"), Ok(LustData::Number(1.0)));
+
+            let e = eval(&mut evaluator, "(let ((x)) x)").unwrap_err();
+            assert!(e.starts_with("let expected a binding like (name value), got (x)"));
+            let e = eval(&mut evaluator, "(let ((x 1)))").unwrap_err();
+            assert!(e.starts_with("let expected a list of bindings and a body"));
+        });
+    }
+
+    #[test]
+    fn match_() {
+        let mut evaluator = Interpreter::new();
+        let matched = |evaluator: &mut Interpreter, source: &str| {
+            eval(evaluator, source).map(|res| res.to_string())
+        };
+        let describe = "(let describe (fn (v) (match v
+                          (0 'zero)
+                          ('a 'symbol-a)
+                          (() 'empty)
+                          ((x) (cons 'one (cons x ())))
+                          ((1 x & xs) (cons x xs))
+                          ((x (y z)) (cons z (cons y (cons x ()))))
+                          (_ 'other))))";
+        eval(&mut evaluator, describe).unwrap();
+        let cases = [
+            ("0", "zero"),
+            ("'a", "symbol-a"),
+            ("()", "empty"),
+            ("'(5)", "(one 5)"),
+            ("'(1 2 3 4)", "(2 3 4)"),
+            // The rest of the list may be empty.
+            ("'(1 2)", "(2)"),
+            ("'(4 (2 3))", "(3 2 4)"),
+            ("'(2 3 4)", "other"),
+            ("'b", "other"),
+        ];
+        for (value, expected) in cases {
+            let source = format!("(describe {})", value);
+            assert_eq!(matched(&mut evaluator, &source), Ok(expected.to_string()));
+        }
+
+        // Bindings are only seen by the result.
+        let e = matched(&mut evaluator, "(match 1 (x x)) x").unwrap_err();
+        assert!(e.starts_with("failed to resolve identifier x"));
+        let e = matched(&mut evaluator, "(match '(1 2) ((x) x))").unwrap_err();
+        assert!(e.starts_with("match found no clause matching (1 2)"));
+        let e = matched(&mut evaluator, "(match '(1) ((x &) x))").unwrap_err();
+        assert!(e.starts_with("match expected one pattern after & in (x &)"));
+    }
+
+    #[test]
+    fn match_cons_and_list() {
+        let mut evaluator = Interpreter::new();
+        let matched = |evaluator: &mut Interpreter, source: &str| {
+            eval(evaluator, source).map(|res| res.to_string())
+        };
+        let describe = "(let describe (fn (v) (match v
+                          (\"hi\" 'greeting)
+                          ((list 'point x y) (cons y (cons x ())))
+                          ((cons 0 (cons x _)) x)
+                          ((cons (list a b) tail) (cons b (cons a tail)))
+                          ((cons h t) t)
+                          (_ 'other))))";
+        eval(&mut evaluator, describe).unwrap();
+        let cases = [
+            ("\"hi\"", "greeting"),
+            ("'(point 1 2)", "(2 1)"),
+            ("'(point 1)", "(1)"),
+            ("'(0 5 6)", "5"),
+            ("'((1 2) 3 4)", "(2 1 3 4)"),
+            ("'(7)", "()"),
+            // The empty list and other values fall through.
+            ("()", "other"),
+            ("3", "other"),
+        ];
+        for (value, expected) in cases {
+            let source = format!("(describe {})", value);
+            assert_eq!(
+                matched(&mut evaluator, &source),
+                Ok(expected.to_string()),
+                "{}",
+                source
+            );
+        }
+        let e = matched(&mut evaluator, "(match '(1) ((cons x) x))").unwrap_err();
+        assert!(e.starts_with("match expected a pattern like (cons head tail), got (cons x)"));
+    }
+
+    #[test]
+    fn match_is_tail_position() {
+        let mut evaluator = Interpreter::new();
+        let source =
+            "(let count (fn (n) (match n (0 'done) (_ (count (sub n 1)))))) (count 100000)";
+        assert_eq!(eval(&mut evaluator, source).unwrap().to_string(), "done");
+    }
+
+    #[test]
+    fn loop_and_recur() {
+        each_engine(|mut evaluator| {
+            let source = "(let factorial (fn (n)
+                            (loop ((n n) (acc 1))
+                              (if (eq n 0) acc (recur (sub n 1) (mul acc n))))))
+                          (factorial 5)";
+            assert_eq!(eval(&mut evaluator, source), Ok(LustData::Number(120.0)));
+            // Recurring doesn't grow the stack.
+            let source = "(loop ((i 0) (j (add i 1)))
+                            (let k (add i j))
+                            (if (eq i 20000) k (recur (add i 1) j)))";
+            assert_eq!(eval(&mut evaluator, source), Ok(LustData::Number(20001.0)));
+            // recur goes back to the nearest loop.
+            let source = "(loop ((i 0) (total 0))
+                            (if (eq i 3)
+                                total
+                                (recur (add i 1)
+                                       (add total (loop ((j 0)) (if (eq j 2) j (recur (add j 1))))))))";
+            assert_eq!(eval(&mut evaluator, source), Ok(LustData::Number(6.0)));
+            // Functions made in the body see the values of that pass.
+            let source = "(loop ((i 0) (fns ()))
+                            (if (eq i 2)
+                                (cons ((car fns)) (cons ((car (cdr fns))) ()))
+                                (recur (add i 1) (cons (fn () i) fns))))";
+            assert_eq!(eval(&mut evaluator, source).unwrap().to_string(), "(1 0)");
+
+            let e = eval(&mut evaluator, "(recur 1)").unwrap_err();
+            assert!(e.starts_with("recur must be called inside a loop"), "{}", e);
+            let e = eval(&mut evaluator, "(loop ((i 0)) (recur 1 2))").unwrap_err();
+            assert!(
+                e.starts_with("recur expected 1 arguments but got 2"),
+                "{}",
+                e
+            );
+            let e = eval(&mut evaluator, "(loop (i 0) i)").unwrap_err();
+            assert!(
+                e.starts_with("loop expected a binding like (name value), got i"),
+                "{}",
+                e
+            );
+        });
+    }
+
+    #[test]
+    fn fn_bodies() {
+        each_engine(|mut evaluator| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            evaluator.set_output(output.clone());
+            let source = "(let square (fn (x) \"Squares X.\" (println x) (mul x x)))
+                          (square 3)";
+            assert_eq!(eval(&mut evaluator, source), Ok(LustData::Number(9.0)));
+            assert_eq!(output.borrow().as_slice(), b"3\n");
+            assert_eq!(
+                eval(&mut evaluator, "square").unwrap().to_string(),
+                "(fn square (x) (println x) (mul x x))"
+            );
+            let doc = eval(&mut evaluator, "(doc square)").unwrap();
+            assert_eq!(doc.to_string(), "\"Squares X.\"");
+
+            // Names bound in the body are seen by later expressions.
+            let source = "((fn (x) (let y (add x 1)) (mul y 2)) 1)";
+            assert_eq!(eval(&mut evaluator, source), Ok(LustData::Number(4.0)));
+            // A lone string is the body, not a docstring.
+            let source = "((fn () \"hi\"))";
+            assert_eq!(eval(&mut evaluator, source).unwrap().to_string(), "\"hi\"");
+
+            // The last expression is a tail call.
+            let source = "(let loop (fn (n acc) (let acc (add acc 1)) (if (eq n 0) acc (loop (sub n 1) acc))))
+                          (loop 20000 0)";
+            assert_eq!(eval(&mut evaluator, source), Ok(LustData::Number(20001.0)));
+
+            let source = "(let twice (macro (x) (let y x) `(add ,y ,y))) (twice 2)";
+            assert_eq!(eval(&mut evaluator, source), Ok(LustData::Number(4.0)));
+            let e = eval(&mut evaluator, "(fn (x))").unwrap_err();
+            assert!(e.starts_with("fn expected a parameter list and a body but got 1 arguments"));
+        });
+    }
+
+    #[test]
+    fn fn_clauses() {
+        each_engine(|mut evaluator| {
+            eval(
+                &mut evaluator,
+                "(let factorial (fn ((0) 1) ((n) (mul n (factorial (sub n 1))))))
+                 (let size (fn (() 'none) ((_) 'one) ((_ _ & _) 'many)))
+                 (let scaler (fn (k) (fn ((0) 0) ((n) (mul k n)))))
+                 (let count (fn ((0 acc) acc) ((n acc) (count (sub n 1) (add acc 1)))))",
+            )
+            .unwrap();
+            let cases = [
+                ("(factorial 0)", "1"),
+                ("(factorial 5)", "120"),
+                ("(size)", "none"),
+                ("(size 1)", "one"),
+                ("(size 1 2 3)", "many"),
+                // Clauses see the enviroment the function was made in.
+                ("((scaler 3) 2)", "6"),
+                ("((scaler 3) 0)", "0"),
+                ("(count 20000 0)", "20000"),
+            ];
+            for (source, expected) in cases {
+                assert_eq!(
+                    shown(&mut evaluator, source),
+                    Ok(expected.to_string()),
+                    "{}",
+                    source
+                );
+            }
+            let e = shown(&mut evaluator, "(factorial 1 2)").unwrap_err();
+            assert!(
+                e.starts_with("fn found no clause matching the arguments (1 2)"),
+                "{}",
+                e
+            );
+            let e = shown(&mut evaluator, "(fn ((x) 1 2))").unwrap_err();
+            assert!(e.starts_with("fn expected a clause like (params body), got ((x) 1 2)"));
+
+            let source = "((case-lambda ((x) x) ((x y) (add x y))) 1 2)";
+            assert_eq!(shown(&mut evaluator, source), Ok("3".to_string()));
+            let e = shown(&mut evaluator, "(case-lambda (x x))").unwrap_err();
+            assert!(e.starts_with("case-lambda expected a clause like (params body), got (x x)"));
+        });
+    }
+
+    #[test]
+    fn tail_call_p() {
+        each_engine(|mut evaluator| {
+            let source = "(let f (fn (n) (if (eq n 0) (tail-call? n) (f (sub n 1))))) (f 3)";
+            assert_eq!(eval(&mut evaluator, source).unwrap().to_string(), "#t");
+            let source = "(let g (fn (n) (cons (tail-call? n) ()))) (g 1)";
+            assert_eq!(eval(&mut evaluator, source).unwrap().to_string(), "(())");
+            // Top level expressions aren't in a function.
+            assert!(eval(&mut evaluator, "(tail-call? 1)")
+                .unwrap()
+                .is_empty_list());
+            // The argument is evaluated.
+            let e = eval(&mut evaluator, "(tail-call? (error \"evaluated\"))").unwrap_err();
+            assert!(e.starts_with("\"evaluated\""));
+        });
+    }
+
+    #[test]
+    fn force_memoizes() {
+        let mut evaluator = Interpreter::new();
+        let output = Rc::new(RefCell::new(Vec::new()));
+        evaluator.set_output(output.clone());
+        let source = "(let p (delay (if (println \"ran\") 0 1))) (force p) (force p)";
+        assert_eq!(eval(&mut evaluator, source), Ok(LustData::Number(1.0)));
+        assert_eq!(output.borrow().as_slice(), b"ran\n");
+    }
+
+    #[test]
+    fn memoize() {
+        each_engine(|mut evaluator| {
+            let output = Rc::new(RefCell::new(Vec::new()));
+            evaluator.set_output(output.clone());
+            let source = "(let double (memoize (fn (n) (if (println n) () (add n n)))))
+                          (cons (double 1) (cons (double 2) (cons (double 1) (cons (double 2) ()))))";
+            assert_eq!(
+                eval(&mut evaluator, source).unwrap().to_string(),
+                "(2 4 2 4)"
+            );
+            assert_eq!(output.borrow().as_slice(), b"1\n2\n");
+
+            // Only the most recent results are remembered.
+            output.borrow_mut().clear();
+            let source = "(let half (memoize (fn (n) (if (println n) () (div n 2))) 1))
+                          (half 2) (half 2) (half 4) (half 2)";
+            assert_eq!(eval(&mut evaluator, source), Ok(LustData::Number(1.0)));
+            assert_eq!(output.borrow().as_slice(), b"2\n4\n2\n");
+
+            // Recursive calls go through the cache.
+            let source =
+                "(let fib (memoize (fn (n) (if (lt n 2) n (add (fib (sub n 1)) (fib (sub n 2)))))))
+                          (fib 30)";
+            assert_eq!(eval(&mut evaluator, source), Ok(LustData::Number(832040.0)));
+            let e = eval(&mut evaluator, "(memoize 1)").unwrap_err();
+            assert!(e.starts_with("memoize expected a fn, got number"));
+        });
+    }
+
+    #[test]
+    fn hash_tables() {
+        let mut evaluator = Interpreter::new();
+        eval(
+            &mut evaluator,
+            "(let plain (make-hash-table))
+             (hash-table-set! plain \"ab\" 1)
+             (hash-table-set! plain '(1 2) 2)
+             (hash-table-set! plain \"ab\" 3)
+             (let same-start (fn (a b) (eq (car a) (car b))))
+             (let loose (make-hash-table #:comparator same-start))
+             (hash-table-set! loose \"ab\" 1)
+             (hash-table-set! loose \"ac\" 2)",
+        )
+        .unwrap();
+        let cases = [
+            ("(hash-table-ref plain \"ab\")", "3"),
+            ("(hash-table-ref plain '(1 2))", "2"),
+            ("(hash-table-ref plain \"ac\" 'none)", "none"),
+            ("plain", "<hash table of 2>"),
+            ("(hash-table-comparator plain)", "()"),
+            ("(hash-table-equivalence-function plain)", "<builtin eq>"),
+            ("((hash-table-equivalence-function plain) 1 1)", "#t"),
+            ("(hash-table-ref loose \"ax\")", "2"),
+            ("(hash-table-ref loose \"b\" 'none)", "none"),
+            ("loose", "<hash table of 1>"),
+            ("(eq (hash-table-comparator loose) same-start)", "#t"),
+            (
+                "(eq (hash-table-equivalence-function loose) same-start)",
+                "#t",
+            ),
+            ("(eq plain plain)", "#t"),
+            ("(eq plain (make-hash-table))", "()"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(
+                shown(&mut evaluator, source),
+                Ok(expected.to_string()),
+                "{}",
+                source
+            );
+        }
+        let e = shown(&mut evaluator, "(hash-table-ref plain \"ac\")").unwrap_err();
+        assert!(e.starts_with("hash-table-ref found no value for \"ac\""));
+        let e = shown(&mut evaluator, "(hash-table-ref 1 2)").unwrap_err();
+        assert!(e.starts_with("hash-table-ref expected a hash table, got number"));
+        let e = shown(&mut evaluator, "(make-hash-table same-start)").unwrap_err();
+        assert!(e.starts_with("make-hash-table expected no arguments or #:comparator"));
+    }
+
+    #[test]
+    fn promise_p() {
+        let mut evaluator = Interpreter::new();
+        let source = "(let p (delay (add 1 2))) (promise? p)";
+        assert_eq!(eval(&mut evaluator, source).unwrap().to_string(), "#t");
+        assert_eq!(eval(&mut evaluator, "p").unwrap().to_string(), "#<promise>");
+        assert!(eval(&mut evaluator, "(promise? (force p))")
+            .unwrap()
+            .is_empty_list());
+        assert_eq!(
+            eval(&mut evaluator, "p").unwrap().to_string(),
+            "#<promise: 3>"
+        );
+    }
+
+    #[test]
+    fn mutually_lazy() {
+        let mut evaluator = Interpreter::new();
+        eval(
+            &mut evaluator,
+            "(let evens (delay (cons 0 (cons odds ()))))
+             (let odds (delay (cons 1 (cons evens ()))))",
+        )
+        .unwrap();
+        let odds = "(force (car (cdr (force evens))))";
+        assert_eq!(
+            eval(&mut evaluator, &format!("(car {})", odds)),
+            Ok(LustData::Number(1.0))
+        );
+        // Following the cycle back around gives the same promise.
+        let source = format!("(eq evens (car (cdr {})))", odds);
+        assert!(eval(&mut evaluator, &source)
+            .unwrap()
+            .expect_symbol()
+            .is_ok());
+    }
+
+    #[test]
+    fn time_is_transparent() {
+        let mut evaluator = Interpreter::new();
+        eval(&mut evaluator, "(let f (fn (x) (cons x (quote (2)))))").unwrap();
+        assert_eq!(
+            eval(&mut evaluator, "(time (f 1))"),
+            eval(&mut evaluator, "(f 1)")
+        );
+        assert!(eval(&mut evaluator, "(time)").is_err());
+    }
+
+    #[test]
+    fn random_is_reproducible() {
+        let source = "(random-seed 7) (cons (random) (cons (random-int 100) ()))";
+        let mut evaluator = Interpreter::new();
+        let first = eval(&mut evaluator, source).unwrap();
+        assert_eq!(eval(&mut evaluator, source), Ok(first.clone()));
+
+        // Interpreters don't share a generator.
+        let mut other = Interpreter::new();
+        eval(&mut other, "(random-seed 7)").unwrap();
+        eval(&mut evaluator, "(random-seed 7) (random)").unwrap();
+        let rest = "(cons (random) (cons (random-int 100) ()))";
+        assert_eq!(eval(&mut other, rest), Ok(first));
+
+        let n = eval(&mut evaluator, "(random)")
+            .unwrap()
+            .expect_num()
+            .unwrap();
+        assert!((0.0..1.0).contains(&n));
+        assert!(eval(&mut evaluator, "(random-int 0)").is_err());
+    }
+
+    #[test]
+    fn assert() {
+        let mut evaluator = Interpreter::new();
+        eval(
+            &mut evaluator,
+            "(let x 1) (assert (eq x 1)) (assert-eq x 1)",
+        )
+        .unwrap();
+        assert_eq!(
+            eval(&mut evaluator, "(assert (eq x 2) \"x is wrong\")"),
+            Err(
+                "assertion failed: (eq x 2): x is wrong\nstack trace:\n  in assert (2 arguments)"
+                    .to_string()
+            )
+        );
+        let e = eval(&mut evaluator, "(assert-eq (add x 1) 3)").unwrap_err();
+        assert!(e.starts_with("assertion failed: (add x 1) == 3 (left: 2, right: 3)"));
+    }
+
+    #[test]
+    fn string_ports() {
+        let mut evaluator = Interpreter::new();
+        eval(
+            &mut evaluator,
+            "(let in (open-input-string \"ab\ncd\")) (let out (open-output-string))",
+        )
+        .unwrap();
+        assert_eq!(
+            eval(&mut evaluator, "(read-char in)"),
+            Ok(LustData::Char('a'))
+        );
+        let line = eval(&mut evaluator, "(read-line in)").unwrap();
+        assert_eq!(line.stringify(), Some("b".to_string()));
+        let line = eval(&mut evaluator, "(read-line in)").unwrap();
+        assert_eq!(line.stringify(), Some("cd".to_string()));
+        assert!(eval(&mut evaluator, "(read-line in)")
+            .unwrap()
+            .is_empty_list());
+        assert!(eval(&mut evaluator, "(read-char in)")
+            .unwrap()
+            .is_empty_list());
+
+        let out = eval(
+            &mut evaluator,
+            "(display \"x = \" out) (write (cons 1 (cons \"two\" ())) out) (get-output-string out)",
+        )
+        .unwrap();
+        assert_eq!(out.stringify(), Some("x = (1 \"two\")".to_string()));
+        assert!(eval(&mut evaluator, "(write 1 in)").is_err());
+    }
+
+    #[test]
+    fn quasiquote() {
+        let mut evaluator = Interpreter::new();
+        eval(&mut evaluator, "(let x 5)").unwrap();
+        let cases = [
+            ("(quasiquote (1 (unquote (add 1 1)) 3))", "(1 2 3)"),
+            ("(quasiquote (a (b (unquote x))))", "(a (b 5))"),
+            ("(quasiquote (unquote x))", "5"),
+            ("(quasiquote x)", "x"),
+            ("`(1 ,(add 1 1) 3)", "(1 2 3)"),
+            // Only the outermost quasiquote's commas are evaluated.
+            (
+                "(quasiquote (a (quasiquote (b (unquote (c (unquote x)))))))",
+                "(a (quasiquote (b (unquote (c 5)))))",
+            ),
+            ("`(a `(b ,x))", "(a (quaziquote (b (comma x))))"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(
+                shown(&mut evaluator, source),
+                Ok(expected.to_string()),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn macroexpand_once() {
+        let mut evaluator = Interpreter::new();
+        eval(
+            &mut evaluator,
+            "(let inner (macro (x) `(add ,x 1))) (let outer (macro (x) `(inner ,x)))",
+        )
+        .unwrap();
+        let expanded = eval(&mut evaluator, "(macroexpand-1 (outer 2))").unwrap();
+        assert_eq!(expanded.to_string(), "(inner 2)");
+        let expanded = eval(&mut evaluator, "(macroexpand (outer 2))").unwrap();
+        assert_eq!(expanded.to_string(), "(add 2 1)");
+        let expanded = eval(&mut evaluator, "(macroexpand-1 (add 2 1))").unwrap();
+        assert_eq!(expanded.to_string(), "(add 2 1)");
+    }
+
+    #[test]
+    fn macro_args_unevaluated() {
+        let mut evaluator = Interpreter::new();
+        let source = "(let describe (macro (x)
+                        (if (eq (car x) 'add)
+                            `(quote (addition of ,(cdr x)))
+                            `(quote (something else)))))";
+        eval(&mut evaluator, source).unwrap();
+        assert_eq!(
+            eval(&mut evaluator, "(describe (add 1 undefined))")
+                .unwrap()
+                .to_string(),
+            "(addition of (1 undefined))"
+        );
+        assert_eq!(
+            eval(&mut evaluator, "(describe (sub 1 2))")
+                .unwrap()
+                .to_string(),
+            "(something else)"
+        );
+    }
+
+    #[test]
+    fn macro_rest_args_unevaluated() {
+        each_engine(|mut evaluator| {
+            eval(
+                &mut evaluator,
+                "(let args (macro (first & rest) `(quote (,first ,rest))))",
+            )
+            .unwrap();
+            // UNBOUND would fail to resolve if it were evaluated.
+            let source = "(args (add 1 2) unbound (car (quote (x))))";
+            assert_eq!(
+                eval(&mut evaluator, source).unwrap().to_string(),
+                "((add 1 2) (unbound (car (quote (x)))))"
+            );
+            assert_eq!(
+                eval(&mut evaluator, "(args 1)").unwrap().to_string(),
+                "(1 ())"
+            );
+        });
+    }
+
+    #[test]
+    fn read() {
+        let mut evaluator = Interpreter::new();
+        let list = eval(&mut evaluator, "(read \"(add 1 2) ignored\")").unwrap();
+        assert_eq!(list.to_string(), "(add 1 2)");
+        assert_eq!(
+            eval(&mut evaluator, "(eval (read \"(add 1 2)\"))"),
+            Ok(LustData::Number(3.0))
+        );
+
+        eval(
+            &mut evaluator,
+            "(let in (open-input-string \"one\t(two 2)\n3\"))",
+        )
+        .unwrap();
+        let items: Vec<String> = (0..4)
+            .map(|_| eval(&mut evaluator, "(read in)").unwrap().to_string())
+            .collect();
+        assert_eq!(items, vec!["one", "(two 2)", "3", "()"]);
+        assert!(eval(&mut evaluator, "(read \"(unclosed\")").is_err());
+    }
+
+    #[test]
+    fn doc() {
+        let mut evaluator = Interpreter::new();
+        let source = "(let square (fn (x) \"Returns x*x.\" (mul x x))) (doc square)";
+        let doc = eval(&mut evaluator, source).unwrap();
+        assert_eq!(doc.stringify(), Some("Returns x*x.".to_string()));
+        assert_eq!(
+            eval(&mut evaluator, "(square 3)"),
+            Ok(LustData::Number(9.0))
+        );
+        assert_eq!(
+            eval(&mut evaluator, "square").unwrap().to_string(),
+            "(fn square (x) (mul x x))"
+        );
+        let doc = eval(&mut evaluator, "(doc car)").unwrap().stringify();
+        assert_eq!(
+            doc.as_deref(),
+            Some("(car list) returns the first item in LIST.")
+        );
+        assert!(eval(&mut evaluator, "(doc (fn (x) x))")
+            .unwrap()
+            .is_empty_list());
+        assert!(eval(&mut evaluator, "(doc 1)").unwrap().is_empty_list());
+    }
+
+    #[test]
+    fn help() {
+        let mut evaluator = Interpreter::new();
+        let output = Rc::new(RefCell::new(Vec::new()));
+        evaluator.set_output(output.clone());
+        eval(
+            &mut evaluator,
+            "(let square (fn (x) \"Squares X.\" (mul x x))) (let y 1) (help)",
+        )
+        .unwrap();
+        let out = String::from_utf8(output.borrow().clone()).unwrap();
+        assert!(out.contains(&format!("{:<32} builtin\n", "(cons item list)")));
+        assert!(out.contains(&format!("{:<32} builtin\n", "(clock)")));
+        assert!(out.contains(&format!("{:<32} fn\n", "(square x)")));
+        assert!(!out.contains("(y"));
+
+        output.borrow_mut().clear();
+        eval(&mut evaluator, "(help 'square)").unwrap();
+        let out = String::from_utf8(output.borrow().clone()).unwrap();
+        assert_eq!(out, format!("{:<32} fn\n  Squares X.\n", "(square x)"));
+        assert!(eval(&mut evaluator, "(help 'y)").is_err());
+    }
+
+    #[test]
+    fn exit() {
+        let e = eval_system("(exit 3)").unwrap_err();
+        assert_eq!(exit_status(&e), Some(3));
+        assert_eq!(exit_status(&eval_system("(exit)").unwrap_err()), Some(0));
+        assert_eq!(exit_status("failed to resolve identifier x"), None);
+    }
+
+    #[test]
+    fn system_builtins_are_opt_in() {
+        assert!(eval(&mut Interpreter::new(), "(getenv \"HOME\")").is_err());
+    }
+
+    #[test]
+    fn getenv() {
+        std::env::set_var("LUST_GETENV_TEST", "hello");
+        assert_eq!(
+            eval_system("(getenv \"LUST_GETENV_TEST\")")
+                .unwrap()
+                .stringify(),
+            Some("hello".to_string())
+        );
+        assert!(eval_system("(getenv \"LUST_GETENV_UNSET\")")
+            .unwrap()
+            .is_empty_list());
+        assert!(eval_system("(getenv 1)")
+            .unwrap_err()
+            .starts_with("getenv expected a string, got number"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shell() {
+        let res = eval_system("(shell \"echo hi\")")
+            .unwrap()
+            .into_list()
+            .unwrap();
+        assert_eq!(res[0].expect_num(), Ok(0.0));
+        assert_eq!(res[1].stringify(), Some("hi\n".to_string()));
+        assert!(res[2].is_empty_list());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn ffi() {
+        let mut evaluator = Interpreter::new();
+        evaluator.enable_system_builtins();
+        let source = "(let libc (ffi-open \"libc.so.6\"))
+                      (let libm (ffi-open \"libm.so.6\"))";
+        eval(&mut evaluator, source).unwrap();
+        for (source, expected) in [
+            (
+                "(ffi-call (ffi-sym libm \"pow\") 'double 'double 2 'double 10)",
+                "1024",
+            ),
+            ("(ffi-call (ffi-sym libm \"cos\") 'double 'double 0)", "1"),
+            (
+                "(ffi-call (ffi-sym libc \"abs\") 'int 'int (negate 5))",
+                "5",
+            ),
+            (
+                "(ffi-call (ffi-sym libc \"strlen\") 'int 'ptr \"hello\")",
+                "5",
+            ),
+            (
+                "(ffi-call (ffi-sym libc \"getpid\") 'int)",
+                &std::process::id().to_string(),
+            ),
+            (
+                "(ffi-call (ffi-sym libc \"getenv\") 'ptr 'ptr \"LUST_FFI_UNSET\")",
+                "()",
+            ),
+            ("(ffi-sym libc \"strlen\")", "<opaque>"),
+        ] {
+            let res = eval(&mut evaluator, source).map(|r| r.to_string());
+            assert_eq!(res, Ok(expected.to_string()), "{}", source);
+        }
+        let source = "(let dup (ffi-call (ffi-sym libc \"strdup\") 'ptr 'ptr \"hi\"))
+                      (ffi-call (ffi-sym libc \"strlen\") 'int 'ptr dup)";
+        assert_eq!(eval(&mut evaluator, source), Ok(LustData::Number(2.0)));
+        // free returns nothing. Its result is read as an int and
+        // ignored as there is no void type.
+        eval(
+            &mut evaluator,
+            "(ffi-call (ffi-sym libc \"free\") 'int 'ptr dup)",
+        )
+        .unwrap();
+
+        for (source, expected) in [
+            (
+                "(ffi-open \"no-such-library.so\")",
+                "ffi-open failed to open no-such-library.so",
+            ),
+            (
+                "(ffi-sym libc \"no_such_function\")",
+                "ffi-sym failed to find no_such_function",
+            ),
+            (
+                "(ffi-sym 1 \"abs\")",
+                "ffi-sym expected a library, got number",
+            ),
+            (
+                "(ffi-call 1 'int)",
+                "ffi-call expected a function from ffi-sym, got number",
+            ),
+            (
+                "(ffi-call (ffi-sym libc \"abs\") 'long 'int 1)",
+                "ffi-call expected a type of int, double, or ptr, got long",
+            ),
+            (
+                "(ffi-call (ffi-sym libc \"abs\") 'int 'int 1.5)",
+                "ffi-call expected an int, got 1.5",
+            ),
+            (
+                "(ffi-call (ffi-sym libc \"abs\") 'int 'int)",
+                "ffi-call expected a function, a return type, and a type and a value",
+            ),
+            (
+                "(ffi-call (ffi-sym libc \"abs\") 'int 'int 1 'int 2 'int 3 'int 4)",
+                "ffi-call supports at most 3 arguments, got 4",
+            ),
+        ] {
+            let e = eval(&mut evaluator, source).unwrap_err();
+            assert!(e.starts_with(expected), "{}: {}", source, e);
+        }
+        assert!(eval(&mut Interpreter::new(), "(ffi-open \"libc.so.6\")").is_err());
     }
 }
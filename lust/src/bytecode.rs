@@ -1,79 +1,679 @@
-use std::fmt;
+/// A stack based virtual machine for compiled code. The operations
+/// made by the `compile` module are lowered to a chunk of bytecode,
+/// a flat list of instructions that push and pop values from a stack
+/// and jump between each other, which the machine runs in a loop.
+///
+/// Calls between functions run by the machine push a frame onto the
+/// machine's own call stack rather than recursing and calls in tail
+/// position replace the running frame. Builtins, macros, functions
+/// made by the interpreter, and anything the compiler left to the
+/// interpreter are handed back to the interpreter.
+///
+/// For example, `(let double (fn (x) (add x x)))` is lowered to
+///
+/// ```text
+///    0 CHECK_SHADOW double
+///    1 MAKE_CLOSURE (x) (add x x)
+///    2 STORE_GLOBAL double
+///    3 RETURN
+/// ```
+///
+/// and the body of `double` to
+///
+/// ```text
+///    0 GET_GLOBAL add
+///    1 CHECK_CALLEE 5
+///    2 LOAD_PARAM 0 0
+///    3 LOAD_PARAM 0 0
+///    4 TAIL_CALL 2 add
+///    5 RETURN
+/// ```
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::rc::Rc;
 
-use crate::interpreter::LustData;
+use crate::builtins::{truthy, Primitive};
+use crate::compile::{self, CompiledFn, Frame, Op, Scope, Template};
+use crate::intern::resolve_intern;
+use crate::interpreter::{Interpreter, LustData, LustEnv, LustFn};
 
-pub enum Inst {
-    PushConst(LustData),
-    StoreLocal(String),
-    GetSym(String),
-    Call,
-    Puts,
-    Exit,
+/// A compiled expression or function body as bytecode.
+pub struct Chunk {
+    code: Vec<OpCode>,
+    constants: Vec<LustData>,
+    templates: Vec<Rc<Template>>,
+    /// The scope of the function body the chunk was lowered from or
+    /// None for top level expressions.
+    scope: Option<Rc<Scope>>,
 }
 
-pub struct Func {
-    params: Vec<String>,
-    body: Vec<Inst>,
+#[derive(Clone, Copy)]
+pub(crate) enum OpCode {
+    /// Pushes a constant.
+    PushConst(usize),
+    /// Pushes a parameter of the function DEPTH functions out from
+    /// the one running.
+    LoadParam {
+        depth: usize,
+        slot: usize,
+    },
+    /// Pushes a name bound with `let` in the function DEPTH functions
+    /// out and jumps to BOUND. If the name hasn't been bound yet, the
+    /// instructions that follow load what it means outside of the
+    /// function.
+    LoadLocal {
+        depth: usize,
+        slot: usize,
+        bound: usize,
+    },
+    /// Pushes the value of a name in the global enviroment.
+    GetGlobal(usize),
+    /// Checks that binding a name doesn't shadow a builtin.
+    CheckShadow(&'static str),
+    /// Binds a name to the value on top of the stack, leaving it there.
+    Store {
+        name: &'static str,
+        slot: Option<usize>,
+    },
+    /// Discards the value on top of the stack.
+    Pop,
+    Jump(usize),
+    /// Pops a value and jumps if it is false.
+    JumpUnless(usize),
+    /// Pushes a function made from a template.
+    MakeClosure(usize),
+    /// Pushes the value of a constant expression evaluated by the
+    /// interpreter. Expressions in TAIL position aren't nested.
+    Interpret {
+        expr: usize,
+        tail: bool,
+    },
+    /// Starts a call that isn't in tail position. The call's source,
+    /// if it is to a named function, is recorded for stack traces.
+    Nest(Option<usize>),
+    /// Finishes the call started by the last `Nest`.
+    Unnest,
+    /// Checks the function on top of the stack. If it has to be given
+    /// its arguments unevaluated, it is replaced by the value of the
+    /// call's source as evaluated by the interpreter and the machine
+    /// jumps to AFTER, skipping the arguments and the call.
+    CheckCallee {
+        source: usize,
+        after: usize,
+    },
+    /// Calls the function below ARGC arguments on the stack. If the
+    /// function is the builtin PRIMITIVE implements, the primitive is
+    /// called directly.
+    Call {
+        argc: usize,
+        primitive: Option<&'static Primitive>,
+    },
+    /// Calls a function in place of the one running. Calls that can't
+    /// replace the running function push their result instead.
+    TailCall {
+        argc: usize,
+        primitive: Option<&'static Primitive>,
+    },
+    /// Returns the value on top of the stack.
+    Return,
 }
 
-pub enum Data {
-    Func(Box<Func>),
-    List(Vec<Data>),
-    Num(f32),
-    Char(char),
-    Symbol(String),
+/// Lowers OP to bytecode. SCOPE is the scope of the function body OP
+/// was compiled from, or None if it is a top level expression.
+pub(crate) fn lower(op: &Op, scope: Option<Rc<Scope>>) -> Chunk {
+    let tail = scope.is_some();
+    let mut chunk = Chunk {
+        code: Vec::new(),
+        constants: Vec::new(),
+        templates: Vec::new(),
+        scope,
+    };
+    chunk.lower(op, tail);
+    if !tail {
+        chunk.code.push(OpCode::Return);
+    }
+    chunk
 }
 
-pub struct Executor {
-    stack: Vec<LustData>,
+/// Calls FUNC, whose body is CHUNK, with ARGS.
+pub(crate) fn call(
+    func: &LustFn,
+    compiled: &CompiledFn,
+    chunk: &Rc<Chunk>,
+    args: Vec<LustData>,
+) -> Result<LustData, String> {
+    let scope = chunk.scope.as_ref().expect("function body without a scope");
+    let frame = compile::new_frame(func, compiled, scope, args);
+    Machine::new(chunk, Some(frame), &func.env).run()
+}
+
+/// Runs CHUNK, a top level expression, in GLOBALS.
+pub(crate) fn run(chunk: &Rc<Chunk>, globals: &Rc<RefCell<LustEnv>>) -> Result<LustData, String> {
+    Machine::new(chunk, None, globals).run()
 }
 
-impl Executor {
-    pub fn new() -> Self {
-        Self { stack: Vec::new() }
+/// Gets the bytecode for the body of FUNC if it is a function run by
+/// the virtual machine.
+pub fn chunk_of(func: &LustData) -> Option<Rc<Chunk>> {
+    match func {
+        LustData::Fn(f) => f.compiled.as_ref()?.template.chunk(&f.env).cloned(),
+        _ => None,
     }
+}
 
-    pub fn compile(data: &LustData) -> Result<Vec<Inst>, String> {
-        match data {
-            LustData::Symbol(s) => Ok(vec![Inst::GetSym(*s.clone())]),
-            LustData::List(l) => {
-                let mut res = Vec::new();
-                for item in l.borrow().iter().rev() {
-                    res.extend(Self::compile(item)?);
+impl Chunk {
+    /// Lists the chunk's instructions, one per line.
+    pub fn disassemble(&self) -> String {
+        let mut res = String::new();
+        for (ip, op) in self.code.iter().enumerate() {
+            let _ = writeln!(res, "{:4} {}", ip, self.describe(op));
+        }
+        res
+    }
+
+    fn describe(&self, op: &OpCode) -> String {
+        match *op {
+            OpCode::PushConst(i) => format!("PUSH_CONST {}", self.constants[i]),
+            OpCode::LoadParam { depth, slot } => format!("LOAD_PARAM {} {}", depth, slot),
+            OpCode::LoadLocal { depth, slot, bound } => {
+                format!("LOAD_LOCAL {} {} {}", depth, slot, bound)
+            }
+            OpCode::GetGlobal(symbol) => format!("GET_GLOBAL {}", resolve_intern(symbol)),
+            OpCode::CheckShadow(name) => format!("CHECK_SHADOW {}", name),
+            OpCode::Store {
+                name,
+                slot: Some(slot),
+            } => format!("STORE_LOCAL {} {}", slot, name),
+            OpCode::Store { name, slot: None } => format!("STORE_GLOBAL {}", name),
+            OpCode::Pop => "POP".to_string(),
+            OpCode::Jump(to) => format!("JUMP {}", to),
+            OpCode::JumpUnless(to) => format!("JUMP_UNLESS {}", to),
+            OpCode::MakeClosure(i) => {
+                let proto = &self.templates[i].proto;
+                format!("MAKE_CLOSURE ({}) {}", proto.params.join(" "), proto.body)
+            }
+            OpCode::Interpret { expr, .. } => format!("INTERPRET {}", self.constants[expr]),
+            OpCode::Nest(Some(i)) => format!("NEST {}", self.constants[i]),
+            OpCode::Nest(None) => "NEST".to_string(),
+            OpCode::Unnest => "UNNEST".to_string(),
+            OpCode::CheckCallee { after, .. } => format!("CHECK_CALLEE {}", after),
+            OpCode::Call { argc, primitive } => describe_call("CALL", argc, primitive),
+            OpCode::TailCall { argc, primitive } => describe_call("TAIL_CALL", argc, primitive),
+            OpCode::Return => "RETURN".to_string(),
+        }
+    }
+
+    /// Lowers OP, leaving its value on the stack or, if it is in TAIL
+    /// position, returning it.
+    fn lower(&mut self, op: &Op, tail: bool) {
+        match op {
+            Op::Const(v) => {
+                let i = self.constant(v.clone());
+                self.code.push(OpCode::PushConst(i));
+            }
+            Op::Param { depth, slot } => self.code.push(OpCode::LoadParam {
+                depth: *depth,
+                slot: *slot,
+            }),
+            Op::Local { depth, slot, outer } => {
+                let at = self.code.len();
+                self.code.push(OpCode::LoadLocal {
+                    depth: *depth,
+                    slot: *slot,
+                    bound: 0,
+                });
+                self.lower(outer, false);
+                let here = self.code.len();
+                if let OpCode::LoadLocal { ref mut bound, .. } = self.code[at] {
+                    *bound = here;
+                }
+            }
+            Op::Global(symbol) => self.code.push(OpCode::GetGlobal(*symbol)),
+            Op::If(cond, then, els) => {
+                self.lower(cond, false);
+                let branch = self.code.len();
+                self.code.push(OpCode::JumpUnless(0));
+                self.lower(then, tail);
+                // A branch in tail position returns so there is
+                // nothing to jump over.
+                let jump = self.code.len();
+                if !tail {
+                    self.code.push(OpCode::Jump(0));
+                }
+                self.code[branch] = OpCode::JumpUnless(self.code.len());
+                self.lower(els, tail);
+                if !tail {
+                    self.code[jump] = OpCode::Jump(self.code.len());
+                }
+                return;
+            }
+            Op::Do(ops) => {
+                let (last, init) = ops.split_last().unwrap();
+                for op in init {
+                    self.lower(op, false);
+                    self.code.push(OpCode::Pop);
+                }
+                // The last operation returns if it is in tail
+                // position.
+                self.lower(last, tail);
+                return;
+            }
+            Op::Let { name, slot, value } => {
+                self.code.push(OpCode::CheckShadow(name));
+                self.lower(value, false);
+                self.code.push(OpCode::Store { name, slot: *slot });
+            }
+            Op::Fn(template) => {
+                self.templates.push(template.clone());
+                self.code
+                    .push(OpCode::MakeClosure(self.templates.len() - 1));
+            }
+            Op::Call {
+                func,
+                args,
+                source,
+                named,
+                primitive,
+            } => {
+                let source = self.constant(source.clone());
+                if !tail {
+                    self.code
+                        .push(OpCode::Nest(if *named { Some(source) } else { None }));
+                }
+                self.lower(func, false);
+                let callee = self.code.len();
+                self.code.push(OpCode::CheckCallee { source, after: 0 });
+                for arg in args {
+                    self.lower(arg, false);
                 }
-                res.push(Inst::Call);
-                return Ok(res);
+                let argc = args.len();
+                self.code.push(if tail {
+                    OpCode::TailCall {
+                        argc,
+                        primitive: *primitive,
+                    }
+                } else {
+                    OpCode::Call {
+                        argc,
+                        primitive: *primitive,
+                    }
+                });
+                let after = self.code.len();
+                if let OpCode::CheckCallee {
+                    after: ref mut a, ..
+                } = self.code[callee]
+                {
+                    *a = after;
+                }
+                if !tail {
+                    self.code.push(OpCode::Unnest);
+                }
+            }
+            Op::Dynamic(expr) => {
+                let expr = self.constant(expr.clone());
+                self.code.push(OpCode::Interpret { expr, tail });
             }
-            LustData::Number(_) => Ok(vec![Inst::PushConst(data.clone())]),
-            LustData::Char(_) => Ok(vec![Inst::PushConst(data.clone())]),
-            LustData::Builtin(_) => Ok(vec![Inst::PushConst(data.clone())]),
-            LustData::Fn(_) => Ok(vec![Inst::PushConst(data.clone())]),
-            LustData::Mac(_) => Ok(vec![Inst::PushConst(data.clone())]),
         }
+        if tail {
+            self.code.push(OpCode::Return);
+        }
+    }
+
+    fn constant(&mut self, value: LustData) -> usize {
+        self.constants.push(value);
+        self.constants.len() - 1
     }
 }
 
-impl fmt::Display for Inst {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Inst::PushConst(d) => {
-                write!(f, "PUSH_CONST {}", d)
-            }
-            Inst::StoreLocal(s) => {
-                write!(f, "STORE_LOCAL {}", s)
+fn describe_call(name: &str, argc: usize, primitive: Option<&'static Primitive>) -> String {
+    match primitive {
+        Some(p) => format!("{} {} {}", name, argc, p.name),
+        None => format!("{} {}", name, argc),
+    }
+}
+
+/// A call being run by the machine.
+struct CallFrame {
+    chunk: Rc<Chunk>,
+    ip: usize,
+    /// The frame holding the call's bindings or None at the top level.
+    frame: Option<Rc<Frame>>,
+    globals: Rc<RefCell<LustEnv>>,
+}
+
+struct Machine {
+    stack: Vec<LustData>,
+    calls: Vec<CallFrame>,
+    /// For each level of evaluation entered with `Nest` and not yet
+    /// left, whether it recorded a call.
+    nested: Vec<bool>,
+}
+
+impl Machine {
+    fn new(chunk: &Rc<Chunk>, frame: Option<Rc<Frame>>, globals: &Rc<RefCell<LustEnv>>) -> Self {
+        Machine {
+            stack: Vec::new(),
+            calls: vec![CallFrame {
+                chunk: chunk.clone(),
+                ip: 0,
+                frame,
+                globals: globals.clone(),
+            }],
+            nested: Vec::new(),
+        }
+    }
+
+    /// Runs until the first call returns. On errors the levels of
+    /// evaluation the machine entered are left as `Interpreter::nested`
+    /// would have left them.
+    fn run(&mut self) -> Result<LustData, String> {
+        let res = self.run_loop();
+        if res.is_err() {
+            while let Some(is_call) = self.nested.pop() {
+                Interpreter::leave_nested(is_call, true);
             }
-            Inst::GetSym(s) => {
-                write!(f, "GET_LOCAL {}", s)
+        }
+        res
+    }
+
+    fn run_loop(&mut self) -> Result<LustData, String> {
+        loop {
+            let call = self.calls.last_mut().unwrap();
+            let op = call.chunk.code[call.ip];
+            call.ip += 1;
+            match op {
+                OpCode::PushConst(i) => {
+                    let value = call.chunk.constants[i].clone();
+                    self.stack.push(value);
+                }
+                OpCode::LoadParam { depth, slot } => {
+                    let value = compile::load(call.frame.as_ref(), depth, slot);
+                    self.stack.push(value.unwrap_or_default());
+                }
+                OpCode::LoadLocal { depth, slot, bound } => {
+                    if let Some(value) = compile::load(call.frame.as_ref(), depth, slot) {
+                        self.stack.push(value);
+                        call.ip = bound;
+                    }
+                }
+                OpCode::GetGlobal(symbol) => {
+                    let value = call.globals.borrow().resolve_symbol(symbol)?;
+                    self.stack.push(value);
+                }
+                OpCode::CheckShadow(name) => Interpreter::check_shadow(name, &call.globals)?,
+                OpCode::Store { name, slot } => {
+                    let value = self.stack.pop().unwrap();
+                    let value =
+                        compile::bind(name, slot, value, call.frame.as_ref(), &call.globals);
+                    self.stack.push(value);
+                }
+                OpCode::Pop => {
+                    self.stack.pop();
+                }
+                OpCode::Jump(to) => call.ip = to,
+                OpCode::JumpUnless(to) => {
+                    if !truthy(&self.stack.pop().unwrap()) {
+                        call.ip = to;
+                    }
+                }
+                OpCode::MakeClosure(i) => {
+                    let func = compile::closure(&call.chunk.templates[i], call.frame.as_ref());
+                    self.stack.push(func);
+                }
+                OpCode::Interpret { expr, tail } => {
+                    let expr = &call.chunk.constants[expr];
+                    let env = Self::env(call);
+                    // The interpreter evaluates expressions in tail
+                    // position without nesting.
+                    let value = if tail {
+                        Interpreter::eval_here(expr, env)?
+                    } else {
+                        Interpreter::eval_in_env(expr, env)?
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::Nest(source) => {
+                    let source = source.map(|i| &call.chunk.constants[i]);
+                    self.nested.push(source.is_some());
+                    Interpreter::enter_nested(source)?;
+                }
+                OpCode::Unnest => {
+                    let is_call = self.nested.pop().unwrap();
+                    Interpreter::leave_nested(is_call, false);
+                }
+                OpCode::CheckCallee { source, after } => {
+                    if compile::needs_interpreter(self.stack.last().unwrap()) {
+                        self.stack.pop();
+                        let source = &call.chunk.constants[source];
+                        // Calls not in tail position have already
+                        // been nested by `Nest`.
+                        let value = Interpreter::eval_here(source, Self::env(call))?;
+                        self.stack.push(value);
+                        call.ip = after;
+                    }
+                }
+                OpCode::Call { argc, primitive } => self.call(argc, primitive, false)?,
+                OpCode::TailCall { argc, primitive } => self.call(argc, primitive, true)?,
+                OpCode::Return => {
+                    self.calls.pop();
+                    if self.calls.is_empty() {
+                        return Ok(self.stack.pop().unwrap());
+                    }
+                }
             }
-            Inst::Puts => {
-                write!(f, "PUTS")
+        }
+    }
+
+    /// Calls the function below ARGC arguments on the stack. Functions
+    /// run by the machine get a new call, replacing the running one if
+    /// the call is in TAIL position. Anything else is called and its
+    /// result pushed.
+    fn call(
+        &mut self,
+        argc: usize,
+        primitive: Option<&'static Primitive>,
+        tail: bool,
+    ) -> Result<(), String> {
+        let at = self.stack.len() - argc;
+        if let Some(p) = compile::primitive_of(primitive, &self.stack[at - 1]) {
+            let value = p.call(&self.stack[at..])?;
+            self.stack.truncate(at - 1);
+            self.stack.push(value);
+            return Ok(());
+        }
+        let args: Vec<LustData> = self.stack.drain(at..).collect();
+        let callee = self.stack.pop().unwrap();
+        let value = match callee {
+            LustData::Fn(ref f) => {
+                let args = f.keyword_args(args)?;
+                f.check_arity(args.len())?;
+                match f.compiled {
+                    Some(ref c) => match c.template.chunk(&f.env) {
+                        Some(chunk) => return self.enter(f, c, chunk, args, tail),
+                        None => compile::call(f, c, args)?,
+                    },
+                    None => compile::interpret(f, f.env.clone(), args)?,
+                }
             }
-            Inst::Exit => {
-                write!(f, "EXIT")
+            LustData::Builtin(b) => {
+                let globals = &self.calls.last().unwrap().globals;
+                compile::call_builtin(b, args, globals)?
             }
-            Inst::Call => {
-                write!(f, "CALL")
+            LustData::Memo(ref m) => m.call(args, &self.calls.last().unwrap().globals)?,
+            LustData::NativeFn(ref f) => {
+                compile::call_native(f, &args, &self.calls.last().unwrap().globals)?
             }
+            _ => return Err(format!("invalid list predicate: {}", callee)),
+        };
+        self.stack.push(value);
+        Ok(())
+    }
+
+    /// Starts a call to FUNC, whose body is CHUNK, with ARGS.
+    fn enter(
+        &mut self,
+        func: &LustFn,
+        compiled: &CompiledFn,
+        chunk: &Rc<Chunk>,
+        args: Vec<LustData>,
+        tail: bool,
+    ) -> Result<(), String> {
+        if Interpreter::timed_out() {
+            return Err("evaluation timeout".to_string());
+        }
+        let scope = chunk.scope.as_ref().expect("function body without a scope");
+        let call = CallFrame {
+            chunk: chunk.clone(),
+            ip: 0,
+            frame: Some(compile::new_frame(func, compiled, scope, args)),
+            globals: func.env.clone(),
+        };
+        if tail {
+            *self.calls.last_mut().unwrap() = call;
+        } else {
+            self.calls.push(call);
+        }
+        Ok(())
+    }
+
+    /// Gets an enviroment for the interpreter to evaluate code from
+    /// CALL in.
+    fn env(call: &CallFrame) -> Rc<RefCell<LustEnv>> {
+        match call.frame {
+            Some(ref frame) => compile::materialize(Some(frame), &call.globals),
+            None => call.globals.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::chunk_of;
+    use crate::interpreter::Interpreter;
+    use crate::parser::Parser;
+
+    /// Evaluates each expression in SOURCE and shows the value of the
+    /// last one. Expressions are run on the virtual machine if
+    /// BYTECODE is set.
+    fn eval(evaluator: &mut Interpreter, source: &str, bytecode: bool) -> Result<String, String> {
+        evaluator.use_bytecode(bytecode);
+        let mut parser = Parser::new(source);
+        let mut res = String::new();
+        while parser.has_more() {
+            let expr = parser.parse_expr().expr.unwrap();
+            res = evaluator.eval_to_data(&expr)?.to_string();
         }
+        Ok(res)
+    }
+
+    #[test]
+    fn same_as_interpreter() {
+        let sources = [
+            "(let adder (fn (n) (fn (x) (add x n)))) ((adder 2) 3)",
+            "(let f (fn (a & rest) (cons a rest))) (cons (f 1) (f 1 2 3))",
+            "(let x 1)
+             (let f (fn (c) (if c (let x 2) x)))
+             (cons (f ()) (cons (f 1) (cons x ())))",
+            "(let f (fn (n) (if (lt n 1) 'small (if (lt n 10) 'medium 'large))))
+             (cons (f 0) (cons (f 5) (cons (f 50) ())))",
+            "(let f (fn (x) (eval 'x))) (f 5)",
+            "(let unless (macro (c body) `(if ,c () ,body))) (let f (fn (x) (unless x 7))) (f ())",
+            "(let f (fn (add) (add 1 2))) (f mul)",
+            "(let loop (fn (n) (if (eq n 0) 'done (loop (sub n 1))))) (loop 10000)",
+            "(let fib (fn (n) (if (lt n 2) n (add (fib (sub n 1)) (fib (sub n 2)))))) (fib 15)",
+            "(let f (fn (x) x)) (f 1 2)",
+            "(let f (fn (n) (add n undefined))) (f 1)",
+            "(let f (fn (n) (println (add n 'a)))) (f 1)",
+            "(let f (fn (n) (car (undefined n)))) (f 1)",
+        ];
+        for source in sources {
+            let expected = eval(&mut Interpreter::new(), source, false);
+            let res = eval(&mut Interpreter::new(), source, true);
+            assert_eq!(res, expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn disassemble() {
+        let mut evaluator = Interpreter::new();
+        let expr = Parser::new("(let double (fn (x) (add x x)))")
+            .parse_expr()
+            .expr
+            .unwrap();
+        evaluator.use_bytecode(true);
+        let compiled = evaluator.compile(&expr).unwrap();
+        assert_eq!(
+            compiled.chunk().unwrap().disassemble(),
+            "   0 CHECK_SHADOW double
+   1 MAKE_CLOSURE (x) (add x x)
+   2 STORE_GLOBAL double
+   3 RETURN
+"
+        );
+
+        let double = compiled.run(&mut evaluator).unwrap();
+        assert_eq!(
+            chunk_of(&double).unwrap().disassemble(),
+            "   0 GET_GLOBAL add
+   1 CHECK_CALLEE 5
+   2 LOAD_PARAM 0 0
+   3 LOAD_PARAM 0 0
+   4 TAIL_CALL 2 add
+   5 RETURN
+"
+        );
+
+        eval(
+            &mut evaluator,
+            "(let f (fn (n) (if (lt n 2) n (add 1 (f (sub n 1))))))",
+            true,
+        )
+        .unwrap();
+        let f = evaluator.global_env.borrow().resolve("f").unwrap();
+        assert_eq!(
+            chunk_of(&f).unwrap().disassemble(),
+            "   0 NEST (lt n 2)
+   1 GET_GLOBAL lt
+   2 CHECK_CALLEE 6
+   3 LOAD_PARAM 0 0
+   4 PUSH_CONST 2
+   5 CALL 2 lt
+   6 UNNEST
+   7 JUMP_UNLESS 10
+   8 LOAD_PARAM 0 0
+   9 RETURN
+  10 GET_GLOBAL add
+  11 CHECK_CALLEE 26
+  12 PUSH_CONST 1
+  13 NEST (f (sub n 1))
+  14 GET_GLOBAL f
+  15 CHECK_CALLEE 24
+  16 NEST (sub n 1)
+  17 GET_GLOBAL sub
+  18 CHECK_CALLEE 22
+  19 LOAD_PARAM 0 0
+  20 PUSH_CONST 1
+  21 CALL 2 sub
+  22 UNNEST
+  23 CALL 1
+  24 UNNEST
+  25 TAIL_CALL 2 add
+  26 RETURN
+"
+        );
+    }
+
+    #[test]
+    fn interpreted_functions() {
+        // Functions the interpreter made have no bytecode but can be
+        // called from functions that do.
+        let mut evaluator = Interpreter::new();
+        eval(&mut evaluator, "(let g (fn (x) (mul x 2)))", false).unwrap();
+        let g = evaluator.global_env.borrow().resolve("g").unwrap();
+        assert!(chunk_of(&g).is_none());
+        assert_eq!(
+            eval(&mut evaluator, "(let f (fn (x) (add (g x) 1))) (f 4)", true),
+            Ok("9".to_string())
+        );
     }
 }
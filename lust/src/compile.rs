@@ -0,0 +1,936 @@
+/// Compiles expressions to a tree of operations that can be run
+/// without walking the expression's data. Macros are expanded and
+/// `quote`, `if`, `let`, and `fn` are resolved once, when the code is
+/// compiled, instead of each time it runs.
+///
+/// The parameters of compiled functions and the names bound with
+/// `let` in their bodies live in a frame, a vector indexed by a slot
+/// found at compile time, rather than in an enviroment that is
+/// searched by name. Everything else is looked up in the global
+/// enviroment when it is used so that redefinitions are seen.
+///
+/// Anything the compiler doesn't understand, like calls to `eval` or
+/// to builtins that need the calling enviroment, is left to the
+/// interpreter. The interpreter is given an enviroment holding a copy
+/// of the frames' bindings. A function body that could bind names in
+/// that enviroment is interpreted as a whole instead.
+///
+/// The operations can either be run directly or lowered to bytecode
+/// for the virtual machine in the `bytecode` module.
+use std::cell::{OnceCell, RefCell};
+use std::rc::Rc;
+
+use crate::builtins::{make_fn, name_fn, primitive, truthy, Primitive, BODY};
+use crate::bytecode::{self, Chunk};
+use crate::intern::intern;
+use crate::interpreter::{BuiltinFn, CallResult, Interpreter, LustData, LustEnv, LustFn, NativeFn};
+use crate::location::Location;
+
+/// Builtins that evaluate each of their arguments and use the
+/// enviroment for nothing else. Compiled code calls them with the
+/// values of their arguments quoted.
+const STRICT_BUILTINS: &[&str] = &[
+    "car",
+    "cdr",
+    "cons",
+    "error",
+    "println",
+    "pretty-print",
+    "print",
+    "negate",
+    "add",
+    "sub",
+    "mul",
+    "div",
+    "mod",
+    "nan?",
+    "inf?",
+    "lt",
+    "gt",
+    "eq",
+    "force",
+    "open-input-string",
+    "open-output-string",
+    "get-output-string",
+    "read-char",
+    "read-line",
+    "read",
+    "write",
+    "display",
+    "from-json",
+    "to-json",
+    "clock",
+    "now",
+    "random",
+    "random-int",
+    "random-seed",
+    "exit",
+    "getenv",
+    "shell",
+    "ffi-open",
+    "ffi-sym",
+    "ffi-call",
+];
+
+/// A top level expression compiled by `Interpreter::compile`.
+pub struct CompiledExpr {
+    op: Op,
+    loc: Location,
+    /// The bytecode for the expression if it is run by the virtual
+    /// machine.
+    chunk: Option<Rc<Chunk>>,
+}
+
+pub(crate) enum Op {
+    /// A value known when the code was compiled.
+    Const(LustData),
+    /// A parameter of the function DEPTH functions out from the one
+    /// running.
+    Param {
+        depth: usize,
+        slot: usize,
+    },
+    /// A name bound with `let` in the body of the function DEPTH
+    /// functions out. Until the `let` has run the name means what
+    /// OUTER does.
+    Local {
+        depth: usize,
+        slot: usize,
+        outer: Box<Op>,
+    },
+    /// A name looked up in the global enviroment by its interned id.
+    Global(usize),
+    If(Box<Op>, Box<Op>, Box<Op>),
+    /// Runs each operation in order. The value is that of the last,
+    /// which is in tail position. Never empty.
+    Do(Vec<Op>),
+    /// Binds NAME in the running function's frame at SLOT or, outside
+    /// of functions, in the global enviroment.
+    Let {
+        name: &'static str,
+        slot: Option<usize>,
+        value: Box<Op>,
+    },
+    Fn(Rc<Template>),
+    /// A call. SOURCE is the expression the call was compiled from.
+    /// It is shown in stack traces and handed to the interpreter if
+    /// FUNC turns out to be something compiled code can't call. If
+    /// FUNC named a builtin with a primitive when the call was
+    /// compiled and still does, the primitive is called directly.
+    Call {
+        func: Box<Op>,
+        args: Vec<Op>,
+        source: LustData,
+        named: bool,
+        primitive: Option<&'static Primitive>,
+    },
+    /// An expression left to the interpreter.
+    Dynamic(LustData),
+}
+
+/// The names in a function's frame. Parameters come first, followed
+/// by the names bound with `let` in the function's body.
+pub(crate) struct Scope {
+    names: RefCell<Vec<&'static str>>,
+    params: usize,
+    parent: Option<Rc<Scope>>,
+}
+
+/// A `fn` expression. Every function it creates shares the template
+/// and the body is compiled when the first of them is called, by
+/// which point any macros it uses have been defined.
+pub(crate) struct Template {
+    /// The function as the interpreter would have created it.
+    pub(crate) proto: LustFn,
+    /// The scope the function was created in.
+    scope: Option<Rc<Scope>>,
+    /// The compiled body or None if it has to be interpreted.
+    body: OnceCell<Option<Body>>,
+    /// Set if the template's functions are run by the virtual machine.
+    bytecode: bool,
+    /// The body lowered to bytecode, made when it is first needed.
+    chunk: OnceCell<Option<Rc<Chunk>>>,
+}
+
+struct Body {
+    op: Op,
+    scope: Rc<Scope>,
+}
+
+/// The bindings of a call to a compiled function.
+pub(crate) struct Frame {
+    slots: RefCell<Vec<Option<LustData>>>,
+    scope: Rc<Scope>,
+    parent: Option<Rc<Frame>>,
+}
+
+/// What makes a function compiled. Functions created by compiled code
+/// hold the template they were created from and the frame they were
+/// created in.
+pub(crate) struct CompiledFn {
+    pub(crate) template: Rc<Template>,
+    pub(crate) frame: Option<Rc<Frame>>,
+}
+
+/// The result of running an operation in tail position.
+enum Flow {
+    Value(LustData),
+    /// A call left for the caller to make so that tail calls don't
+    /// grow the stack.
+    TailCall(LustData, Vec<LustData>),
+}
+
+/// Compiles DATA, a top level expression located at LOC, to be run
+/// in GLOBALS. If BYTECODE is set the expression and the functions it
+/// creates are run by the virtual machine.
+pub(crate) fn compile(
+    data: &LustData,
+    globals: &Rc<RefCell<LustEnv>>,
+    loc: Location,
+    bytecode: bool,
+) -> CompiledExpr {
+    let compiler = Compiler {
+        globals,
+        scope: None,
+        bytecode,
+    };
+    let op = compiler
+        .compile(data)
+        .unwrap_or_else(|_| Op::Dynamic(data.clone()));
+    let chunk = if bytecode {
+        Some(Rc::new(bytecode::lower(&op, None)))
+    } else {
+        None
+    };
+    CompiledExpr { op, loc, chunk }
+}
+
+/// Calls FUNC, which was created by compiled code, with ARGS.
+pub(crate) fn call(
+    func: &LustFn,
+    compiled: &CompiledFn,
+    args: Vec<LustData>,
+) -> Result<LustData, String> {
+    let mut globals = func.env.clone();
+    let mut flow = enter(func, compiled, args)?;
+    loop {
+        let (callee, args) = match flow {
+            Flow::Value(v) => return Ok(v),
+            Flow::TailCall(callee, args) => (callee, args),
+        };
+        if Interpreter::timed_out() {
+            return Err("evaluation timeout".to_string());
+        }
+        flow = match callee {
+            LustData::Fn(ref f) => {
+                let args = f.keyword_args(args)?;
+                f.check_arity(args.len())?;
+                globals = f.env.clone();
+                match f.compiled {
+                    Some(ref c) => enter(f, c, args)?,
+                    None => Flow::Value(interpret(f, f.env.clone(), args)?),
+                }
+            }
+            LustData::Builtin(b) => Flow::Value(call_builtin(b, args, &globals)?),
+            LustData::Memo(ref m) => Flow::Value(m.call(args, &globals)?),
+            LustData::NativeFn(ref f) => Flow::Value(call_native(f, &args, &globals)?),
+            _ => return Err(format!("invalid list predicate: {}", callee)),
+        };
+    }
+}
+
+/// Calls CALLEE with ARGS. Builtins are called in GLOBALS.
+pub(crate) fn apply(
+    callee: LustData,
+    args: Vec<LustData>,
+    globals: &Rc<RefCell<LustEnv>>,
+) -> Result<LustData, String> {
+    match callee {
+        LustData::Fn(ref f) => {
+            let args = f.keyword_args(args)?;
+            f.check_arity(args.len())?;
+            match f.compiled {
+                Some(ref c) => call(f, c, args),
+                None => interpret(f, f.env.clone(), args),
+            }
+        }
+        LustData::Builtin(b) => call_builtin(b, args, globals),
+        LustData::Memo(ref m) => m.call(args, globals),
+        LustData::NativeFn(ref f) => call_native(f, &args, globals),
+        _ => Err(format!("invalid list predicate: {}", callee)),
+    }
+}
+
+/// Starts a call to FUNC. The call is finished if the body ends with
+/// something other than a call.
+fn enter(func: &LustFn, compiled: &CompiledFn, args: Vec<LustData>) -> Result<Flow, String> {
+    if let Some(chunk) = compiled.template.chunk(&func.env) {
+        return Ok(Flow::Value(bytecode::call(func, compiled, chunk, args)?));
+    }
+    let body = match compiled.template.body(&func.env) {
+        Some(body) => body,
+        None => {
+            let env = materialize(compiled.frame.as_ref(), &func.env);
+            return Ok(Flow::Value(interpret(func, env, args)?));
+        }
+    };
+    let frame = new_frame(func, compiled, &body.scope, args);
+    Run {
+        frame: Some(&frame),
+        globals: &func.env,
+    }
+    .run_tail(&body.op)
+}
+
+/// Makes the frame for a call to FUNC with ARGS. SCOPE is the scope
+/// of FUNC's body.
+pub(crate) fn new_frame(
+    func: &LustFn,
+    compiled: &CompiledFn,
+    scope: &Rc<Scope>,
+    args: Vec<LustData>,
+) -> Rc<Frame> {
+    let mut slots = vec![None; scope.names.borrow().len()];
+    let mut args = args.into_iter();
+    // Parameters take the first slots. The last one of a varadic
+    // function gets the remaining arguments.
+    for (slot, value) in slots.iter_mut().take(scope.params).enumerate() {
+        *value = if func.is_varadic() && slot == scope.params - 1 {
+            Some(LustData::list(args.by_ref().collect()))
+        } else {
+            args.next()
+        };
+    }
+    Rc::new(Frame {
+        slots: RefCell::new(slots),
+        scope: scope.clone(),
+        parent: compiled.frame.clone(),
+    })
+}
+
+/// Calls FUNC with ARGS by binding them in a child of ENV and
+/// evaluating its body there.
+pub(crate) fn interpret(
+    func: &LustFn,
+    env: Rc<RefCell<LustEnv>>,
+    args: Vec<LustData>,
+) -> Result<LustData, String> {
+    let fnenv = LustEnv::new_child(env);
+    let mut args = args.into_iter();
+    let mut params = func.params.iter().zip(&func.param_ids);
+    while let Some((param, id)) = params.next() {
+        if param == "&" {
+            if let Some((_, rest)) = params.next() {
+                let rest_args = LustData::list(args.collect());
+                fnenv.borrow_mut().insert_symbol(*rest, rest_args);
+            }
+            break;
+        }
+        let arg = args.next().unwrap_or_default();
+        fnenv.borrow_mut().insert_symbol(*id, arg);
+    }
+    Interpreter::eval_in_env(&func.body, fnenv)
+}
+
+/// Calls the builtin FUNC with ARGS, which have already been
+/// evaluated.
+pub(crate) fn call_builtin(
+    func: BuiltinFn,
+    args: Vec<LustData>,
+    globals: &Rc<RefCell<LustEnv>>,
+) -> Result<LustData, String> {
+    let args = LustData::list(args.into_iter().map(quote).collect());
+    match (func.func)(args.as_list()?, globals.clone())? {
+        CallResult::Ret(v) => Ok(v),
+        CallResult::Call(env, expr) => Interpreter::eval_in_env(&expr, env),
+    }
+}
+
+/// Calls the native function FUNC with ARGS in GLOBALS.
+pub(crate) fn call_native(
+    func: &Rc<NativeFn>,
+    args: &[LustData],
+    globals: &Rc<RefCell<LustEnv>>,
+) -> Result<LustData, String> {
+    match func(args, globals.clone())? {
+        CallResult::Ret(v) => Ok(v),
+        CallResult::Call(env, expr) => Interpreter::eval_in_env(&expr, env),
+    }
+}
+
+/// Makes an expression that evaluates to VALUE.
+fn quote(value: LustData) -> LustData {
+    match value {
+        LustData::Symbol(_) => LustData::list(vec![LustData::symbol("quote"), value]),
+        LustData::Cons(_) if !value.is_empty_list() => {
+            LustData::list(vec![LustData::symbol("quote"), value])
+        }
+        _ => value,
+    }
+}
+
+/// Builds an enviroment in which the names bound in FRAME and the
+/// frames it was created in mean what they do in compiled code.
+pub(crate) fn materialize(
+    frame: Option<&Rc<Frame>>,
+    globals: &Rc<RefCell<LustEnv>>,
+) -> Rc<RefCell<LustEnv>> {
+    let mut frames = Vec::new();
+    let mut next = frame;
+    while let Some(f) = next {
+        frames.push(f);
+        next = f.parent.as_ref();
+    }
+    let env = LustEnv::new_child(globals.clone());
+    // Outermost first so that inner bindings replace outer ones.
+    for f in frames.iter().rev() {
+        let names = f.scope.names.borrow();
+        for (name, slot) in names.iter().zip(f.slots.borrow().iter()) {
+            if let Some(v) = slot {
+                env.borrow_mut().insert(name.to_string(), v.clone());
+            }
+        }
+    }
+    env
+}
+
+/// Gets the value in SLOT of the frame DEPTH frames out from FRAME.
+pub(crate) fn load(frame: Option<&Rc<Frame>>, depth: usize, slot: usize) -> Option<LustData> {
+    let mut frame = frame?;
+    for _ in 0..depth {
+        frame = frame.parent.as_ref()?;
+    }
+    let slots = frame.slots.borrow();
+    slots[slot].clone()
+}
+
+/// Binds NAME to VALUE, as `let` does in compiled code, in SLOT of
+/// FRAME or, outside of functions, in GLOBALS.
+pub(crate) fn bind(
+    name: &str,
+    slot: Option<usize>,
+    value: LustData,
+    frame: Option<&Rc<Frame>>,
+    globals: &Rc<RefCell<LustEnv>>,
+) -> LustData {
+    let mut value = value;
+    name_fn(name, &mut value);
+    match (slot, frame) {
+        (Some(slot), Some(frame)) => {
+            frame.slots.borrow_mut()[slot] = Some(value.clone());
+        }
+        _ => {
+            globals.borrow_mut().insert(name.to_string(), value.clone());
+        }
+    }
+    value
+}
+
+/// Makes a function from TEMPLATE that was created in FRAME.
+pub(crate) fn closure(template: &Rc<Template>, frame: Option<&Rc<Frame>>) -> LustData {
+    let mut func = template.proto.clone();
+    func.defined_at = Interpreter::current_location();
+    func.compiled = Some(Rc::new(CompiledFn {
+        template: template.clone(),
+        frame: frame.cloned(),
+    }));
+    LustData::Fn(Rc::new(func))
+}
+
+/// Gets PRIMITIVE if CALLEE is the builtin it implements.
+pub(crate) fn primitive_of(
+    primitive: Option<&'static Primitive>,
+    callee: &LustData,
+) -> Option<&'static Primitive> {
+    match (primitive, callee) {
+        (Some(p), LustData::Builtin(b)) if b.name == p.name => Some(p),
+        _ => None,
+    }
+}
+
+/// Determines if FUNC has to be called by the interpreter with the
+/// unevaluated arguments.
+pub(crate) fn needs_interpreter(func: &LustData) -> bool {
+    match func {
+        LustData::Builtin(b) => !STRICT_BUILTINS.contains(&b.name),
+        LustData::Mac(_) => true,
+        _ => false,
+    }
+}
+
+impl CompiledExpr {
+    /// Runs the compiled expression in EVALUATOR's global enviroment.
+    pub fn run(&self, evaluator: &mut Interpreter) -> Result<LustData, String> {
+        let loc = self.loc.clone();
+        evaluator.enter(&loc, |me| self.run_entered(&me.global_env))
+    }
+
+    /// Runs the compiled expression in GLOBALS. The interpreter's
+    /// context must already be in place.
+    pub(crate) fn run_entered(&self, globals: &Rc<RefCell<LustEnv>>) -> Result<LustData, String> {
+        if let Some(ref chunk) = self.chunk {
+            return bytecode::run(chunk, globals);
+        }
+        Run {
+            frame: None,
+            globals,
+        }
+        .run(&self.op)
+    }
+
+    /// Gets the bytecode the expression is run as, if it is run by
+    /// the virtual machine.
+    pub fn chunk(&self) -> Option<&Chunk> {
+        self.chunk.as_deref()
+    }
+}
+
+impl Template {
+    /// Gets the compiled body of the template's functions, compiling
+    /// it the first time.
+    fn body(&self, globals: &Rc<RefCell<LustEnv>>) -> Option<&Body> {
+        self.body
+            .get_or_init(|| {
+                let names: Vec<&'static str> = self
+                    .proto
+                    .params
+                    .iter()
+                    .filter(|p| *p != "&")
+                    .map(|p| LustData::symbol(p).expect_symbol().unwrap())
+                    .collect();
+                let scope = Rc::new(Scope {
+                    params: names.len(),
+                    names: RefCell::new(names),
+                    parent: self.scope.clone(),
+                });
+                let compiler = Compiler {
+                    globals,
+                    scope: Some(scope.clone()),
+                    bytecode: self.bytecode,
+                };
+                compiler
+                    .compile(&self.proto.body)
+                    .ok()
+                    .map(|op| Body { op, scope })
+            })
+            .as_ref()
+    }
+
+    /// Gets the body of the template's functions as bytecode. None if
+    /// they aren't run by the virtual machine or have to be
+    /// interpreted.
+    pub(crate) fn chunk(&self, globals: &Rc<RefCell<LustEnv>>) -> Option<&Rc<Chunk>> {
+        if !self.bytecode {
+            return None;
+        }
+        self.chunk
+            .get_or_init(|| {
+                let body = self.body(globals)?;
+                Some(Rc::new(bytecode::lower(&body.op, Some(body.scope.clone()))))
+            })
+            .as_ref()
+    }
+}
+
+struct Compiler<'a> {
+    globals: &'a Rc<RefCell<LustEnv>>,
+    /// The scope of the function body being compiled or None at the
+    /// top level.
+    scope: Option<Rc<Scope>>,
+    /// Set if the code is being compiled for the virtual machine.
+    bytecode: bool,
+}
+
+impl Compiler<'_> {
+    /// Compiles EXPR. Errors if EXPR is in a function body and can't
+    /// be compiled. At the top level what can't be compiled is left
+    /// to the interpreter.
+    fn compile(&self, expr: &LustData) -> Result<Op, String> {
+        match Interpreter::nested(None, || self.compile_expr(expr)) {
+            Err(_) if self.scope.is_none() => Ok(Op::Dynamic(expr.clone())),
+            res => res,
+        }
+    }
+
+    fn compile_expr(&self, expr: &LustData) -> Result<Op, String> {
+        let items = match expr {
+            LustData::Symbol(_) => return Ok(self.lookup(expr.expect_symbol()?)),
+            LustData::Cons(_) if !expr.is_empty_list() => expr.clone().into_list()?,
+            _ => return Ok(Op::Const(expr.clone())),
+        };
+        let name = match items[0] {
+            LustData::Symbol(_) => items[0].expect_symbol()?,
+            LustData::Mac(_) => return self.dynamic(expr),
+            LustData::Builtin(b) if b == BODY && items.len() > 1 => {
+                return Ok(Op::Do(
+                    items[1..]
+                        .iter()
+                        .map(|item| self.compile(item))
+                        .collect::<Result<_, _>>()?,
+                ))
+            }
+            _ => return self.call(expr, &items, None),
+        };
+        if self.is_local(name) {
+            return self.call(expr, &items, None);
+        }
+        let builtin = match self.globals.borrow().get(name) {
+            Some(LustData::Builtin(b)) => b,
+            Some(LustData::Mac(_)) => {
+                let expanded = Interpreter::macroexpand(expr.clone(), self.globals.clone())?;
+                return self.compile(&expanded);
+            }
+            _ => return self.call(expr, &items, None),
+        };
+        match (builtin.name, items.len()) {
+            ("quote", 2) => Ok(Op::Const(items[1].clone())),
+            ("if", 4) => Ok(Op::If(
+                Box::new(self.compile(&items[1])?),
+                Box::new(self.compile(&items[2])?),
+                Box::new(self.compile(&items[3])?),
+            )),
+            ("let", 3) if matches!(items[1], LustData::Symbol(_)) => {
+                let name = items[1].expect_symbol()?;
+                // The value is compiled first as the name isn't bound
+                // while it is evaluated.
+                let value = Box::new(self.compile(&items[2])?);
+                Ok(Op::Let {
+                    name,
+                    slot: self.bind(name),
+                    value,
+                })
+            }
+            ("fn", _) => match make_fn("fn", expr.as_list()?.nth_item(1), self.globals.clone()) {
+                Ok(proto) => Ok(Op::Fn(Rc::new(Template {
+                    proto,
+                    scope: self.scope.clone(),
+                    body: OnceCell::new(),
+                    bytecode: self.bytecode,
+                    chunk: OnceCell::new(),
+                }))),
+                Err(_) => self.dynamic(expr),
+            },
+            (name, _) if STRICT_BUILTINS.contains(&name) => {
+                self.call(expr, &items, primitive(name))
+            }
+            _ => self.dynamic(expr),
+        }
+    }
+
+    fn call(
+        &self,
+        expr: &LustData,
+        items: &[LustData],
+        primitive: Option<&'static Primitive>,
+    ) -> Result<Op, String> {
+        let func = Box::new(self.compile(&items[0])?);
+        let args = items[1..]
+            .iter()
+            .map(|item| self.compile(item))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Op::Call {
+            func,
+            args,
+            source: expr.clone(),
+            named: Interpreter::is_named_call(expr),
+            primitive,
+        })
+    }
+
+    /// Leaves EXPR to the interpreter. In a function body this fails
+    /// if EXPR might bind a name, as it would be bound in a copy of
+    /// the function's bindings.
+    fn dynamic(&self, expr: &LustData) -> Result<Op, String> {
+        if self.scope.is_some() && self.might_bind(expr) {
+            return Err(format!("can not compile {}", expr));
+        }
+        Ok(Op::Dynamic(expr.clone()))
+    }
+
+    /// Determines if EXPR might bind a name when it is evaluated. This
+    /// is conservative: any mention of `let` or of a macro counts.
+    fn might_bind(&self, expr: &LustData) -> bool {
+        match expr {
+            LustData::Symbol(_) => {
+                let name = expr.expect_symbol().unwrap_or_default();
+                name == "let" || matches!(self.globals.borrow().get(name), Some(LustData::Mac(_)))
+            }
+            LustData::Mac(_) => true,
+            LustData::Cons(c) => c.into_iter().any(|item| self.might_bind(item)),
+            _ => false,
+        }
+    }
+
+    /// Determines if NAME is bound in a function enclosing the
+    /// expression being compiled.
+    fn is_local(&self, name: &str) -> bool {
+        let mut scope = self.scope.as_ref();
+        while let Some(s) = scope {
+            if s.names.borrow().contains(&name) {
+                return true;
+            }
+            scope = s.parent.as_ref();
+        }
+        false
+    }
+
+    /// Compiles a reference to NAME.
+    fn lookup(&self, name: &'static str) -> Op {
+        lookup_in(name, self.scope.as_ref(), 0)
+    }
+
+    /// Gives NAME a slot in the frame of the function being compiled.
+    /// Returns None at the top level where names are bound globally.
+    fn bind(&self, name: &'static str) -> Option<usize> {
+        let scope = self.scope.as_ref()?;
+        let mut names = scope.names.borrow_mut();
+        match names.iter().position(|n| *n == name) {
+            Some(slot) => Some(slot),
+            None => {
+                names.push(name);
+                Some(names.len() - 1)
+            }
+        }
+    }
+}
+
+/// Compiles a reference to NAME in SCOPE, which is DEPTH functions
+/// out from the one the reference is in.
+fn lookup_in(name: &'static str, scope: Option<&Rc<Scope>>, depth: usize) -> Op {
+    let scope = match scope {
+        Some(s) => s,
+        None => return Op::Global(intern(name)),
+    };
+    match scope.names.borrow().iter().position(|n| *n == name) {
+        Some(slot) if slot < scope.params => Op::Param { depth, slot },
+        Some(slot) => Op::Local {
+            depth,
+            slot,
+            outer: Box::new(lookup_in(name, scope.parent.as_ref(), depth + 1)),
+        },
+        None => lookup_in(name, scope.parent.as_ref(), depth + 1),
+    }
+}
+
+/// Runs operations in a frame.
+struct Run<'a> {
+    /// The frame of the function being run or None at the top level.
+    frame: Option<&'a Rc<Frame>>,
+    globals: &'a Rc<RefCell<LustEnv>>,
+}
+
+impl Run<'_> {
+    fn run(&self, op: &Op) -> Result<LustData, String> {
+        match op {
+            Op::Const(v) => Ok(v.clone()),
+            Op::Param { depth, slot } => Ok(load(self.frame, *depth, *slot).unwrap_or_default()),
+            Op::Local { depth, slot, outer } => match load(self.frame, *depth, *slot) {
+                Some(v) => Ok(v),
+                None => self.run(outer),
+            },
+            Op::Global(symbol) => self.globals.borrow().resolve_symbol(*symbol),
+            Op::If(cond, then, els) => {
+                if truthy(&self.run(cond)?) {
+                    self.run(then)
+                } else {
+                    self.run(els)
+                }
+            }
+            Op::Do(ops) => {
+                let (last, init) = ops.split_last().unwrap();
+                for op in init {
+                    self.run(op)?;
+                }
+                self.run(last)
+            }
+            Op::Let { name, slot, value } => {
+                Interpreter::check_shadow(name, self.globals)?;
+                let val = self.run(value)?;
+                Ok(bind(name, *slot, val, self.frame, self.globals))
+            }
+            Op::Fn(template) => Ok(closure(template, self.frame)),
+            Op::Call {
+                func,
+                args,
+                source,
+                named,
+                primitive,
+            } => {
+                // Like the interpreter, calls that aren't in tail
+                // position nest from before the function is found.
+                let call = if *named { Some(source) } else { None };
+                Interpreter::nested(call, || {
+                    let callee = self.run(func)?;
+                    if let Some(p) = primitive_of(*primitive, &callee) {
+                        return self.call_primitive(p, args);
+                    }
+                    if needs_interpreter(&callee) {
+                        return Interpreter::eval_here(source, self.env());
+                    }
+                    let args = self.run_args(args)?;
+                    self.apply(callee, args)
+                })
+            }
+            Op::Dynamic(expr) => self.interpret(expr),
+        }
+    }
+
+    /// Runs OP in tail position. Calls are returned to the caller to
+    /// make.
+    fn run_tail(&self, op: &Op) -> Result<Flow, String> {
+        match op {
+            Op::If(cond, then, els) => {
+                if truthy(&self.run(cond)?) {
+                    self.run_tail(then)
+                } else {
+                    self.run_tail(els)
+                }
+            }
+            Op::Do(ops) => {
+                let (last, init) = ops.split_last().unwrap();
+                for op in init {
+                    self.run(op)?;
+                }
+                self.run_tail(last)
+            }
+            Op::Call {
+                func,
+                args,
+                source,
+                primitive,
+                ..
+            } => {
+                let callee = self.run(func)?;
+                if let Some(p) = primitive_of(*primitive, &callee) {
+                    return Ok(Flow::Value(self.call_primitive(p, args)?));
+                }
+                if needs_interpreter(&callee) {
+                    return Ok(Flow::Value(Interpreter::eval_here(source, self.env())?));
+                }
+                Ok(Flow::TailCall(callee, self.run_args(args)?))
+            }
+            // The interpreter evaluates expressions in tail position
+            // without nesting.
+            Op::Dynamic(expr) => Ok(Flow::Value(Interpreter::eval_here(expr, self.env())?)),
+            _ => Ok(Flow::Value(self.run(op)?)),
+        }
+    }
+
+    fn run_args(&self, args: &[Op]) -> Result<Vec<LustData>, String> {
+        let mut values = Vec::with_capacity(args.len());
+        for arg in args {
+            values.push(self.run(arg)?);
+        }
+        Ok(values)
+    }
+
+    /// Calls PRIMITIVE with the values of ARGS. Primitives take at
+    /// most two arguments so their values are kept on the stack.
+    fn call_primitive(&self, primitive: &Primitive, args: &[Op]) -> Result<LustData, String> {
+        if args.len() > 2 {
+            return primitive.call(&self.run_args(args)?);
+        }
+        let mut values: [LustData; 2] = Default::default();
+        for (value, arg) in values.iter_mut().zip(args) {
+            *value = self.run(arg)?;
+        }
+        primitive.call(&values[..args.len()])
+    }
+
+    /// Calls CALLEE with ARGS.
+    fn apply(&self, callee: LustData, args: Vec<LustData>) -> Result<LustData, String> {
+        apply(callee, args, self.globals)
+    }
+
+    /// Evaluates EXPR with the interpreter.
+    fn interpret(&self, expr: &LustData) -> Result<LustData, String> {
+        Interpreter::eval_in_env(expr, self.env())
+    }
+
+    /// Gets an enviroment for the interpreter to evaluate code from
+    /// the function being run in.
+    fn env(&self) -> Rc<RefCell<LustEnv>> {
+        match self.frame {
+            Some(frame) => materialize(Some(frame), self.globals),
+            None => self.globals.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::Interpreter;
+    use crate::parser::Parser;
+
+    /// Evaluates each expression in SOURCE and shows the value of the
+    /// last one. Expressions are compiled if COMPILE is set.
+    fn eval(source: &str, compile: bool) -> Result<String, String> {
+        let mut evaluator = Interpreter::new();
+        evaluator.set_compile(compile);
+        let mut parser = Parser::new(source);
+        let mut res = String::new();
+        while parser.has_more() {
+            let expr = parser.parse_expr().expr.unwrap();
+            res = evaluator.eval_to_data(&expr)?.to_string();
+        }
+        Ok(res)
+    }
+
+    #[test]
+    fn same_as_interpreter() {
+        let sources = [
+            "(let adder (fn (n) (fn (x) (add x n)))) ((adder 2) 3)",
+            "(let f (fn (a & rest) (cons a rest))) (cons (f 1) (f 1 2 3))",
+            // Names bound with let mean what they did outside of the
+            // function until the let runs.
+            "(let x 1)
+             (let f (fn (c) (if c (let x 2) x)))
+             (cons (f ()) (cons (f 1) (cons x ())))",
+            // Nested functions see later bindings.
+            "(let do (fn (a b) b))
+             (let count (fn (n)
+               (do (let helper (fn (i acc) (if (eq i 0) acc (helper (sub i 1) (add acc 1)))))
+                   (helper n 0))))
+             (count 10)",
+            "(let f (fn (x) (eval 'x))) (f 5)",
+            "(let unless (macro (c body) `(if ,c () ,body))) (let f (fn (x) (unless x 7))) (f ())",
+            "(let f (fn (add) (add 1 2))) (f mul)",
+            "(let loop (fn (n) (if (eq n 0) 'done (loop (sub n 1))))) (loop 10000)",
+            "(let f (fn (x) x)) f",
+            "(let f (fn (x) x)) (f 1 2)",
+            "(let f (fn (n) (add n undefined))) (f 1)",
+            "(let f (fn (n) (add n 'a))) (f 1)",
+            "(let f (fn (n) (println (add n 'a)))) (f 1)",
+            "(let f (fn (n) (car (undefined n)))) (f 1)",
+        ];
+        for source in sources {
+            assert_eq!(eval(source, true), eval(source, false), "{}", source);
+        }
+    }
+
+    #[test]
+    fn redefinitions() {
+        for compile in [true, false] {
+            let source = "(let g (fn () 1)) (let f (fn () (g))) (f) (let g (fn () 2)) (f)";
+            assert_eq!(eval(source, compile), Ok("2".to_string()));
+            let source = "(let f (fn (a) (add a 1))) (f 5) (let add sub) (f 5)";
+            assert_eq!(eval(source, compile), Ok("4".to_string()));
+            // The function called in a loop may change between
+            // iterations.
+            let source = "(let f (fn (g n) (if (eq n 0) (g) (f (fn () n) (sub n 1))))) (f () 3)";
+            assert_eq!(eval(source, compile), Ok("1".to_string()));
+        }
+    }
+
+    #[test]
+    fn run_compiled() {
+        let mut evaluator = Interpreter::new();
+        let expr = Parser::new("(let n (add n 1))").parse_expr().expr.unwrap();
+        evaluator
+            .eval(&Parser::new("(let n 0)").parse_expr().expr.unwrap())
+            .unwrap();
+        let compiled = evaluator.compile(&expr).unwrap();
+        compiled.run(&mut evaluator).unwrap();
+        assert_eq!(compiled.run(&mut evaluator).unwrap().to_string(), "2");
+    }
+}
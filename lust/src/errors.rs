@@ -1,3 +1,4 @@
+use crate::interpreter::TRACE_HEADER;
 use crate::location::Location;
 use crate::parser::Expr;
 use crate::reader;
@@ -8,7 +9,10 @@ use colored::*;
 pub struct Error {
     pub loc: Location,
     pub what: String,
-    suggestion: Option<Suggestion>,
+    /// The calls being evaluated when the error occured, innermost
+    /// first. For example, `in fact (1 argument)`.
+    pub trace: Vec<String>,
+    pub suggestion: Option<Suggestion>,
 }
 
 #[derive(Debug)]
@@ -149,6 +153,12 @@ impl Printable for Error {
         if let Some(s) = &self.suggestion {
             s.show(source, file);
         }
+        if !self.trace.is_empty() {
+            println!("stack trace:");
+            for frame in &self.trace {
+                println!("  {}", frame);
+            }
+        }
     }
 }
 
@@ -169,6 +179,7 @@ impl Error {
         Self {
             loc: token.loc.clone(),
             what: what.to_string(),
+            trace: vec![],
             suggestion: Suggestion::on_tok(token),
         }
     }
@@ -176,16 +187,42 @@ impl Error {
         Self {
             loc: loc.clone(),
             what: what.to_string(),
+            trace: vec![],
             suggestion: None,
         }
     }
+    /// Makes an error from a message returned by the interpreter
+    /// while evaluating EXPR. Any stack trace in the message is moved
+    /// into the error's trace.
     pub fn on_expr(what: &str, expr: &Expr) -> Self {
+        let (what, trace) = match what.find(TRACE_HEADER) {
+            Some(i) => (
+                &what[..i],
+                what[i + TRACE_HEADER.len()..]
+                    .lines()
+                    .map(|l| l.trim_start().to_string())
+                    .filter(|l| !l.is_empty())
+                    .collect(),
+            ),
+            None => (what, vec![]),
+        };
         Self {
             loc: expr.loc.clone(),
             what: what.to_string(),
+            trace,
             suggestion: None,
         }
     }
+    /// Moves the error down by LINES lines. Used when the source the
+    /// error was found in is part of a larger buffer.
+    pub fn offset_lines(&mut self, lines: usize) {
+        self.loc.start.line += lines;
+        self.loc.end.line += lines;
+        if let Some(ref mut s) = self.suggestion {
+            s.loc.start.line += lines;
+            s.loc.end.line += lines;
+        }
+    }
     pub fn from_raw(s_line: usize, s_col: usize, e_line: usize, e_col: usize, what: &str) -> Self {
         let tok = Token::from_raw(
             s_line,
@@ -0,0 +1,186 @@
+/// Calls C functions in shared libraries. A library is opened with
+/// `open` and the functions in it are found with `sym`. Both return
+/// opaque values that Lust code passes around. C functions are called
+/// with `call`, which is given the C types of the function's return
+/// value and arguments:
+///
+/// - `int`, a C `int`, converted from and to a whole number.
+/// - `double`, a C `double`, converted from and to a number.
+/// - `ptr`, a pointer. Strings are passed as pointers to a copy of
+///   them ending in a nul byte and () as a null pointer. Pointers that
+///   C functions return are opaque values that can be passed back to
+///   C, or () if they are null.
+///
+/// There is no way to check that the types given are the function's
+/// real types. Getting them wrong is undefined behavior, as is calling
+/// a function that doesn't exist with a pointer from `sym`.
+use std::any::Any;
+use std::ffi::{c_int, c_void, CString};
+use std::rc::Rc;
+
+use libloading::Library;
+
+use crate::interpreter::{Interpreter, LustData};
+
+/// The most arguments a C function can be called with.
+const MAX_ARGS: usize = 3;
+
+/// A function found in a library by `sym`. Keeps the library open.
+struct Symbol {
+    ptr: *const c_void,
+    _library: Rc<dyn Any>,
+}
+
+/// A pointer returned by a C function.
+struct Pointer(*mut c_void);
+
+/// The C types that values can be converted to and from.
+#[derive(Clone, Copy)]
+pub enum CType {
+    Int,
+    Double,
+    Ptr,
+}
+
+impl CType {
+    /// Gets the type called NAME.
+    pub fn from_name(name: &str) -> Result<CType, String> {
+        match name {
+            "int" => Ok(CType::Int),
+            "double" => Ok(CType::Double),
+            "ptr" => Ok(CType::Ptr),
+            _ => Err(format!(
+                "ffi-call expected a type of int, double, or ptr, got {}",
+                name
+            )),
+        }
+    }
+}
+
+/// An argument to a C function. Ints and pointers are both passed as
+/// 64 bit integers, which the function reads the part it needs of.
+#[derive(Clone, Copy)]
+enum CArg {
+    Int(i64),
+    Double(f64),
+}
+
+/// Opens the shared library at PATH.
+pub fn open(path: &str) -> Result<LustData, String> {
+    // Loading a library runs its initializers, which are trusted the
+    // same as the functions that will be called.
+    let library = unsafe { Library::new(path) }
+        .map_err(|e| format!("ffi-open failed to open {}: {}", path, e))?;
+    Ok(Interpreter::make_opaque(library))
+}
+
+/// Finds the function called NAME in LIBRARY, a library from `open`.
+pub fn sym(library: &LustData, name: &str) -> Result<LustData, String> {
+    let library = match library {
+        LustData::Opaque(l) if l.is::<Library>() => l.clone(),
+        other => {
+            return Err(format!(
+                "ffi-sym expected a library, got {}",
+                other.type_name()
+            ))
+        }
+    };
+    let ptr = unsafe {
+        let symbol = library
+            .downcast_ref::<Library>()
+            .unwrap()
+            .get::<*const c_void>(name.as_bytes())
+            .map_err(|e| format!("ffi-sym failed to find {}: {}", name, e))?;
+        *symbol
+    };
+    Ok(Interpreter::make_opaque(Symbol {
+        ptr,
+        _library: library,
+    }))
+}
+
+/// Calls FUNC, a function from `sym`, with ARGS converted to their C
+/// types. The result is converted from the C type RET.
+pub fn call(func: &LustData, ret: CType, args: &[(CType, LustData)]) -> Result<LustData, String> {
+    let func = Interpreter::extract_opaque::<Symbol>(func).ok_or_else(|| {
+        format!(
+            "ffi-call expected a function from ffi-sym, got {}",
+            func.type_name()
+        )
+    })?;
+    if args.len() > MAX_ARGS {
+        return Err(format!(
+            "ffi-call supports at most {} arguments, got {}",
+            MAX_ARGS,
+            args.len()
+        ));
+    }
+    // The strings passed to the function have to outlive the call.
+    let mut strings = Vec::new();
+    let mut c_args = Vec::with_capacity(args.len());
+    for (ctype, value) in args {
+        c_args.push(to_c(*ctype, value, &mut strings)?);
+    }
+    Ok(unsafe {
+        match ret {
+            CType::Int => LustData::Number(call_with::<c_int>(func.ptr, &c_args) as f32),
+            CType::Double => LustData::Number(call_with::<f64>(func.ptr, &c_args) as f32),
+            CType::Ptr => match call_with::<*mut c_void>(func.ptr, &c_args) {
+                p if p.is_null() => LustData::get_empty_list(),
+                p => Interpreter::make_opaque(Pointer(p)),
+            },
+        }
+    })
+}
+
+/// Converts VALUE to the C type CTYPE. Strings are copied into
+/// STRINGS, which must outlive the call they are passed to.
+fn to_c(ctype: CType, value: &LustData, strings: &mut Vec<CString>) -> Result<CArg, String> {
+    match (ctype, value) {
+        (CType::Int, LustData::Number(n)) if n.fract() == 0.0 => Ok(CArg::Int(*n as i64)),
+        (CType::Double, LustData::Number(n)) => Ok(CArg::Double(*n as f64)),
+        (CType::Ptr, v) if v.is_empty_list() => Ok(CArg::Int(0)),
+        (CType::Ptr, LustData::Opaque(_)) => match Interpreter::extract_opaque::<Pointer>(value) {
+            Some(p) => Ok(CArg::Int(p.0 as i64)),
+            None => Err("ffi-call expected a ptr, got opaque".to_string()),
+        },
+        (CType::Ptr, v) if v.stringify().is_some() => {
+            let s = CString::new(v.stringify().unwrap())
+                .map_err(|_| format!("ffi-call can't pass {} as it contains a nul", v))?;
+            strings.push(s);
+            Ok(CArg::Int(strings.last().unwrap().as_ptr() as i64))
+        }
+        (CType::Int, v) => Err(format!("ffi-call expected an int, got {}", v)),
+        (CType::Double, v) => Err(format!("ffi-call expected a double, got {}", v)),
+        (CType::Ptr, v) => Err(format!("ffi-call expected a ptr, got {}", v)),
+    }
+}
+
+/// Calls the C function at PTR with ARGS, returning an R. There must
+/// be no more than `MAX_ARGS` arguments.
+unsafe fn call_with<R>(ptr: *const c_void, args: &[CArg]) -> R {
+    use std::mem::transmute;
+    use CArg::{Double as D, Int as I};
+    type F0<R> = unsafe extern "C" fn() -> R;
+    type F1<A, R> = unsafe extern "C" fn(A) -> R;
+    type F2<A, B, R> = unsafe extern "C" fn(A, B) -> R;
+    type F3<A, B, C, R> = unsafe extern "C" fn(A, B, C) -> R;
+    match *args {
+        [] => transmute::<*const c_void, F0<R>>(ptr)(),
+        [I(a)] => transmute::<*const c_void, F1<i64, R>>(ptr)(a),
+        [D(a)] => transmute::<*const c_void, F1<f64, R>>(ptr)(a),
+        [I(a), I(b)] => transmute::<*const c_void, F2<i64, i64, R>>(ptr)(a, b),
+        [I(a), D(b)] => transmute::<*const c_void, F2<i64, f64, R>>(ptr)(a, b),
+        [D(a), I(b)] => transmute::<*const c_void, F2<f64, i64, R>>(ptr)(a, b),
+        [D(a), D(b)] => transmute::<*const c_void, F2<f64, f64, R>>(ptr)(a, b),
+        [I(a), I(b), I(c)] => transmute::<*const c_void, F3<i64, i64, i64, R>>(ptr)(a, b, c),
+        [I(a), I(b), D(c)] => transmute::<*const c_void, F3<i64, i64, f64, R>>(ptr)(a, b, c),
+        [I(a), D(b), I(c)] => transmute::<*const c_void, F3<i64, f64, i64, R>>(ptr)(a, b, c),
+        [I(a), D(b), D(c)] => transmute::<*const c_void, F3<i64, f64, f64, R>>(ptr)(a, b, c),
+        [D(a), I(b), I(c)] => transmute::<*const c_void, F3<f64, i64, i64, R>>(ptr)(a, b, c),
+        [D(a), I(b), D(c)] => transmute::<*const c_void, F3<f64, i64, f64, R>>(ptr)(a, b, c),
+        [D(a), D(b), I(c)] => transmute::<*const c_void, F3<f64, f64, i64, R>>(ptr)(a, b, c),
+        [D(a), D(b), D(c)] => transmute::<*const c_void, F3<f64, f64, f64, R>>(ptr)(a, b, c),
+        _ => unreachable!("call_with called with more than MAX_ARGS arguments"),
+    }
+}
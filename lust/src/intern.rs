@@ -0,0 +1,56 @@
+/// Interns symbol names so that symbols can be stored and compared as
+/// integers. Interned names live for the rest of the program.
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+#[derive(Default)]
+struct Interner {
+    /// Names indexed by their id.
+    names: Vec<&'static str>,
+    ids: HashMap<&'static str, usize>,
+}
+
+fn interner() -> &'static Mutex<Interner> {
+    static INTERNER: OnceLock<Mutex<Interner>> = OnceLock::new();
+    INTERNER.get_or_init(Default::default)
+}
+
+/// Gets the id of the symbol named S, interning it if this is the
+/// first time it has been seen.
+pub fn intern(s: &str) -> usize {
+    let mut interner = interner().lock().unwrap();
+    if let Some(id) = interner.ids.get(s) {
+        return *id;
+    }
+    let name: &'static str = Box::leak(s.to_string().into_boxed_str());
+    let id = interner.names.len();
+    interner.names.push(name);
+    interner.ids.insert(name, id);
+    id
+}
+
+/// Gets the id of the symbol named S if it has been interned.
+pub fn interned(s: &str) -> Option<usize> {
+    interner().lock().unwrap().ids.get(s).copied()
+}
+
+/// Gets the name of the symbol with id ID. Panics if ID was not
+/// returned by `intern`.
+pub fn resolve_intern(id: usize) -> &'static str {
+    interner().lock().unwrap().names[id]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let id = intern("intern-test-symbol");
+        assert_eq!(intern("intern-test-symbol"), id);
+        assert_ne!(intern("intern-test-other"), id);
+        assert_eq!(interned("intern-test-symbol"), Some(id));
+        assert_eq!(interned("intern-test-never-interned"), None);
+        assert_eq!(resolve_intern(id), "intern-test-symbol");
+    }
+}
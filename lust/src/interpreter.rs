@@ -1,14 +1,342 @@
 use crate::builtins;
+use crate::compile::{self, CompiledExpr, CompiledFn};
+use crate::intern::{intern, interned, resolve_intern};
+use crate::location::Location;
+use crate::optimize;
 use crate::parser::{Expr, ExprVal};
-use std::cell::RefCell;
+use indexmap::IndexMap;
+use rustc_hash::FxBuildHasher;
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt;
+use std::io::Write;
 use std::ops::Index;
-use std::rc::Rc;
+use std::rc::{Rc, Weak};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The default limit on how deeply evaluation may nest.
+pub const DEFAULT_MAX_DEPTH: usize = 10_000;
+
+/// The default limit on how many times a single expression may be
+/// macro expanded.
+pub const DEFAULT_MAX_EXPANSIONS: usize = 1000;
+
+/// The most stack frames shown in an error's stack trace.
+const MAX_TRACE_FRAMES: usize = 20;
+
+thread_local! {
+    /// The non-tail calls to named functions currently being
+    /// evaluated, innermost last.
+    static CALL_STACK: RefCell<Vec<LustData>> = const { RefCell::new(Vec::new()) };
+    /// Descriptions of the call stack from the point where the error
+    /// being returned was raised.
+    static ERROR_TRACE: RefCell<Option<Vec<String>>> = const { RefCell::new(None) };
+    /// The number of calls to eval_in_env currently active.
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+    /// The limit on DEPTH. Set from the interpreter doing the
+    /// evaluating.
+    static MAX_DEPTH: Cell<usize> = const { Cell::new(DEFAULT_MAX_DEPTH) };
+    /// The limit on expansions in macroexpand. Set from the
+    /// interpreter doing the evaluating.
+    static MAX_EXPANSIONS: Cell<usize> = const { Cell::new(DEFAULT_MAX_EXPANSIONS) };
+    /// Set by a timer thread when the evaluation in progress has run
+    /// out of time.
+    static TIMED_OUT: RefCell<Option<Arc<AtomicBool>>> = const { RefCell::new(None) };
+    /// The context of the interpreter doing the evaluating.
+    static CONTEXT: RefCell<Option<Context>> = const { RefCell::new(None) };
+    /// The number of traced calls currently active. Used to indent
+    /// the trace.
+    static TRACE_DEPTH: Cell<usize> = const { Cell::new(0) };
+    /// The location of the top level expression being evaluated.
+    static LOCATION: RefCell<Option<Location>> = const { RefCell::new(None) };
+    /// Macro calls that have already been expanded.
+    static EXPANSIONS: RefCell<ExpansionCache> = RefCell::new(ExpansionCache::default());
+    /// Metadata attached to lists.
+    static LIST_METADATA: RefCell<ListMetadata> = RefCell::new(ListMetadata::default());
+}
+
+/// Remembers what macro calls expanded to so that code in loops and
+/// function bodies is only expanded once. Entries are keyed by the
+/// address of the call's cons cell and hold weak references to the
+/// call and the macro. The weak references keep the addresses from
+/// being reused, so an address match means the same call. An entry is
+/// only used if the call still resolves to the same macro.
+#[derive(Default)]
+struct ExpansionCache {
+    entries: HashMap<usize, CachedExpansion>,
+    /// The number of entries at which to drop the entries for calls
+    /// that no longer exist.
+    prune_at: usize,
+}
+
+struct CachedExpansion {
+    call: Weak<ConsCell>,
+    mac: Weak<LustFn>,
+    expansion: LustData,
+}
+
+impl ExpansionCache {
+    fn get(&self, call: &Rc<ConsCell>, mac: &Rc<LustFn>) -> Option<LustData> {
+        let entry = self.entries.get(&(Rc::as_ptr(call) as usize))?;
+        if entry.mac.as_ptr() == Rc::as_ptr(mac) {
+            Some(entry.expansion.clone())
+        } else {
+            None
+        }
+    }
+
+    fn insert(&mut self, call: &Rc<ConsCell>, mac: &Rc<LustFn>, expansion: LustData) {
+        if self.entries.len() >= self.prune_at {
+            self.entries.retain(|_, e| e.call.strong_count() > 0);
+            self.prune_at = (self.entries.len() * 2).max(1024);
+        }
+        self.entries.insert(
+            Rc::as_ptr(call) as usize,
+            CachedExpansion {
+                call: Rc::downgrade(call),
+                mac: Rc::downgrade(mac),
+                expansion,
+            },
+        );
+    }
+}
+
+/// Metadata attached to lists with `with-meta`. Lists are shared
+/// so the metadata isn't stored in them. Instead, entries are keyed
+/// by the address of the list's first cell and hold a weak reference
+/// to it, as in `ExpansionCache`.
+#[derive(Default)]
+struct ListMetadata {
+    entries: HashMap<usize, (Weak<ConsCell>, LustData)>,
+    /// The number of entries at which to drop the entries for lists
+    /// that no longer exist.
+    prune_at: usize,
+}
+
+impl ListMetadata {
+    fn get(&self, list: &Rc<ConsCell>) -> Option<LustData> {
+        self.entries
+            .get(&(Rc::as_ptr(list) as usize))
+            .map(|(_, meta)| meta.clone())
+    }
+
+    fn insert(&mut self, list: &Rc<ConsCell>, meta: LustData) {
+        if self.entries.len() >= self.prune_at {
+            self.entries.retain(|_, (l, _)| l.strong_count() > 0);
+            self.prune_at = (self.entries.len() * 2).max(1024);
+        }
+        self.entries
+            .insert(Rc::as_ptr(list) as usize, (Rc::downgrade(list), meta));
+    }
+}
+
+/// State belonging to an interpreter that builtins need access to.
+#[derive(Clone)]
+struct Context {
+    /// Where printing builtins write to.
+    output: Rc<RefCell<dyn Write>>,
+    /// Where diagnostics such as the timings printed by `time` are
+    /// written to.
+    diagnostics: Rc<RefCell<dyn Write>>,
+    /// Returns monotonic time in seconds.
+    clock: Rc<dyn Fn() -> f64>,
+    /// State of the random number generator.
+    rng: Rc<Cell<u64>>,
+    /// If set, function calls and their return values are printed.
+    trace: bool,
+    /// Tests registered with `deftest`.
+    tests: Rc<RefCell<Vec<Test>>>,
+    /// What to do when `let` shadows a builtin.
+    shadow_policy: ShadowPolicy,
+    /// The shadowing that has already been warned about. Holds the
+    /// name that was bound and where.
+    shadow_warnings: Rc<RefCell<HashSet<String>>>,
+    /// The variables declared with `defdynamic`, keyed by their
+    /// interned ids, and the enviroments they were declared in.
+    dynamic: Rc<RefCell<HashMap<usize, Rc<RefCell<LustEnv>>>>>,
+}
+
+/// What happens when user code binds a name that resolves to a
+/// builtin.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum ShadowPolicy {
+    /// Shadow the builtin silently.
+    #[default]
+    Allow,
+    /// Print a warning the first time each binding shadows a builtin.
+    Warn,
+    /// Fail with an error.
+    Deny,
+}
+
+/// A test registered with `deftest`.
+#[derive(Clone)]
+pub struct Test {
+    pub name: String,
+    /// The expressions to evaluate when the test is run.
+    pub body: Vec<LustData>,
+    /// The enviroment the test was defined in.
+    pub env: Rc<RefCell<LustEnv>>,
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        static EPOCH: OnceLock<Instant> = OnceLock::new();
+        let epoch = *EPOCH.get_or_init(Instant::now);
+        Self {
+            output: Rc::new(RefCell::new(std::io::stdout())),
+            diagnostics: Rc::new(RefCell::new(std::io::stderr())),
+            clock: Rc::new(move || epoch.elapsed().as_secs_f64()),
+            rng: Rc::new(Cell::new(
+                std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map_or(0, |d| d.as_nanos() as u64),
+            )),
+            trace: false,
+            tests: Rc::new(RefCell::new(Vec::new())),
+            shadow_policy: ShadowPolicy::Allow,
+            shadow_warnings: Rc::new(RefCell::new(HashSet::new())),
+            dynamic: Rc::new(RefCell::new(HashMap::new())),
+        }
+    }
+}
+
+/// The builtins available to a sandboxed interpreter.
+pub const SANDBOX_BUILTINS: &[&str] = &[
+    "quote",
+    "quaziquote",
+    "quasiquote",
+    "car",
+    "cdr",
+    "caar",
+    "cadr",
+    "cdar",
+    "cddr",
+    "caddr",
+    "cdddr",
+    "first",
+    "second",
+    "third",
+    "rest",
+    "car?",
+    "nth",
+    "nth?",
+    "cons",
+    "pair",
+    "if",
+    "let",
+    "fn",
+    "error",
+    "macro",
+    "macroexpand",
+    "macroexpand-1",
+    "negate",
+    "add",
+    "sub",
+    "mul",
+    "div",
+    "mod",
+    "nan?",
+    "inf?",
+    "lt",
+    "gt",
+    "eq",
+    "identical?",
+    "equal?",
+    "eq?",
+    "eqv?",
+    "assert",
+    "assert-eq",
+    "doc",
+    "from-json",
+    "to-json",
+    "delay",
+    "force",
+    "promise?",
+    "memoize",
+    "make-hash-table",
+    "hash-table-set!",
+    "hash-table-ref",
+    "hash-table-comparator",
+    "hash-table-equivalence-function",
+    "tail-call?",
+    "flatten",
+    "flatten-deep",
+    "sort",
+    "sort-by",
+    "match",
+    "case-lambda",
+    "partition",
+    "group-by",
+    "defrecord",
+    "loop",
+    "recur",
+    "record-set",
+    "define-record-type",
+    "defdynamic",
+    "binding",
+    "module",
+    "take",
+    "drop",
+    "iota",
+    "repeat",
+    "concatenate",
+    "flatten-map",
+    "take-while",
+    "find",
+    "find-index",
+    "count",
+    "any?",
+    "every?",
+    "count-if",
+    "all?",
+    "drop-while",
+    "zip",
+    "unzip",
+    "with-meta",
+    "meta",
+];
+
+/// Separates an error message from the stack trace appended to it by
+/// the interpreter.
+pub const TRACE_HEADER: &str = "\nstack trace:";
 
 /// An interpreter for Lust code.
 pub struct Interpreter {
     /// The global enviroment in which functions are evlauted.
     pub global_env: Rc<RefCell<LustEnv>>,
+    /// If set, expressions are printed as parsed before they are
+    /// evaluated.
+    pub dump_ast: bool,
+    /// If set, expressions are printed after macro expansion before
+    /// they are evaluated.
+    pub dump_expanded: bool,
+    /// The maximum depth that evaluation may nest before an error is
+    /// raised.
+    max_depth: usize,
+    /// The maximum number of times an expression may be macro
+    /// expanded before an error is raised.
+    max_expansions: usize,
+    /// If set, constants are folded in expressions after they are
+    /// macro expanded.
+    optimize: bool,
+    /// If set, expressions are compiled before they are evaluated.
+    compile: bool,
+    /// If set, compiled expressions are run on the bytecode virtual
+    /// machine.
+    bytecode: bool,
+    /// Set when the system builtins have been installed.
+    system_builtins: bool,
+    /// The builtins the interpreter was built with if not the
+    /// defaults.
+    allowed_builtins: Option<Vec<String>>,
+    /// The functions installed with `register_fn`, in order.
+    native_fns: Vec<(String, LustData)>,
+    context: Context,
 }
 
 /// The result of calling a function. If the function is a builtin the
@@ -28,37 +356,560 @@ impl Interpreter {
     pub fn new() -> Self {
         Self {
             global_env: LustEnv::new(),
+            dump_ast: false,
+            dump_expanded: false,
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_expansions: DEFAULT_MAX_EXPANSIONS,
+            optimize: false,
+            compile: false,
+            bytecode: false,
+            system_builtins: false,
+            allowed_builtins: None,
+            native_fns: Vec::new(),
+            context: Context::default(),
+        }
+    }
+
+    /// Builds an interpreter with only the builtins that can't have
+    /// side effects outside of the interpreter. Printing, `eval`,
+    /// `import`, and the system builtins are not available.
+    pub fn new_sandboxed() -> Self {
+        Self::new_with_builtins(SANDBOX_BUILTINS)
+    }
+
+    /// Builds an interpreter with only the builtins named in
+    /// ALLOWED. See `LustEnv::new_with_builtins`.
+    pub fn new_with_builtins(allowed: &[&str]) -> Self {
+        Self {
+            global_env: LustEnv::new_with_builtins(allowed),
+            allowed_builtins: Some(allowed.iter().map(|s| s.to_string()).collect()),
+            ..Self::new()
+        }
+    }
+
+    /// Makes the system builtins available in the global
+    /// enviroment. See `LustEnv::install_system_builtins`.
+    pub fn enable_system_builtins(&mut self) {
+        self.global_env.borrow_mut().install_system_builtins();
+        self.system_builtins = true;
+    }
+
+    /// Returns true if `enable_system_builtins` has been called.
+    pub fn system_builtins_enabled(&self) -> bool {
+        self.system_builtins
+    }
+
+    /// Forgets everything defined in the global enviroment. The
+    /// builtins the interpreter was built with remain.
+    pub fn reset(&mut self) {
+        self.global_env = match self.allowed_builtins {
+            Some(ref allowed) => {
+                let allowed: Vec<&str> = allowed.iter().map(String::as_str).collect();
+                LustEnv::new_with_builtins(&allowed)
+            }
+            None => LustEnv::new(),
+        };
+        if self.system_builtins {
+            self.enable_system_builtins();
+        }
+        for (name, f) in &self.native_fns {
+            self.global_env.borrow_mut().insert(name.clone(), f.clone());
+        }
+        self.clear_tests();
+        self.context.dynamic.borrow_mut().clear();
+    }
+
+    /// Forgets the tests registered with `deftest`.
+    pub fn clear_tests(&mut self) {
+        self.context.tests.borrow_mut().clear();
+    }
+
+    /// Registers a test with the interpreter doing the evaluating. A
+    /// test with the same name as an existing one replaces it.
+    pub fn register_test(test: Test) {
+        let tests = Self::context().tests;
+        let mut tests = tests.borrow_mut();
+        match tests.iter_mut().find(|t| t.name == test.name) {
+            Some(existing) => *existing = test,
+            None => tests.push(test),
         }
     }
 
+    /// Gets the tests registered with the interpreter doing the
+    /// evaluating in the order they were defined.
+    pub fn registered_tests() -> Vec<Test> {
+        Self::context().tests.borrow().clone()
+    }
+
+    /// Declares the symbol with interned id ID, bound in ENV, a
+    /// dynamic variable of the interpreter doing the evaluating.
+    pub(crate) fn declare_dynamic(id: usize, env: Rc<RefCell<LustEnv>>) {
+        Self::context().dynamic.borrow_mut().insert(id, env);
+    }
+
+    /// Gets the enviroment the dynamic variable with interned id ID
+    /// was declared in, if it is one.
+    pub(crate) fn dynamic_env(id: usize) -> Option<Rc<RefCell<LustEnv>>> {
+        Self::context().dynamic.borrow().get(&id).cloned()
+    }
+
+    /// Forgets the stack trace recorded for an error which has been
+    /// handled so that it isn't reported with a later one.
+    pub fn forget_error_trace() {
+        ERROR_TRACE.with(|t| t.borrow_mut().take());
+    }
+
     /// Evlalutes an expression from the parser. The expression is
     /// first stripped of location data and then evaluated.
     pub fn eval(&mut self, expr: &Expr) -> Result<(), String> {
-        let data = expr.to_data()?;
-
-        Self::eval_in_env(&data, self.global_env.clone())?;
+        self.eval_to_data(expr)?;
         Ok(())
     }
 
     /// Evaluates an expression and then prints the result. Used by the
-    /// repl.
+    /// repl. The result is bound to `_` and `*1` in the global
+    /// enviroment so it can be used by later expressions, and the two
+    /// results before it to `*2` and `*3`.
     pub fn eval_print(&mut self, expr: &Expr) -> Result<(), String> {
-        let data = expr.to_data()?;
-        let res = Self::eval_in_env(&data, self.global_env.clone())?;
+        let res = self.eval_to_data(expr)?;
 
         if !res.is_empty_list() {
             println!("=> {}", res);
         }
+        self.remember_result(res);
         Ok(())
     }
 
+    fn remember_result(&mut self, res: LustData) {
+        let mut env = self.global_env.borrow_mut();
+        for (from, to) in [("*2", "*3"), ("*1", "*2")] {
+            if let Some(val) = env.get(from) {
+                env.insert(to.to_string(), val);
+            }
+        }
+        env.insert("*1".to_string(), res.clone());
+        env.insert("_".to_string(), res);
+    }
+
+    /// Evaluates an expression from the parser in the global
+    /// enviroment and returns the result.
+    pub fn eval_to_data(&mut self, expr: &Expr) -> Result<LustData, String> {
+        self.dump(expr);
+        let data = expr.to_data()?;
+        self.enter(&expr.loc, |me| {
+            let data = me.prepare(data)?;
+            // Traces are only recorded by the interpreter.
+            if (me.compile || me.bytecode) && !me.context.trace {
+                let loc = expr.loc.clone();
+                let compiled = compile::compile(&data, &me.global_env, loc, me.bytecode);
+                compiled.run_entered(&me.global_env)
+            } else {
+                Self::eval_in_env(&data, me.global_env.clone())
+            }
+        })
+    }
+
+    /// Compiles EXPR so that it can be run faster than it would be
+    /// evaluated. Macros are expanded as the expression is compiled
+    /// and function bodies are compiled when the function is first
+    /// called. See the `compile` module.
+    pub fn compile(&mut self, expr: &Expr) -> Result<CompiledExpr, String> {
+        self.dump(expr);
+        let data = expr.to_data()?;
+        let mut res = None;
+        self.enter(&expr.loc, |me| {
+            let data = me.prepare(data)?;
+            let loc = expr.loc.clone();
+            res = Some(compile::compile(&data, &me.global_env, loc, me.bytecode));
+            Ok(LustData::get_empty_list())
+        })?;
+        Ok(res.unwrap())
+    }
+
+    /// Expands, folds, and prints DATA before it is evaluated as
+    /// asked for by the interpreter's settings.
+    fn prepare(&self, data: LustData) -> Result<LustData, String> {
+        if !self.dump_expanded && !self.optimize {
+            return Ok(data);
+        }
+        let mut expanded = Self::macroexpand(data, self.global_env.clone())?;
+        if self.optimize {
+            expanded = optimize::fold_constants(&expanded, &self.global_env);
+        }
+        if self.dump_expanded {
+            println!("{}", expanded);
+        }
+        Ok(expanded)
+    }
+
+    /// Runs EVAL with the interpreter's settings and context in place
+    /// for the top level expression at LOC. Errors are given the
+    /// stack trace from where they were raised.
+    pub(crate) fn enter<F>(&mut self, loc: &Location, eval: F) -> Result<LustData, String>
+    where
+        F: FnOnce(&mut Self) -> Result<LustData, String>,
+    {
+        ERROR_TRACE.with(|t| t.borrow_mut().take());
+        let outer_max_depth = MAX_DEPTH.with(|m| m.replace(self.max_depth));
+        let outer_max_expansions = MAX_EXPANSIONS.with(|m| m.replace(self.max_expansions));
+        let outer_context = CONTEXT.with(|c| c.replace(Some(self.context.clone())));
+        let outer_location = LOCATION.with(|l| l.replace(Some(loc.clone())));
+        let res = eval(self);
+        MAX_DEPTH.with(|m| m.set(outer_max_depth));
+        MAX_EXPANSIONS.with(|m| m.set(outer_max_expansions));
+        CONTEXT.with(|c| c.replace(outer_context));
+        LOCATION.with(|l| l.replace(outer_location));
+        res.map_err(|e| match ERROR_TRACE.with(|t| t.borrow_mut().take()) {
+            Some(trace) if !trace.is_empty() => {
+                let mut e = e;
+                e.push_str(TRACE_HEADER);
+                for frame in trace.iter().rev().take(MAX_TRACE_FRAMES) {
+                    e.push_str("\n  in ");
+                    e.push_str(frame);
+                }
+                if trace.len() > MAX_TRACE_FRAMES {
+                    e.push_str(&format!("\n  ... {} more", trace.len() - MAX_TRACE_FRAMES));
+                }
+                e
+            }
+            _ => e,
+        })
+    }
+
+    /// Evaluates an expression, giving up with an error if it takes
+    /// longer than TIMEOUT.
+    pub fn eval_with_timeout(&mut self, expr: &Expr, timeout: Duration) -> Result<(), String> {
+        let flag = Arc::new(AtomicBool::new(false));
+        let (done, wait) = mpsc::channel::<()>();
+        let timer_flag = flag.clone();
+        thread::spawn(move || {
+            // Evaluation finishing drops the sender which wakes us up
+            // early.
+            if let Err(mpsc::RecvTimeoutError::Timeout) = wait.recv_timeout(timeout) {
+                timer_flag.store(true, Ordering::Relaxed);
+            }
+        });
+
+        let outer = TIMED_OUT.with(|t| t.replace(Some(flag)));
+        let res = self.eval(expr);
+        TIMED_OUT.with(|t| t.replace(outer));
+        drop(done);
+        res
+    }
+
+    /// Enables or disables constant folding. When enabled, calls to
+    /// pure builtins with literal arguments are evaluated once before
+    /// the expression containing them is. See the `optimize` module.
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+    }
+
+    /// Enables or disables compiling expressions before they are
+    /// evaluated. Compiled code gives the same results as the
+    /// interpreter but runs faster. See `Interpreter::compile`.
+    pub fn set_compile(&mut self, compile: bool) {
+        self.compile = compile;
+    }
+
+    /// Enables or disables running expressions on the bytecode
+    /// virtual machine. Expressions are compiled as they are with
+    /// `set_compile` and then lowered to bytecode. See the `bytecode`
+    /// module.
+    pub fn use_bytecode(&mut self, bytecode: bool) {
+        self.bytecode = bytecode;
+    }
+
+    /// Sets the maximum depth that evaluation may nest before failing
+    /// with an error. Tail calls do not count towards this.
+    pub fn set_max_depth(&mut self, depth: usize) {
+        self.max_depth = depth;
+    }
+
+    /// Sets where the printing builtins write to. Standard output is
+    /// used by default.
+    pub fn set_output(&mut self, output: Rc<RefCell<dyn Write>>) {
+        self.context.output = output;
+    }
+
+    /// Sets where diagnostics, such as the timings printed by `time`,
+    /// are written to. Standard error is used by default.
+    pub fn set_diagnostics(&mut self, diagnostics: Rc<RefCell<dyn Write>>) {
+        self.context.diagnostics = diagnostics;
+    }
+
+    /// Wraps a Rust value so it can be passed to Lust code. Lust can
+    /// only pass the value around and compare it to others. Use
+    /// `extract_opaque` to get it back.
+    pub fn make_opaque<T: Any>(val: T) -> LustData {
+        LustData::Opaque(Rc::new(val))
+    }
+
+    /// Gets the Rust value in DATA if it was made by `make_opaque`
+    /// from a value of type T.
+    pub fn extract_opaque<T: Any>(data: &LustData) -> Option<&T> {
+        match data {
+            LustData::Opaque(v) => v.downcast_ref(),
+            _ => None,
+        }
+    }
+
+    /// Makes F callable from Lust as a function called NAME in the
+    /// global enviroment. F is passed the evaluated arguments and the
+    /// enviroment of the call. Unlike builtins, F can be a closure
+    /// that captures state. Registered functions are kept by `reset`.
+    pub fn register_fn<F>(&mut self, name: &str, f: F)
+    where
+        F: Fn(&[LustData], Rc<RefCell<LustEnv>>) -> Result<CallResult, String> + 'static,
+    {
+        let f = LustData::NativeFn(Rc::new(f));
+        self.global_env
+            .borrow_mut()
+            .insert(name.to_string(), f.clone());
+        self.native_fns.push((name.to_string(), f));
+    }
+
+    /// Sets the clock used by the `clock` builtin. CLOCK returns
+    /// monotonic time in seconds.
+    pub fn set_clock(&mut self, clock: Box<dyn Fn() -> f64>) {
+        self.context.clock = Rc::from(clock);
+    }
+
+    /// Enables or disables tracing. While tracing, every call of a
+    /// function or macro is printed with its arguments and indented
+    /// by how deeply it is nested, followed by its return value.
+    pub fn set_trace(&mut self, trace: bool) {
+        self.context.trace = trace;
+    }
+
+    /// Sets what happens when `let` binds a name that resolves to a
+    /// builtin. Shadowing is allowed by default.
+    pub fn set_shadow_policy(&mut self, policy: ShadowPolicy) {
+        self.context.shadow_policy = policy;
+    }
+
+    /// Applies the shadow policy of the interpreter doing the
+    /// evaluating to binding NAME in ENV. Warnings are printed once
+    /// for each name and top level expression.
+    pub fn check_shadow(name: &str, env: &Rc<RefCell<LustEnv>>) -> Result<(), String> {
+        let context = Self::context();
+        if context.shadow_policy == ShadowPolicy::Allow {
+            return Ok(());
+        }
+        if !matches!(env.borrow().get(name), Some(LustData::Builtin(_))) {
+            return Ok(());
+        }
+        let at = match Self::current_location() {
+            Some(loc) => format!(" at {}:{}", loc.start.line, loc.start.col),
+            None => String::new(),
+        };
+        match context.shadow_policy {
+            ShadowPolicy::Deny => Err(format!("{} shadows a builtin", name)),
+            _ => {
+                let site = format!("{}{}", name, at);
+                if context.shadow_warnings.borrow_mut().insert(site) {
+                    Self::write_output(&format!("warning: {} shadows a builtin{}\n", name, at))?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Seeds the random number generator used by the `random`
+    /// builtins. Interpreters seeded the same way produce the same
+    /// numbers.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.context.rng.set(seed);
+    }
+
+    /// Seeds the random number generator of the interpreter doing the
+    /// evaluating.
+    pub fn seed_random(seed: u64) {
+        Self::context().rng.set(seed);
+    }
+
+    /// Gets the next number from the random number generator of the
+    /// interpreter doing the evaluating. Uses splitmix64 which is
+    /// fast and good enough for anything other than cryptography.
+    pub fn next_random() -> u64 {
+        let rng = Self::context().rng;
+        let state = rng.get().wrapping_add(0x9e3779b97f4a7c15);
+        rng.set(state);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Gets the context of the interpreter doing the evaluating or
+    /// the default context if evaluation was started some other way.
+    fn context() -> Context {
+        CONTEXT.with(|c| c.borrow().clone()).unwrap_or_default()
+    }
+
+    /// Writes S to the output of the interpreter doing the
+    /// evaluating.
+    pub fn write_output(s: &str) -> Result<(), String> {
+        let output = Self::context().output;
+        let mut output = output.borrow_mut();
+        output
+            .write_all(s.as_bytes())
+            .and_then(|_| output.flush())
+            .map_err(|e| format!("failed to write output: {}", e))
+    }
+
+    /// Writes S to the diagnostics of the interpreter doing the
+    /// evaluating.
+    pub fn write_diagnostic(s: &str) -> Result<(), String> {
+        let diagnostics = Self::context().diagnostics;
+        let mut diagnostics = diagnostics.borrow_mut();
+        diagnostics
+            .write_all(s.as_bytes())
+            .and_then(|_| diagnostics.flush())
+            .map_err(|e| format!("failed to write diagnostic: {}", e))
+    }
+
+    /// Reads the clock of the interpreter doing the evaluating.
+    pub fn clock() -> f64 {
+        (Self::context().clock)()
+    }
+
+    /// Sets the maximum number of times an expression may be macro
+    /// expanded before failing with an error. This stops macros that
+    /// expand to themselves from looping forever.
+    pub fn set_max_expansions(&mut self, expansions: usize) {
+        self.max_expansions = expansions;
+    }
+
+    /// Gets the calls that are currently being evaluated, innermost
+    /// last. Tail calls replace their caller's frame and so do not
+    /// show up here.
+    pub fn call_stack() -> Vec<String> {
+        CALL_STACK.with(|s| s.borrow().iter().map(Self::describe_call).collect())
+    }
+
+    /// Determines if the call with ARGS as its arguments is on top of
+    /// the call stack. Calls in tail position never are.
+    pub(crate) fn is_innermost_call(args: &ConsCell) -> bool {
+        CALL_STACK.with(|s| match s.borrow().last() {
+            Some(LustData::Cons(c)) => match **c {
+                ConsCell::Cons(ref cell) => std::ptr::eq(&*cell.next, args),
+                ConsCell::Nil => false,
+            },
+            _ => false,
+        })
+    }
+
+    /// Prints EXPR if AST dumping is enabled.
+    fn dump(&self, expr: &Expr) {
+        if self.dump_ast {
+            print!("{}", expr.dump());
+        }
+    }
+
+    /// Returns the sorted names of every binding visible from the
+    /// global enviroment that start with PREFIX. Used by the repl for
+    /// tab completion.
+    pub fn completions(&self, prefix: &str) -> Vec<String> {
+        self.global_env.borrow().completions(prefix)
+    }
+
     /// Evaluates an expression in the given enviroment.
     pub fn eval_in_env(expr: &LustData, env: Rc<RefCell<LustEnv>>) -> Result<LustData, String> {
-        // The current enviroment we're evaluating in.
-        let currentenv = env;
-        let currexpr = Self::macroexpand(expr.clone(), currentenv.clone())?;
+        // Everything other than symbols and lists evaluates to itself.
+        if !matches!(expr, LustData::Symbol(_) | LustData::Cons(_)) {
+            return Ok(expr.clone());
+        }
+        let call = if Self::is_named_call(expr) {
+            Some(expr)
+        } else {
+            None
+        };
+        Self::nested(call, || Self::eval_here(expr, env))
+    }
 
-        Self::eval_expanded(currexpr, currentenv)
+    /// Evaluates EXPR in ENV without nesting the evaluation. Used to
+    /// evaluate calls whose nesting has already been accounted for.
+    pub(crate) fn eval_here(
+        expr: &LustData,
+        env: Rc<RefCell<LustEnv>>,
+    ) -> Result<LustData, String> {
+        Self::macroexpand(expr.clone(), env.clone())
+            .and_then(|currexpr| Self::eval_expanded(currexpr, env))
+    }
+
+    /// Runs EVAL one level deeper in the evaluation, failing if that
+    /// is deeper than the maximum depth. If the evaluation is of
+    /// CALL, a call to a named function, it is recorded in the call
+    /// stack for error traces.
+    pub(crate) fn nested<T, F>(call: Option<&LustData>, eval: F) -> Result<T, String>
+    where
+        F: FnOnce() -> Result<T, String>,
+    {
+        let res = Self::enter_nested(call).and_then(|()| eval());
+        Self::leave_nested(call.is_some(), res.is_err());
+        res
+    }
+
+    /// Starts evaluating one level deeper, as `nested` does. Errors if
+    /// that is deeper than the maximum depth. The level is entered
+    /// either way and must be left with `leave_nested`.
+    pub(crate) fn enter_nested(call: Option<&LustData>) -> Result<(), String> {
+        if let Some(call) = call {
+            CALL_STACK.with(|s| s.borrow_mut().push(call.clone()));
+        }
+        let depth = DEPTH.with(|d| {
+            d.set(d.get() + 1);
+            d.get()
+        });
+        if depth > MAX_DEPTH.with(Cell::get) {
+            Err("maximum recursion depth exceeded".to_string())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Leaves a level entered with `enter_nested`. IS_CALL is set if
+    /// the level was entered with a call and FAILED if it is being
+    /// left with an error, in which case the call stack is recorded
+    /// for the error's trace.
+    pub(crate) fn leave_nested(is_call: bool, failed: bool) {
+        DEPTH.with(|d| d.set(d.get() - 1));
+        if is_call {
+            CALL_STACK.with(|s| {
+                let mut stack = s.borrow_mut();
+                if failed {
+                    ERROR_TRACE.with(|t| {
+                        t.borrow_mut()
+                            .get_or_insert_with(|| stack.iter().map(Self::describe_call).collect());
+                    });
+                }
+                stack.pop();
+            });
+        }
+    }
+
+    /// Determines if EXPR is a call to a function named by a symbol.
+    pub(crate) fn is_named_call(expr: &LustData) -> bool {
+        match expr {
+            LustData::Cons(ref c) => match **c {
+                ConsCell::Cons(ref cell) => matches!(cell.data, LustData::Symbol(_)),
+                ConsCell::Nil => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// Describes a call for the call stack. For example, `(fact (sub n
+    /// 1))` is described as `fact (1 argument)`.
+    fn describe_call(call: &LustData) -> String {
+        if let LustData::Cons(ref c) = call {
+            if let ConsCell::Cons(ref cell) = **c {
+                let argc = cell.next.len();
+                let plural = if argc == 1 { "" } else { "s" };
+                return format!("{} ({} argument{})", cell.data, argc, plural);
+            }
+        }
+        format!("{}", call)
     }
 
     /// Evaluates an expanded expression. Expanded meaning that
@@ -67,15 +918,24 @@ impl Interpreter {
         mut currexpr: LustData,
         mut currentenv: Rc<RefCell<LustEnv>>,
     ) -> Result<LustData, String> {
-        loop {
+        // The number of traced calls made by this loop that have not
+        // yet returned.
+        let mut traced = 0;
+        let res = loop {
+            if Self::timed_out() {
+                break Err("evaluation timeout".to_string());
+            }
             match currexpr {
-                LustData::Symbol(ref s) => break currentenv.borrow().resolve(s),
+                LustData::Symbol(s) => break currentenv.borrow().resolve_symbol(s),
 
                 LustData::Cons(ref c) => {
                     match **c {
                         ConsCell::Nil => break Ok(currexpr),
                         ConsCell::Cons(ref c) => {
-                            let fnres = Self::eval_cons(c, currentenv)?;
+                            let fnres = match Self::eval_cons(c, currentenv) {
+                                Ok(r) => r,
+                                Err(e) => break Err(e),
+                            };
                             match fnres {
                                 CallResult::Ret(v) => break Ok(v),
                                 // If this is a call of a user-defined
@@ -84,10 +944,19 @@ impl Interpreter {
                                 // that we're evlauting with the returned
                                 // ones.
                                 CallResult::Call(env, expr) => {
+                                    if Self::tracing() {
+                                        traced += 1;
+                                        if let Err(e) = Self::trace_call(&c.data, &env) {
+                                            break Err(e);
+                                        }
+                                    }
                                     currentenv = env;
                                     // Need to expand if the new expression is
                                     // a macro
-                                    currexpr = Self::macroexpand(expr, currentenv.clone())?;
+                                    currexpr = match Self::macroexpand(expr, currentenv.clone()) {
+                                        Ok(e) => e,
+                                        Err(e) => break Err(e),
+                                    };
                                 }
                             }
                         }
@@ -96,51 +965,150 @@ impl Interpreter {
 
                 _ => break Ok(currexpr),
             }
+        };
+        if traced > 0 {
+            Self::trace_returns(traced, &res)?;
         }
+        res
     }
 
-    /// Determines if an expression is a call to a macro.
-    fn is_macro_call(ast: &LustData, env: Rc<RefCell<LustEnv>>) -> bool {
-        if let LustData::Cons(c) = ast {
-            if c.len() == 0 {
-                return false;
-            }
-            let pred = &c[0];
-            match pred {
-                LustData::Symbol(ref s) => match env.borrow().resolve(s) {
-                    Ok(data) => {
-                        if let LustData::Mac(_) = data {
-                            true
-                        } else {
-                            false
-                        }
-                    }
-                    Err(_) => false,
-                },
-                LustData::Mac(_) => true,
-                _ => false,
+    /// Gets the location of the top level expression being evaluated
+    /// if there is one.
+    pub fn current_location() -> Option<Location> {
+        LOCATION.with(|l| l.borrow().clone())
+    }
+
+    /// Determines if the interpreter doing the evaluating is tracing
+    /// calls.
+    pub(crate) fn tracing() -> bool {
+        CONTEXT.with(|c| c.borrow().as_ref().is_some_and(|c| c.trace))
+    }
+
+    /// Prints a call to FUNC with the arguments bound in ENV and
+    /// indents the trace for calls that it makes.
+    fn trace_call(func: &LustData, env: &Rc<RefCell<LustEnv>>) -> Result<(), String> {
+        let depth = TRACE_DEPTH.with(|d| d.replace(d.get() + 1));
+        let mut line = format!("{}({}", "  ".repeat(depth), func);
+        for (_, arg) in env.borrow().bindings() {
+            line.push_str(&format!(" {}", arg));
+        }
+        line.push_str(")\n");
+        Self::write_output(&line)
+    }
+
+    /// Prints the values returned by the last COUNT traced calls. All
+    /// of them return RES as they were tail calls of each other.
+    fn trace_returns(count: usize, res: &Result<LustData, String>) -> Result<(), String> {
+        for _ in 0..count {
+            let depth = TRACE_DEPTH.with(|d| {
+                d.set(d.get().saturating_sub(1));
+                d.get()
+            });
+            if let Ok(ref v) = res {
+                Self::write_output(&format!("{}=> {}\n", "  ".repeat(depth), v))?;
             }
-        } else {
-            false
         }
+        Ok(())
+    }
+
+    /// Determines if the timeout for the evaluation in progress has
+    /// passed.
+    pub(crate) fn timed_out() -> bool {
+        TIMED_OUT.with(|t| match *t.borrow() {
+            Some(ref flag) => flag.load(Ordering::Relaxed),
+            None => false,
+        })
+    }
+
+    /// Gets the macro that an expression calls if it is a call to a
+    /// macro.
+    fn called_macro(ast: &LustData, env: &Rc<RefCell<LustEnv>>) -> Option<Rc<LustFn>> {
+        let c = match ast {
+            LustData::Cons(c) if c.len() > 0 => c,
+            _ => return None,
+        };
+        match &c[0] {
+            LustData::Symbol(s) => match env.borrow().get_symbol(*s) {
+                Some(LustData::Mac(m)) => Some(m),
+                _ => None,
+            },
+            LustData::Mac(m) => Some(m.clone()),
+            _ => None,
+        }
+    }
+
+    /// Expands AST, a call to MAC, once. Expansions are cached so
+    /// evaluating the same call again does not call the macro again.
+    fn expand_call(
+        ast: LustData,
+        mac: &Rc<LustFn>,
+        env: &Rc<RefCell<LustEnv>>,
+    ) -> Result<LustData, String> {
+        let call = match ast {
+            LustData::Cons(ref c) => c.clone(),
+            _ => return Self::eval_expanded(ast, env.clone()),
+        };
+        if let Some(expansion) = EXPANSIONS.with(|e| e.borrow().get(&call, mac)) {
+            return Ok(expansion);
+        }
+        let expansion = Self::eval_expanded(ast, env.clone())?;
+        EXPANSIONS.with(|e| e.borrow_mut().insert(&call, mac, expansion.clone()));
+        Ok(expansion)
     }
 
     /// Expands an expression if it is a macro.
     pub fn macroexpand(mut ast: LustData, env: Rc<RefCell<LustEnv>>) -> Result<LustData, String> {
+        let limit = MAX_EXPANSIONS.with(Cell::get);
+        let mut expansions = 0;
         loop {
-            if !Self::is_macro_call(&ast, env.clone()) {
-                break Ok(ast);
+            let mac = match Self::called_macro(&ast, &env) {
+                Some(m) => m,
+                None => break Ok(ast),
+            };
+            if expansions == limit {
+                break Err("macro expansion limit exceeded".to_string());
             }
-            ast = Self::eval_expanded(ast, env.clone())?;
+            expansions += 1;
+            ast = Self::expand_call(ast, &mac, &env)?;
+        }
+    }
+
+    /// Expands an expression once if it is a macro call. The result
+    /// may be another macro call.
+    pub fn macroexpand_1(ast: LustData, env: Rc<RefCell<LustEnv>>) -> Result<LustData, String> {
+        match Self::called_macro(&ast, &env) {
+            Some(mac) => Self::expand_call(ast, &mac, &env),
+            None => Ok(ast),
         }
     }
 
     fn eval_cons(cons: &Cons, env: Rc<RefCell<LustEnv>>) -> Result<CallResult, String> {
-        let pred = Self::eval_in_env(&cons.data, env.clone())?;
+        // Most calls are to a function named by a symbol. Resolving it
+        // directly skips going through evaluation for each call, which
+        // adds up in loops. It is still resolved on every call so that
+        // redefining the function takes effect.
+        let pred = match cons.data {
+            LustData::Symbol(s) => env.borrow().resolve_symbol(s)?,
+            ref pred => Self::eval_in_env(pred, env.clone())?,
+        };
         match pred {
-            LustData::Builtin(ref f) => f(&*cons.next, env),
-            LustData::Fn(ref f) => Self::eval_funcall(f, &*cons.next, env, true),
-            LustData::Mac(ref f) => Self::eval_funcall(f, &*cons.next, env, false),
+            LustData::Builtin(ref f) => (f.func)(&*cons.next, env),
+            LustData::Fn(ref f) => Self::eval_funcall(f, &*cons.next, env),
+            LustData::Mac(ref f) => Self::eval_macro_call(f, &*cons.next),
+            LustData::Memo(ref m) => {
+                let mut args = Vec::with_capacity(cons.next.len());
+                for arg in &*cons.next {
+                    args.push(Self::eval_in_env(arg, env.clone())?);
+                }
+                Ok(CallResult::Ret(m.call(args, &env)?))
+            }
+            LustData::NativeFn(ref f) => {
+                let mut args = Vec::with_capacity(cons.next.len());
+                for arg in &*cons.next {
+                    args.push(Self::eval_in_env(arg, env.clone())?);
+                }
+                f(&args, env)
+            }
             _ => Err(format!("invalid list predicate: {}", pred)),
         }
     }
@@ -151,68 +1119,58 @@ impl Interpreter {
         func: &LustFn,
         args: &ConsCell,
         env: Rc<RefCell<LustEnv>>,
-        eval_args: bool,
     ) -> Result<CallResult, String> {
-        if (func.is_varadic() && args.len() < func.get_min_param_count())
-            || (!func.is_varadic() && args.len() != func.params.len())
-        {
-            if func.is_varadic() {
-                Err(format!(
-                    "wrong number of arguments for function call. got {} and expected at least {}",
-                    args.len(),
-                    func.params.len() - 1 // Minus one to offset for & argument
-                ))
-            } else {
-                Err(format!(
-                    "wrong number of arguments for function call. got {} and expected {}",
-                    args.len(),
-                    func.get_min_param_count()
-                ))
-            }
-        } else {
-            let fnenv = LustEnv::new();
+        let mut values = Vec::with_capacity(args.len());
+        for arg in args {
+            values.push(Self::eval_in_env(arg, env.clone())?);
+        }
+        let values = func.keyword_args(values)?;
+        func.check_arity(values.len())?;
+        if let Some(compiled) = &func.compiled {
+            return Ok(CallResult::Ret(compile::call(func, compiled, values)?));
+        }
+        Ok(Self::bind_args(func, values))
+    }
 
-            for (i, param) in func.params.iter().enumerate() {
-                if param == "&" {
-                    let bind = func.params[i + 1].clone();
-                    let val = if i >= args.len() {
-                        LustData::get_empty_list()
-                    } else {
-                        let varadic_args = args.nth_item(i);
-                        LustData::Cons(Rc::new(varadic_args.transform_fallible(
-                            |item: &LustData| {
-                                if eval_args {
-                                    Self::eval_in_env(&item, env.clone())
-                                } else {
-                                    Ok(item.clone())
-                                }
-                            },
-                        )?))
-                    };
-                    fnenv.borrow_mut().insert(bind, val);
-                    break;
-                }
-                let arg = if eval_args {
-                    Self::eval_in_env(&args[i], env.clone())?
-                } else {
-                    args[i].clone()
-                };
-                fnenv.borrow_mut().insert(param.clone(), arg);
-            }
+    /// Calls the macro FUNC with ARGS. Macros are passed their
+    /// arguments as they were written, without evaluating them.
+    fn eval_macro_call(func: &LustFn, args: &ConsCell) -> Result<CallResult, String> {
+        func.check_arity(args.len())?;
+        Ok(Self::bind_args(func, args.into_iter().cloned().collect()))
+    }
 
-            fnenv.borrow_mut().outer = Some(func.env.clone());
-            Ok(CallResult::Call(fnenv, func.body.clone()))
+    /// Binds FUNC's parameters to ARGS and returns the call to FUNC's
+    /// body.
+    fn bind_args(func: &LustFn, args: Vec<LustData>) -> CallResult {
+        // Builtins are found through the function's enviroment so
+        // there is no need to install them here. Doing so would
+        // also leak builtins into sandboxed interpreters.
+        let fnenv = LustEnv::new_child(func.env.clone());
+
+        let mut args = args.into_iter();
+        for (i, param) in func.params.iter().enumerate() {
+            if param == "&" {
+                let bind = func.param_ids[i + 1];
+                let val = LustData::list(args.collect());
+                fnenv.borrow_mut().insert_symbol(bind, val);
+                break;
+            }
+            fnenv
+                .borrow_mut()
+                .insert_symbol(func.param_ids[i], args.next().unwrap());
         }
+
+        CallResult::Call(fnenv, func.body.clone())
     }
 }
 
 impl Expr {
-    fn to_data(&self) -> Result<LustData, String> {
+    pub(crate) fn to_data(&self) -> Result<LustData, String> {
         match &self.val {
             ExprVal::Number(f) => Ok(LustData::Number(*f)),
             ExprVal::List(ref l) => Self::list_to_cons(l),
             ExprVal::String(s) => Ok(LustData::from_string(s)),
-            ExprVal::Id(s) => Ok(LustData::Symbol(Box::new(s.clone()))),
+            ExprVal::Id(s) => Ok(LustData::from_id(s)),
         }
     }
 
@@ -256,18 +1214,146 @@ pub enum LustData {
     /// A cons cell
     Cons(Rc<ConsCell>),
     /// A symbol. Used to represent IDs and files in import
-    /// expressions.
-    Symbol(Box<String>),
+    /// expressions. Holds the symbol's interned id, see
+    /// `LustData::symbol`.
+    Symbol(usize),
+    /// A keyword, written `:name`. Keywords evaluate to themselves. In
+    /// a call, a keyword that names one of the function's parameters
+    /// passes the argument after it as that parameter, as in `(f :x
+    /// 1)`. Holds the interned id of the name without the colon.
+    Keyword(usize),
     /// A character. The building block of a string.
     Char(char),
     /// A builtin function.
-    Builtin(fn(&ConsCell, Rc<RefCell<LustEnv>>) -> Result<CallResult, String>),
+    Builtin(BuiltinFn),
     /// A user defined function.
-    Fn(Box<LustFn>),
+    Fn(Rc<LustFn>),
     /// A user defined macro. Macros differ from functions in that
     /// their arguments are implicitly quoted and that they are
     /// evlauted at compile time.
-    Mac(Box<LustFn>),
+    Mac(Rc<LustFn>),
+    /// A delayed computation created by `delay`.
+    Promise(Rc<RefCell<PromiseState>>),
+    /// A port that characters can be read from or written to.
+    Port(Rc<RefCell<Port>>),
+    /// A function whose results are remembered, created by
+    /// `memoize`.
+    Memo(Rc<Memo>),
+    /// An instance of a record type declared with `defrecord` or
+    /// `define-record-type`.
+    Record(Rc<Record>),
+    /// A hash table created by `make-hash-table`.
+    Map(Rc<RefCell<Map>>),
+    /// A Rust value passed to Lust by the program embedding it, see
+    /// `Interpreter::make_opaque`.
+    Opaque(Rc<dyn Any>),
+    /// A Rust closure installed by the program embedding Lust, see
+    /// `Interpreter::register_fn`.
+    NativeFn(Rc<NativeFn>),
+}
+
+/// A Rust closure that can be called from Lust. Unlike builtins, it is
+/// passed its arguments already evaluated.
+pub type NativeFn = dyn Fn(&[LustData], Rc<RefCell<LustEnv>>) -> Result<CallResult, String>;
+
+/// A record. Records are compared by their type's name and fields and
+/// their values.
+#[derive(Clone, PartialEq)]
+pub struct Record {
+    /// The name of the record type, for example `point`.
+    pub name: String,
+    pub fields: Rc<Vec<String>>,
+    /// The value of each field, in the order of FIELDS. Changed by the
+    /// modifiers `define-record-type` makes.
+    pub values: RefCell<Vec<LustData>>,
+}
+
+/// A hash table. Not all data can be hashed so entries are kept in
+/// the order they were added and searched for.
+pub struct Map {
+    pub entries: Vec<(LustData, LustData)>,
+    /// A function of two keys that returns true if they are the
+    /// same. Keys are compared with `eq` if there is none.
+    pub comparator: Option<LustData>,
+}
+
+/// An in-memory port.
+pub enum Port {
+    /// Reads characters from a string. POS is the index of the next
+    /// character to be read.
+    Input { chars: Vec<char>, pos: usize },
+    /// Collects the characters written to it.
+    Output(String),
+}
+
+/// A function implemented in Rust along with the name it was
+/// installed under.
+#[derive(Clone, Copy)]
+pub struct BuiltinFn {
+    pub name: &'static str,
+    /// The builtin's parameters. For example, `item list` for `cons`.
+    pub signature: &'static str,
+    pub func: fn(&ConsCell, Rc<RefCell<LustEnv>>) -> Result<CallResult, String>,
+}
+
+impl PartialEq for BuiltinFn {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && std::ptr::fn_addr_eq(self.func, other.func)
+    }
+}
+
+/// The state of a promise. Promises start out unevaluated and are
+/// evaluated at most once when they are forced.
+pub enum PromiseState {
+    /// An expression waiting to be evaluated in an enviroment.
+    Unevaluated(LustData, Rc<RefCell<LustEnv>>),
+    /// The value the expression evaluated to.
+    Forced(LustData),
+}
+
+/// A function wrapped by `memoize`. Calls with arguments equal to
+/// those of an earlier call return the earlier result.
+pub struct Memo {
+    pub func: LustData,
+    /// Arguments and the results of calling FUNC with them, oldest
+    /// first.
+    cache: RefCell<VecDeque<(Vec<LustData>, LustData)>>,
+    /// The most results to remember. The oldest is forgotten when
+    /// there are more.
+    limit: Option<usize>,
+}
+
+impl Memo {
+    pub fn new(func: LustData, limit: Option<usize>) -> Self {
+        Self {
+            func,
+            cache: RefCell::new(VecDeque::new()),
+            limit,
+        }
+    }
+
+    /// Calls the memoized function with ARGS unless it has already
+    /// been called with them. Builtins are called in GLOBALS.
+    pub(crate) fn call(
+        &self,
+        args: Vec<LustData>,
+        globals: &Rc<RefCell<LustEnv>>,
+    ) -> Result<LustData, String> {
+        if let Some((_, res)) = self.cache.borrow().iter().find(|(a, _)| *a == args) {
+            return Ok(res.clone());
+        }
+        let res = compile::apply(self.func.clone(), args.clone(), globals)?;
+        // The function may have called itself with the same
+        // arguments in which case they are already cached.
+        let mut cache = self.cache.borrow_mut();
+        if !cache.iter().any(|(a, _)| *a == args) {
+            cache.push_back((args, res.clone()));
+            if self.limit.is_some_and(|limit| cache.len() > limit) {
+                cache.pop_front();
+            }
+        }
+        Ok(res)
+    }
 }
 
 impl Default for LustData {
@@ -281,41 +1367,102 @@ pub struct LustFn {
     pub params: Vec<String>,
     pub body: LustData,
     pub env: Rc<RefCell<LustEnv>>,
+    /// Documentation given when the function was declared.
+    pub doc: Option<String>,
+    /// The name the function was first bound to. Anonymous
+    /// functions have no name.
+    pub name: Option<String>,
+    /// The location of the top level expression that created the
+    /// function.
+    pub defined_at: Option<Location>,
+    /// Metadata attached with `with-meta`.
+    pub meta: Option<LustData>,
+    /// Does the parameter list contain `&`? Computed once in
+    /// `LustFn::new` as it is checked on every call.
+    varadic: bool,
+    /// The number of arguments the function requires.
+    min_params: usize,
+    /// The interned ids of the parameters, found once in `LustFn::new`
+    /// so that calls don't have to intern them.
+    pub(crate) param_ids: Vec<usize>,
+    /// Set for functions created by compiled code. See the `compile`
+    /// module.
+    pub(crate) compiled: Option<Rc<CompiledFn>>,
 }
 
 pub struct LustEnv {
-    data: Vec<(String, LustData)>,
+    /// The symbols bound in this enviroment, keyed by their interned
+    /// ids, in the order that they were first defined.
+    data: IndexMap<usize, LustData, FxBuildHasher>,
     outer: Option<Rc<RefCell<LustEnv>>>,
 }
 
 impl LustData {
-    pub fn from_string(s: &str) -> LustData {
-        let mut res = Rc::new(ConsCell::Nil);
-        for c in s.chars().rev() {
-            res = Rc::new(ConsCell::push_front(res, LustData::Char(c)))
+    /// Makes a symbol named NAME.
+    pub fn symbol(name: &str) -> LustData {
+        LustData::Symbol(intern(name))
+    }
+
+    /// Makes a keyword named NAME, which doesn't include the colon.
+    pub fn keyword(name: &str) -> LustData {
+        LustData::Keyword(intern(name))
+    }
+
+    /// Makes the data that the identifier ID is read as: a keyword if
+    /// it starts with a colon and a symbol otherwise.
+    pub fn from_id(id: &str) -> LustData {
+        match id.strip_prefix(':') {
+            Some(name) if !name.is_empty() => LustData::keyword(name),
+            _ => LustData::symbol(id),
         }
+    }
+
+    pub fn from_string(s: &str) -> LustData {
         let mut quote = Rc::new(ConsCell::Nil);
-        quote = Rc::new(ConsCell::push_front(quote, LustData::Cons(res)));
-        quote = Rc::new(ConsCell::push_front(
-            quote,
-            LustData::Symbol(Box::new("quote".to_string())),
-        ));
+        quote = Rc::new(ConsCell::push_front(quote, Self::string_value(s)));
+        quote = Rc::new(ConsCell::push_front(quote, LustData::symbol("quote")));
 
         LustData::Cons(quote)
     }
 
+    /// Builds the list of characters that a string literal evaluates
+    /// to.
+    pub fn string_value(s: &str) -> LustData {
+        Self::list(s.chars().map(LustData::Char).collect())
+    }
+
+    /// Builds a list containing ITEMS.
+    pub fn list(items: Vec<LustData>) -> LustData {
+        let mut res = Rc::new(ConsCell::Nil);
+        for item in items.into_iter().rev() {
+            res = Rc::new(ConsCell::push_front(res, item))
+        }
+        LustData::Cons(res)
+    }
+
     /// Extracts a list from some data or returns an error.
     pub fn expect_cons(&self) -> Result<Rc<ConsCell>, String> {
+        self.as_list().map(Rc::clone)
+    }
+
+    /// Borrows the list held by some data or returns an error.
+    pub fn as_list(&self) -> Result<&Rc<ConsCell>, String> {
         match self {
-            LustData::Cons(ref r) => Ok(r.clone()),
+            LustData::Cons(ref r) => Ok(r),
             _ => Err(format!("expected list, got {}", self)),
         }
     }
 
+    /// Collects the items of a list into a vector or returns an error
+    /// if the data is not a list.
+    pub fn into_list(self) -> Result<Vec<LustData>, String> {
+        Ok(self.as_list()?.into_iter().cloned().collect())
+    }
+
     /// Extracts a symbol from some data or returns an error.
-    pub fn expect_symbol<'a>(&'a self) -> Result<&'a String, String> {
+    pub fn expect_symbol(&self) -> Result<&'static str, String> {
         match self {
-            LustData::Symbol(ref s) => Ok(s),
+            LustData::Symbol(s) => Ok(resolve_intern(*s)),
             _ => Err(format!("expected symbol, got {}", self)),
         }
     }
@@ -350,6 +1497,33 @@ impl LustData {
         }
     }
 
+    /// Returns true if this and OTHER are the same value. Numbers,
+    /// symbols, and characters are the same if they are equal, the
+    /// empty list is the same as itself, and everything else is only
+    /// the same as itself. Unlike `==` this never walks a list.
+    pub fn identical(&self, other: &LustData) -> bool {
+        match (self, other) {
+            (LustData::Number(l), LustData::Number(r)) => l == r,
+            (LustData::Symbol(l), LustData::Symbol(r)) => l == r,
+            (LustData::Keyword(l), LustData::Keyword(r)) => l == r,
+            (LustData::Char(l), LustData::Char(r)) => l == r,
+            (LustData::Cons(l), LustData::Cons(r)) => {
+                Rc::ptr_eq(l, r) || (self.is_empty_list() && other.is_empty_list())
+            }
+            (LustData::Builtin(l), LustData::Builtin(r)) => l == r,
+            (LustData::Fn(l), LustData::Fn(r)) => Rc::ptr_eq(l, r),
+            (LustData::Mac(l), LustData::Mac(r)) => Rc::ptr_eq(l, r),
+            (LustData::Promise(l), LustData::Promise(r)) => Rc::ptr_eq(l, r),
+            (LustData::Port(l), LustData::Port(r)) => Rc::ptr_eq(l, r),
+            (LustData::Memo(l), LustData::Memo(r)) => Rc::ptr_eq(l, r),
+            (LustData::Record(l), LustData::Record(r)) => Rc::ptr_eq(l, r),
+            (LustData::Map(l), LustData::Map(r)) => Rc::ptr_eq(l, r),
+            (LustData::Opaque(l), LustData::Opaque(r)) => Rc::ptr_eq(l, r),
+            (LustData::NativeFn(l), LustData::NativeFn(r)) => Rc::ptr_eq(l, r),
+            (_, _) => false,
+        }
+    }
+
     pub fn deep_clone(&self, mutable: bool) -> LustData {
         match self {
             LustData::Cons(ref c) => LustData::Cons(Rc::new(
@@ -359,6 +1533,72 @@ impl LustData {
         }
     }
 
+    /// Returns a copy of this function, macro, or list with META
+    /// attached. The original keeps its metadata. Lists share their
+    /// items with the original.
+    pub fn with_meta(&self, meta: LustData) -> Result<LustData, String> {
+        match self {
+            LustData::Fn(f) | LustData::Mac(f) => {
+                let mut copy = (**f).clone();
+                copy.meta = Some(meta);
+                Ok(match self {
+                    LustData::Fn(_) => LustData::Fn(Rc::new(copy)),
+                    _ => LustData::Mac(Rc::new(copy)),
+                })
+            }
+            LustData::Cons(c) => {
+                let copy = Rc::new(match **c {
+                    ConsCell::Nil => ConsCell::Nil,
+                    ConsCell::Cons(ref cell) => ConsCell::Cons(Cons {
+                        data: cell.data.clone(),
+                        next: cell.next.clone(),
+                        mutable: cell.mutable,
+                    }),
+                });
+                LIST_METADATA.with(|m| m.borrow_mut().insert(&copy, meta));
+                Ok(LustData::Cons(copy))
+            }
+            _ => Err(format!(
+                "with-meta expected a fn, macro, or list, got {}",
+                self.type_name()
+            )),
+        }
+    }
+
+    /// Gets the metadata attached to this with `with-meta`, if any.
+    pub fn meta(&self) -> Option<LustData> {
+        match self {
+            LustData::Fn(f) | LustData::Mac(f) => f.meta.clone(),
+            LustData::Cons(c) => LIST_METADATA.with(|m| m.borrow().get(c)),
+            _ => None,
+        }
+    }
+
+    /// Gets the name of the type of some data. Lists of characters
+    /// are reported as strings.
+    pub fn type_name(&self) -> &'static str {
+        if self.stringify().is_some() {
+            return "string";
+        }
+        match self {
+            LustData::Number(_) => "number",
+            LustData::Cons(_) => "list",
+            LustData::Symbol(_) => "symbol",
+            LustData::Keyword(_) => "keyword",
+            LustData::Char(_) => "char",
+            LustData::Builtin(_) => "builtin",
+            LustData::Fn(_) => "fn",
+            LustData::Mac(_) => "macro",
+            LustData::Promise(_) => "promise",
+            LustData::Port(_) => "port",
+            LustData::Memo(_) => "memoized fn",
+            LustData::Record(_) => "record",
+            LustData::Map(_) => "hash table",
+            LustData::Opaque(_) => "opaque",
+            LustData::NativeFn(_) => "native fn",
+        }
+    }
+
     pub fn is_imutable(&self) -> bool {
         if let LustData::Cons(ref c) = self {
             c.is_mutable()
@@ -376,11 +1616,12 @@ impl LustData {
                 }
                 let mut res = String::with_capacity(len);
                 for d in c.into_iter() {
-                    let c = match d.expect_char() {
-                        Ok(c) => c,
-                        Err(_) => return None,
-                    };
-                    res.push(c);
+                    // Not expect_char as formatting its error shows
+                    // the item, which may be a large list.
+                    match d {
+                        LustData::Char(c) => res.push(*c),
+                        _ => return None,
+                    }
                 }
                 Some(res)
             }
@@ -390,16 +1631,133 @@ impl LustData {
 }
 
 impl LustFn {
-    pub fn get_min_param_count(&self) -> usize {
-        if self.is_varadic() {
-            self.params.len() - 2
+    /// Makes a function that binds PARAMS when called and evaluates
+    /// BODY in a child of ENV.
+    pub fn new(params: Vec<String>, body: LustData, env: Rc<RefCell<LustEnv>>) -> Self {
+        let varadic = params.iter().rev().any(|i| *i == "&");
+        let min_params = if varadic {
+            params.len() - 2
         } else {
-            self.params.len()
+            params.len()
+        };
+        let param_ids = params.iter().map(|p| intern(p)).collect();
+        Self {
+            params,
+            body,
+            env,
+            doc: None,
+            name: None,
+            defined_at: None,
+            varadic,
+            min_params,
+            param_ids,
+            meta: None,
+            compiled: None,
         }
     }
 
+    /// Describes the function for errors about calling it.
+    fn describe(&self) -> String {
+        match (&self.name, &self.defined_at) {
+            (Some(name), Some(loc)) => {
+                format!("{} (defined at {}:{})", name, loc.start.line, loc.start.col)
+            }
+            (Some(name), None) => name.clone(),
+            (None, _) => "function call".to_string(),
+        }
+    }
+
+    /// Checks that ARGC arguments can be passed to the function.
+    pub(crate) fn check_arity(&self, argc: usize) -> Result<(), String> {
+        if (self.varadic && argc >= self.min_params) || (!self.varadic && argc == self.min_params) {
+            return Ok(());
+        }
+        let what = self.describe();
+        if self.varadic {
+            Err(format!(
+                "wrong number of arguments for {}. got {} and expected at least {}",
+                what, argc, self.min_params
+            ))
+        } else {
+            Err(format!(
+                "wrong number of arguments for {}. got {} and expected {}",
+                what,
+                argc,
+                self.params.len()
+            ))
+        }
+    }
+
+    /// Puts the keyword arguments in ARGS in order. A keyword naming
+    /// one of the function's parameters, other than the one after `&`,
+    /// passes the argument after it as that parameter. The other
+    /// arguments fill the parameters that are left in order, so `(f
+    /// :y 2 1)` and `(f 1 2)` are the same call of `(fn (x y) ...)`.
+    /// Returns the arguments as they would be passed without keywords.
+    pub(crate) fn keyword_args(&self, args: Vec<LustData>) -> Result<Vec<LustData>, String> {
+        if !args.iter().any(|a| matches!(a, LustData::Keyword(_))) {
+            return Ok(args);
+        }
+        let named_params = &self.param_ids[..self.min_params];
+        let mut named = vec![None; self.min_params];
+        let mut positional = Vec::with_capacity(args.len());
+        let mut args = args.into_iter().peekable();
+        while let Some(arg) = args.next() {
+            let param = match arg {
+                LustData::Keyword(k) if args.peek().is_some() => {
+                    named_params.iter().position(|p| *p == k)
+                }
+                _ => None,
+            };
+            match param {
+                Some(i) if named[i].is_some() => {
+                    return Err(format!(
+                        "{} got more than one value for {}",
+                        self.describe(),
+                        self.params[i]
+                    ))
+                }
+                Some(i) => named[i] = args.next(),
+                None => positional.push(arg),
+            }
+        }
+        let mut positional = positional.into_iter();
+        let mut res = Vec::with_capacity(named.len());
+        for (i, value) in named.into_iter().enumerate() {
+            match value.or_else(|| positional.next()) {
+                Some(v) => res.push(v),
+                None => {
+                    return Err(format!(
+                        "{} got no value for {}",
+                        self.describe(),
+                        self.params[i]
+                    ))
+                }
+            }
+        }
+        res.extend(positional);
+        Ok(res)
+    }
+
+    /// Gets the expressions in the function's body. There is more
+    /// than one if the body is wrapped in a call to `builtins::BODY`.
+    pub fn body_forms(&self) -> Vec<LustData> {
+        if let LustData::Cons(ref c) = self.body {
+            if let ConsCell::Cons(ref cons) = **c {
+                if cons.data == LustData::Builtin(builtins::BODY) {
+                    return cons.next.into_iter().cloned().collect();
+                }
+            }
+        }
+        vec![self.body.clone()]
+    }
+
+    pub fn get_min_param_count(&self) -> usize {
+        self.min_params
+    }
+
     pub fn is_varadic(&self) -> bool {
-        self.params.iter().rev().any(|i| *i == "&")
+        self.varadic
     }
 }
 
@@ -408,80 +1766,344 @@ impl LustEnv {
         Rc::new(RefCell::new(Self::new_with_defaults()))
     }
 
+    /// Builds an enviroment with only the builtins named in
+    /// ALLOWED. Names may include the system builtins. Names which
+    /// are not builtins are ignored.
+    pub fn new_with_builtins(allowed: &[&str]) -> Rc<RefCell<Self>> {
+        let mut me = Self::new_with_defaults();
+        me.install_system_builtins();
+        me.data
+            .retain(|id, _| allowed.contains(&resolve_intern(*id)));
+        Rc::new(RefCell::new(me))
+    }
+
+    /// Builds an empty enviroment that resolves anything it doesn't
+    /// define in OUTER.
+    pub fn new_child(outer: Rc<RefCell<LustEnv>>) -> Rc<RefCell<Self>> {
+        Rc::new(RefCell::new(Self {
+            data: IndexMap::default(),
+            outer: Some(outer),
+        }))
+    }
+
+    /// Installs FUNC as a builtin called NAME. SIGNATURE describes
+    /// the builtin's parameters for `help`. Optional parameters are
+    /// written in square brackets.
     fn install_builtin(
         &mut self,
-        name: &str,
+        name: &'static str,
+        signature: &'static str,
         func: fn(&ConsCell, Rc<RefCell<LustEnv>>) -> Result<CallResult, String>,
     ) {
-        self.data.push((name.to_string(), LustData::Builtin(func)));
+        let builtin = BuiltinFn {
+            name,
+            signature,
+            func,
+        };
+        self.data.insert(intern(name), LustData::Builtin(builtin));
     }
 
     fn new_with_defaults() -> Self {
         let mut me = Self {
-            data: Vec::new(),
+            data: IndexMap::default(),
             outer: None,
         };
 
-        me.install_builtin("quote", builtins::quote);
-        me.install_builtin("quaziquote", builtins::quaziquote);
-        me.install_builtin("car", builtins::car);
-        me.install_builtin("cdr", builtins::cdr);
-        me.install_builtin("cons", builtins::cons);
-        me.install_builtin("if", builtins::if_);
-        me.install_builtin("eval", builtins::eval);
-        me.install_builtin("let", builtins::let_);
-        me.install_builtin("fn", builtins::fn_);
-        me.install_builtin("error", builtins::error);
-        me.install_builtin("macro", builtins::macro_);
-        me.install_builtin("macroexpand", builtins::macroexpand);
-        me.install_builtin("println", builtins::println_);
-        me.install_builtin("print", builtins::print_);
-        me.install_builtin("import", builtins::import);
-        me.install_builtin("negate", builtins::negate);
-        me.install_builtin("add", builtins::add);
-        me.install_builtin("sub", builtins::sub);
-        me.install_builtin("mul", builtins::mul);
-        me.install_builtin("div", builtins::div);
-        me.install_builtin("lt", builtins::lt);
-        me.install_builtin("gt", builtins::gt);
-        me.install_builtin("eq", builtins::eq);
+        me.install_builtin("quote", "expr", builtins::quote);
+        me.install_builtin("quaziquote", "expr", builtins::quaziquote);
+        me.install_builtin("quasiquote", "expr", builtins::quasiquote);
+        me.install_builtin("car", "list", builtins::car);
+        me.install_builtin("cdr", "list", builtins::cdr);
+        me.install_builtin("caar", "list", builtins::caar);
+        me.install_builtin("cadr", "list", builtins::cadr);
+        me.install_builtin("cdar", "list", builtins::cdar);
+        me.install_builtin("cddr", "list", builtins::cddr);
+        me.install_builtin("caddr", "list", builtins::caddr);
+        me.install_builtin("cdddr", "list", builtins::cdddr);
+        me.install_builtin("first", "list", builtins::first);
+        me.install_builtin("second", "list", builtins::second);
+        me.install_builtin("third", "list", builtins::third);
+        me.install_builtin("rest", "list", builtins::rest);
+        me.install_builtin("car?", "list", builtins::car_p);
+        me.install_builtin("nth", "n list", builtins::nth);
+        me.install_builtin("nth?", "n list", builtins::nth_p);
+        me.install_builtin("cons", "item list", builtins::cons);
+        me.install_builtin("pair", "a b", builtins::pair);
+        me.install_builtin("flatten", "list", builtins::flatten);
+        me.install_builtin("flatten-deep", "list", builtins::flatten_deep);
+        me.install_builtin("take", "n list", builtins::take);
+        me.install_builtin("drop", "n list", builtins::drop_);
+        me.install_builtin("iota", "count [start step]", builtins::iota);
+        me.install_builtin("repeat", "n value", builtins::repeat);
+        me.install_builtin("concatenate", "lists", builtins::concatenate);
+        me.install_builtin("flatten-map", "f list", builtins::flatten_map);
+        me.install_builtin("take-while", "pred list", builtins::take_while);
+        me.install_builtin("find", "pred list", builtins::find);
+        me.install_builtin("find-index", "pred list", builtins::find_index);
+        me.install_builtin("count", "pred list", builtins::count);
+        me.install_builtin("any?", "pred list", builtins::any_p);
+        me.install_builtin("every?", "pred list", builtins::every_p);
+        me.install_builtin("count-if", "pred list", builtins::count_if);
+        me.install_builtin("all?", "pred list", builtins::all_p);
+        me.install_builtin("drop-while", "pred list", builtins::drop_while);
+        me.install_builtin("zip", "list & lists", builtins::zip);
+        me.install_builtin("unzip", "pairs", builtins::unzip);
+        me.install_builtin("sort", "list", builtins::sort);
+        me.install_builtin("sort-by", "compare list", builtins::sort_by);
+        me.install_builtin("partition", "pred list", builtins::partition);
+        me.install_builtin("group-by", "key list", builtins::group_by);
+        me.install_builtin("if", "cond then else", builtins::if_);
+        me.install_builtin("match", "value & clauses", builtins::match_);
+        me.install_builtin("case-lambda", "& clauses", builtins::case_lambda);
+        me.install_builtin("defrecord", "name (fields...)", builtins::defrecord);
+        me.install_builtin("loop", "bindings & body", builtins::loop_);
+        me.install_builtin("recur", "& values", builtins::recur);
+        me.install_builtin("record-set", "record field value", builtins::record_set);
+        me.install_builtin(
+            "define-record-type",
+            "name constructor predicate & fields",
+            builtins::define_record_type,
+        );
+        me.install_builtin("eval", "expr", builtins::eval);
+        me.install_builtin("tail-call?", "expr", builtins::tail_call_p);
+        me.install_builtin("let", "name value", builtins::let_);
+        me.install_builtin("defdynamic", "name value", builtins::defdynamic);
+        me.install_builtin("binding", "((name value)...) & body", builtins::binding);
+        me.install_builtin("fn", "params [doc] body", builtins::fn_);
+        me.install_builtin("error", "message", builtins::error);
+        me.install_builtin("macro", "params [doc] body", builtins::macro_);
+        me.install_builtin("macroexpand", "expr", builtins::macroexpand);
+        me.install_builtin("macroexpand-1", "expr", builtins::macroexpand_1);
+        me.install_builtin("println", "value", builtins::println_);
+        me.install_builtin("print", "value", builtins::print_);
+        me.install_builtin("pretty-print", "value", builtins::pp);
+        me.install_builtin("import", "name", builtins::import);
+        me.install_builtin("module", "name & body", builtins::module);
+        me.install_builtin("negate", "n", builtins::negate);
+        me.install_builtin("add", "a b", builtins::add);
+        me.install_builtin("sub", "a b", builtins::sub);
+        me.install_builtin("mul", "a b", builtins::mul);
+        me.install_builtin("div", "a b", builtins::div);
+        me.install_builtin("mod", "a b", builtins::mod_);
+        me.install_builtin("nan?", "n", builtins::nan_p);
+        me.install_builtin("inf?", "n", builtins::inf_p);
+        me.install_builtin("lt", "a b", builtins::lt);
+        me.install_builtin("gt", "a b", builtins::gt);
+        me.install_builtin("eq", "a b", builtins::eq);
+        me.install_builtin("identical?", "a b", builtins::identical_p);
+        me.install_builtin("equal?", "a b", builtins::equal_p);
+        me.install_builtin("eq?", "a b", builtins::eq_p);
+        me.install_builtin("eqv?", "a b", builtins::eqv_p);
+        me.install_builtin("assert", "expr [message]", builtins::assert);
+        me.install_builtin("assert-eq", "a b", builtins::assert_eq);
+        me.install_builtin("deftest", "name & body", builtins::deftest);
+        me.install_builtin("run-tests", "[prefix]", builtins::run_tests);
+        me.install_builtin("doc", "value", builtins::doc);
+        me.install_builtin("with-meta", "value meta", builtins::with_meta);
+        me.install_builtin("meta", "value", builtins::meta);
+        me.install_builtin("help", "[name]", builtins::help);
+        me.install_builtin("delay", "expr", builtins::delay);
+        me.install_builtin("force", "promise", builtins::force);
+        me.install_builtin("promise?", "value", builtins::promise_p);
+        me.install_builtin("memoize", "f [limit]", builtins::memoize);
+        me.install_builtin(
+            "make-hash-table",
+            "[#:comparator f]",
+            builtins::make_hash_table,
+        );
+        me.install_builtin(
+            "hash-table-set!",
+            "table key value",
+            builtins::hash_table_set,
+        );
+        me.install_builtin(
+            "hash-table-ref",
+            "table key [default]",
+            builtins::hash_table_ref,
+        );
+        me.install_builtin(
+            "hash-table-comparator",
+            "table",
+            builtins::hash_table_comparator,
+        );
+        me.install_builtin(
+            "hash-table-equivalence-function",
+            "table",
+            builtins::hash_table_equivalence_function,
+        );
+        me.install_builtin("open-input-string", "s", builtins::open_input_string);
+        me.install_builtin("open-output-string", "", builtins::open_output_string);
+        me.install_builtin("get-output-string", "port", builtins::get_output_string);
+        me.install_builtin("read-char", "port", builtins::read_char);
+        me.install_builtin("read-line", "port", builtins::read_line);
+        me.install_builtin("read", "source", builtins::read);
+        me.install_builtin("write", "value [port]", builtins::write);
+        me.install_builtin("display", "value [port]", builtins::display);
+        me.install_builtin("from-json", "s", builtins::from_json);
+        me.install_builtin("to-json", "value", builtins::to_json);
+        me.install_builtin("clock", "", builtins::clock);
+        me.install_builtin("time", "expr", builtins::time);
+        me.install_builtin("now", "", builtins::now);
+        me.install_builtin("random", "", builtins::random);
+        me.install_builtin("random-int", "n", builtins::random_int);
+        me.install_builtin("random-seed", "s", builtins::random_seed);
 
         me
     }
 
-    // These functions don't remove old definitions from the
-    // enviroment if a symbol is redefined. Instead, symbols are added
-    // to the back of the enviroment and when resolving something we
-    // resolve back to front.
-    //
-    // This is all based on the assumption that most enviroments are
-    // small and short lived so we're best off keeping overhead for
-    // their creation as small as possible.
+    /// Installs the builtins that give access to the operating
+    /// system: `exit`, `getenv`, and `shell`. These are not installed
+    /// by default so that embedders have to opt in to them.
+    pub fn install_system_builtins(&mut self) {
+        self.install_builtin("exit", "[status]", builtins::exit);
+        self.install_builtin("getenv", "name", builtins::getenv);
+        self.install_builtin("shell", "command", builtins::shell);
+        self.install_builtin("ffi-open", "path", builtins::ffi_open);
+        self.install_builtin("ffi-sym", "library name", builtins::ffi_sym);
+        self.install_builtin("ffi-call", "func ret & args", builtins::ffi_call);
+    }
 
+    /// Gets the value bound to ID. The error suggests names that are
+    /// bound if ID looks like a typo of one.
     pub fn resolve(&self, id: &str) -> Result<LustData, String> {
-        match self.data.iter().rev().find(|x| x.0 == id) {
-            Some(data) => Ok(data.1.clone()),
+        self.get(id).ok_or_else(|| self.unresolved(id))
+    }
+
+    /// Same as `resolve` but takes the interned id of the symbol to
+    /// resolve. This is what the interpreter uses as it saves
+    /// looking up the symbol's name.
+    pub fn resolve_symbol(&self, symbol: usize) -> Result<LustData, String> {
+        self.get_symbol(symbol)
+            .ok_or_else(|| self.unresolved(resolve_intern(symbol)))
+    }
+
+    /// Same as `resolve` but returns None instead of an error. Use
+    /// this to check if a name is bound without building an error.
+    pub fn get(&self, id: &str) -> Option<LustData> {
+        // Names that have never been interned can't be bound.
+        interned(id).and_then(|symbol| self.get_symbol(symbol))
+    }
+
+    /// Same as `get` but takes the interned id of the symbol.
+    pub fn get_symbol(&self, symbol: usize) -> Option<LustData> {
+        match self.data.get(&symbol) {
+            Some(data) => Some(data.clone()),
             None => match self.outer {
-                Some(ref outer) => outer.borrow().resolve(id),
-                None => Err(format!("failed to resolve identifier {}", id)),
+                Some(ref outer) => outer.borrow().get_symbol(symbol),
+                None => None,
             },
         }
     }
 
+    /// Builds the error for failing to resolve ID. Names bound here or
+    /// in an outer enviroment that are within two edits of ID are
+    /// suggested, those differing only in case first. At most three
+    /// are suggested.
+    fn unresolved(&self, id: &str) -> String {
+        let mut candidates: Vec<(bool, usize, String)> = self
+            .completions("")
+            .into_iter()
+            .filter_map(|name| {
+                let distance = edit_distance(id, &name);
+                // Short names are within two edits of most other short
+                // names.
+                if distance > 2 || distance >= id.chars().count() {
+                    return None;
+                }
+                Some((!name.eq_ignore_ascii_case(id), distance, name))
+            })
+            .collect();
+        candidates.sort();
+        let names: Vec<String> = candidates
+            .into_iter()
+            .take(3)
+            .map(|(_, _, name)| format!("`{}`", name))
+            .collect();
+        match names.len() {
+            0 => format!("failed to resolve identifier {}", id),
+            1 => format!(
+                "failed to resolve identifier {}. did you mean {}?",
+                id, names[0]
+            ),
+            n => format!(
+                "failed to resolve identifier {}. did you mean {} or {}?",
+                id,
+                names[..n - 1].join(", "),
+                names[n - 1]
+            ),
+        }
+    }
+
+    /// Binds ID to VAL. Redefining a symbol keeps its original
+    /// position in the enviroment.
     pub fn insert(&mut self, id: String, val: LustData) {
-        self.data.push((id, val.clone()));
+        self.insert_symbol(intern(&id), val);
+    }
+
+    /// Same as `insert` but takes the interned id of the symbol to
+    /// bind.
+    pub fn insert_symbol(&mut self, symbol: usize, val: LustData) {
+        self.data.insert(symbol, val);
     }
 
     pub fn extend(&mut self, other: &Self) {
         self.data.extend(other.data.clone())
     }
+
+    /// Gets the current value of every symbol bound directly in this
+    /// enviroment in the order that they were first defined.
+    pub fn bindings(&self) -> Vec<(String, LustData)> {
+        self.data
+            .iter()
+            .map(|(id, val)| (resolve_intern(*id).to_string(), val.clone()))
+            .collect()
+    }
+
+    /// Collects the names of all the symbols bound in this
+    /// enviroment and its outer enviroments that start with
+    /// PREFIX. The result is sorted and contains no duplicates.
+    pub fn completions(&self, prefix: &str) -> Vec<String> {
+        let mut res: Vec<String> = self
+            .data
+            .keys()
+            .map(|id| resolve_intern(*id))
+            .filter(|name| name.starts_with(prefix))
+            .map(str::to_string)
+            .collect();
+        if let Some(ref outer) = self.outer {
+            res.extend(outer.borrow().completions(prefix));
+        }
+        res.sort();
+        res.dedup();
+        res
+    }
+}
+
+/// The number of single character insertions, deletions, and
+/// substitutions needed to turn A into B.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    // The distances from the prefix of A seen so far to each prefix of
+    // B.
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitute.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
 }
 
 impl PartialEq for LustData {
     fn eq(&self, other: &Self) -> bool {
         match (&self, other) {
             (LustData::Number(l), LustData::Number(r)) => l == r,
-            (LustData::Symbol(ref l), LustData::Symbol(ref r)) => l == r,
+            (LustData::Symbol(l), LustData::Symbol(r)) => l == r,
+            (LustData::Keyword(l), LustData::Keyword(r)) => l == r,
             (LustData::Cons(ref l), LustData::Cons(ref r)) => {
                 l.len() == r.len()
                     && l.into_iter()
@@ -489,6 +2111,21 @@ impl PartialEq for LustData {
                         .all(|(lhs, rhs)| lhs == rhs)
             }
             (LustData::Char(l), LustData::Char(r)) => l == r,
+            // Functions are only equal to themselves. Comparing their
+            // code would make two closures that capture different
+            // values equal.
+            (LustData::Builtin(l), LustData::Builtin(r)) => l == r,
+            (LustData::Fn(l), LustData::Fn(r)) => Rc::ptr_eq(l, r),
+            (LustData::Mac(l), LustData::Mac(r)) => Rc::ptr_eq(l, r),
+            (LustData::Promise(l), LustData::Promise(r)) => Rc::ptr_eq(l, r),
+            (LustData::Port(l), LustData::Port(r)) => Rc::ptr_eq(l, r),
+            (LustData::Memo(l), LustData::Memo(r)) => Rc::ptr_eq(l, r),
+            (LustData::Record(l), LustData::Record(r)) => l == r,
+            // Hash tables can change so they are only equal to
+            // themselves.
+            (LustData::Map(l), LustData::Map(r)) => Rc::ptr_eq(l, r),
+            (LustData::Opaque(l), LustData::Opaque(r)) => Rc::ptr_eq(l, r),
+            (LustData::NativeFn(l), LustData::NativeFn(r)) => Rc::ptr_eq(l, r),
             (_, _) => false,
         }
     }
@@ -509,11 +2146,15 @@ impl fmt::Display for LustData {
 
                 Self::Cons(c) => write!(f, "({})", c),
 
-                Self::Symbol(s) => write!(f, "{}", s),
-                Self::Builtin(_) => write!(f, "<builtin anonymous fn>"),
+                Self::Symbol(s) => write!(f, "{}", resolve_intern(*s)),
+                Self::Keyword(k) => write!(f, ":{}", resolve_intern(*k)),
+                Self::Builtin(b) => write!(f, "<builtin {}>", b.name),
 
                 Self::Fn(func) => {
                     write!(f, "(fn ")?;
+                    if let Some(ref name) = func.name {
+                        write!(f, "{} ", name)?;
+                    }
                     if func.params.is_empty() {
                         write!(f, "()")?;
                     } else {
@@ -523,12 +2164,17 @@ impl fmt::Display for LustData {
                         }
                         write!(f, "{})", func.params[func.params.len() - 1])?;
                     }
-                    write!(f, " {}", func.body)?;
+                    for form in func.body_forms() {
+                        write!(f, " {}", form)?;
+                    }
                     write!(f, ")")
                 }
 
                 Self::Mac(func) => {
                     write!(f, "(macro ")?;
+                    if let Some(ref name) = func.name {
+                        write!(f, "{} ", name)?;
+                    }
                     if func.params.is_empty() {
                         write!(f, "()")?;
                     } else {
@@ -538,14 +2184,53 @@ impl fmt::Display for LustData {
                         }
                         write!(f, "{})", func.params[func.params.len() - 1])?;
                     }
-                    write!(f, " {}", func.body)?;
+                    for form in func.body_forms() {
+                        write!(f, " {}", form)?;
+                    }
                     write!(f, ")")
                 }
+
+                Self::Promise(p) => match *p.borrow() {
+                    PromiseState::Unevaluated(..) => write!(f, "#<promise>"),
+                    PromiseState::Forced(ref v) => write!(f, "#<promise: {}>", v),
+                },
+
+                Self::Port(p) => match *p.borrow() {
+                    Port::Input { .. } => write!(f, "<input port>"),
+                    Port::Output(_) => write!(f, "<output port>"),
+                },
+
+                Self::Memo(m) => write!(f, "<memoized {}>", m.func),
+
+                Self::Map(m) => write!(f, "<hash table of {}>", m.borrow().entries.len()),
+
+                Self::Opaque(_) => write!(f, "<opaque>"),
+
+                Self::NativeFn(_) => write!(f, "<native fn>"),
+
+                Self::Record(r) => {
+                    write!(f, "#{}{{", r.name)?;
+                    for (i, (field, value)) in
+                        r.fields.iter().zip(r.values.borrow().iter()).enumerate()
+                    {
+                        if i > 0 {
+                            write!(f, " ")?;
+                        }
+                        write!(f, "{} {}", field, value)?;
+                    }
+                    write!(f, "}}")
+                }
             }
         }
     }
 }
 
+impl fmt::Debug for LustData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
 impl ConsCell {
     pub fn len(&self) -> usize {
         match self {
@@ -675,3 +2360,584 @@ impl Index<usize> for ConsCell {
         }
     }
 }
+
+/// Calls F with a new interpreter for each engine: the interpreter,
+/// the closure compiler, and the bytecode virtual machine.
+#[cfg(test)]
+pub(crate) fn each_engine(f: impl Fn(Interpreter)) {
+    for engine in 0..3 {
+        let mut evaluator = Interpreter::new();
+        evaluator.set_compile(engine == 1);
+        evaluator.use_bytecode(engine == 2);
+        f(evaluator);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn eval_all(evaluator: &mut Interpreter, source: &str) -> Result<(), String> {
+        let mut parser = Parser::new(source);
+        while parser.has_more() {
+            let res = parser.parse_expr();
+            assert!(res.errors.is_empty());
+            evaluator.eval(&res.expr.unwrap())?;
+        }
+        Ok(())
+    }
+
+    /// Evaluates the expression in SOURCE and shows its value.
+    fn shown(evaluator: &mut Interpreter, source: &str) -> Result<String, String> {
+        let expr = Parser::new(source).parse_expr().expr.unwrap();
+        evaluator.eval_to_data(&expr).map(|res| res.to_string())
+    }
+
+    #[test]
+    fn stack_trace() {
+        let mut evaluator = Interpreter::new();
+        eval_all(
+            &mut evaluator,
+            "(let inner (fn (x) (add x (error \"boom\"))))
+             (let outer (fn () (add 1 (inner 2))))",
+        )
+        .unwrap();
+        assert_eq!(
+            eval_all(&mut evaluator, "(outer)"),
+            Err("\"boom\"
+stack trace:
+  in error (1 argument)
+  in inner (1 argument)
+  in outer (0 arguments)"
+                .to_string())
+        );
+        assert!(Interpreter::call_stack().is_empty());
+
+        eval_all(&mut evaluator, "(outer 1)").unwrap_err();
+        assert!(Interpreter::call_stack().is_empty());
+        assert_eq!(eval_all(&mut evaluator, "(add 1 (add 2 3))"), Ok(()));
+        assert!(Interpreter::call_stack().is_empty());
+    }
+
+    #[test]
+    fn max_depth() {
+        let mut evaluator = Interpreter::new();
+        evaluator.set_max_depth(50);
+        eval_all(
+            &mut evaluator,
+            "(let deep (fn (n) (if (eq n 0) 0 (add 1 (deep (sub n 1))))))
+             (let tail (fn (n) (if (eq n 0) 0 (tail (sub n 1)))))",
+        )
+        .unwrap();
+        assert_eq!(eval_all(&mut evaluator, "(deep 20)"), Ok(()));
+        let e = eval_all(&mut evaluator, "(deep 100)").unwrap_err();
+        assert!(e.starts_with("maximum recursion depth exceeded\nstack trace:\n"));
+        assert_eq!(e.lines().count(), 2 + MAX_TRACE_FRAMES + 1);
+        assert!(e.ends_with(" more"));
+        // Tail calls don't nest.
+        assert_eq!(eval_all(&mut evaluator, "(tail 1000)"), Ok(()));
+        assert_eq!(DEPTH.with(Cell::get), 0);
+    }
+
+    #[test]
+    fn mutual_tail_calls() {
+        // Tail calls between different functions don't nest either,
+        // interpreted, compiled, or run as bytecode.
+        each_engine(|mut evaluator| {
+            evaluator.set_max_depth(50);
+            eval_all(
+                &mut evaluator,
+                "(let is-even (fn (n) (if (eq n 0) 'yes (is-odd (sub n 1)))))
+                 (let is-odd (fn (n) (if (eq n 0) () (is-even (sub n 1)))))
+                 (let res (is-even 100000))",
+            )
+            .unwrap();
+            let res = evaluator.global_env.borrow().resolve("res");
+            assert_eq!(res, Ok(LustData::symbol("yes")));
+        });
+    }
+
+    #[test]
+    fn timeout() {
+        let mut evaluator = Interpreter::new();
+        eval_all(&mut evaluator, "(let forever (fn () (forever)))").unwrap();
+
+        let mut parser = Parser::new("(forever)");
+        let expr = parser.parse_expr().expr.unwrap();
+        let res = evaluator.eval_with_timeout(&expr, Duration::from_millis(50));
+        assert!(res.unwrap_err().starts_with("evaluation timeout"));
+
+        let mut parser = Parser::new("(add 1 2)");
+        let expr = parser.parse_expr().expr.unwrap();
+        let res = evaluator.eval_with_timeout(&expr, Duration::from_secs(10));
+        assert_eq!(res, Ok(()));
+        assert!(!Interpreter::timed_out());
+    }
+
+    #[test]
+    fn sandboxed() {
+        let mut evaluator = Interpreter::new_sandboxed();
+        eval_all(&mut evaluator, "(let f (fn (x) (add x 1)))").unwrap();
+        eval_all(&mut evaluator, "(f 1)").unwrap();
+        assert!(eval_all(&mut evaluator, "(println 1)").is_err());
+        // Builtins must not be reinstalled when functions are called.
+        let e = eval_all(&mut evaluator, "(let g (fn () (println 1))) (g)").unwrap_err();
+        assert!(e.starts_with("failed to resolve identifier println"));
+        // help prints so it isn't available either.
+        let e = eval_all(&mut evaluator, "(help)").unwrap_err();
+        assert!(e.starts_with("failed to resolve identifier help"));
+
+        let mut evaluator = Interpreter::new_with_builtins(&["quote", "add", "getenv"]);
+        eval_all(&mut evaluator, "(add 1 2)").unwrap();
+        assert!(eval_all(&mut evaluator, "(getenv \"HOME\")").is_ok());
+        assert!(eval_all(&mut evaluator, "(car '(1))").is_err());
+    }
+
+    #[test]
+    fn time_with_fake_clock() {
+        let mut evaluator = Interpreter::new();
+        crate::interpret_source(include_str!("../std/std.lisp"), "std.lisp", &mut evaluator)
+            .unwrap();
+        let ticks = Cell::new(0.0);
+        evaluator.set_clock(Box::new(move || {
+            ticks.set(ticks.get() + 0.25);
+            ticks.get()
+        }));
+        let output = Rc::new(RefCell::new(Vec::new()));
+        evaluator.set_output(output.clone());
+        let diagnostics = Rc::new(RefCell::new(Vec::new()));
+        evaluator.set_diagnostics(diagnostics.clone());
+
+        // The time goes to the diagnostics rather than the output.
+        let expr = Parser::new("(time (add 1 2))").parse_expr().expr.unwrap();
+        assert_eq!(evaluator.eval_to_data(&expr), Ok(LustData::Number(3.0)));
+        assert!(output.borrow().is_empty());
+        assert_eq!(
+            String::from_utf8(diagnostics.borrow().clone()).unwrap(),
+            "elapsed: 250 ms\n"
+        );
+    }
+
+    #[test]
+    fn std_tail_calls() {
+        each_engine(|mut evaluator| {
+            crate::interpret_source(include_str!("../std/std.lisp"), "std.lisp", &mut evaluator)
+                .unwrap();
+            // Both loops run deeper than the default depth limit, which
+            // they would hit if cond and when didn't leave the
+            // recursive call in tail position.
+            let source = "(let count-down (fn (n acc)
+                            (cond
+                              ((eq n 0) acc)
+                              ((eq (mod n 2) 0) (count-down (sub n 1) (add acc 2)))
+                              (#t (count-down (sub n 1) (add acc 1))))))";
+            eval_all(&mut evaluator, source).unwrap();
+            let expr = Parser::new("(count-down 20000 0)")
+                .parse_expr()
+                .expr
+                .unwrap();
+            assert_eq!(evaluator.eval_to_data(&expr), Ok(LustData::Number(30000.0)));
+            let source = "(let last (fn (lst) (when (cdr lst) (last (cdr lst)))))";
+            eval_all(&mut evaluator, source).unwrap();
+            let expr = Parser::new("(last (iota 20000))")
+                .parse_expr()
+                .expr
+                .unwrap();
+            assert_eq!(
+                evaluator.eval_to_data(&expr),
+                Ok(LustData::get_empty_list())
+            );
+        });
+    }
+
+    #[test]
+    fn opaque_values() {
+        struct Handle(u32);
+        let mut evaluator = Interpreter::new();
+        let handle = Interpreter::make_opaque(Handle(7));
+        evaluator
+            .global_env
+            .borrow_mut()
+            .insert("handle".to_string(), handle.clone());
+        evaluator
+            .global_env
+            .borrow_mut()
+            .insert("other".to_string(), Interpreter::make_opaque(Handle(7)));
+        eval_all(&mut evaluator, "(let id (fn (x) x))").unwrap();
+
+        let expr = Parser::new("(car (cons (id handle) ()))")
+            .parse_expr()
+            .expr
+            .unwrap();
+        let res = evaluator.eval_to_data(&expr).unwrap();
+        assert_eq!(Interpreter::extract_opaque::<Handle>(&res).unwrap().0, 7);
+        assert!(Interpreter::extract_opaque::<String>(&res).is_none());
+        assert!(Interpreter::extract_opaque::<Handle>(&LustData::Number(7.0)).is_none());
+        assert_eq!(res.to_string(), "<opaque>");
+        assert_eq!(res.type_name(), "opaque");
+
+        assert_eq!(
+            shown(&mut evaluator, "(eq handle (id handle))").unwrap(),
+            "#t"
+        );
+        assert_eq!(shown(&mut evaluator, "(eq handle other)").unwrap(), "()");
+    }
+
+    #[test]
+    fn register_fn() {
+        let calls = Rc::new(Cell::new(0));
+        let register = |evaluator: &mut Interpreter| {
+            let counted = calls.clone();
+            evaluator.register_fn("count", move |args, _env| {
+                counted.set(counted.get() + 1);
+                Ok(CallResult::Ret(LustData::Number(args.len() as f32)))
+            });
+        };
+        each_engine(|mut evaluator| {
+            register(&mut evaluator);
+            eval_all(&mut evaluator, "(let twice (fn (f x) (f x x)))").unwrap();
+            // Arguments are evaluated before they are passed.
+            assert_eq!(shown(&mut evaluator, "(count (add 1 2) 'a)").unwrap(), "2");
+            assert_eq!(shown(&mut evaluator, "(twice count 1)").unwrap(), "2");
+        });
+        assert_eq!(calls.get(), 6);
+
+        let mut evaluator = Interpreter::new();
+        register(&mut evaluator);
+        assert_eq!(shown(&mut evaluator, "count").unwrap(), "<native fn>");
+        assert_eq!(shown(&mut evaluator, "(eq count count)").unwrap(), "#t");
+
+        evaluator.register_fn("fail", |_, _| Err("failed".to_string()));
+        assert!(shown(&mut evaluator, "(fail)")
+            .unwrap_err()
+            .starts_with("failed"));
+
+        evaluator.reset();
+        assert_eq!(shown(&mut evaluator, "(count)").unwrap(), "0");
+        assert_eq!(calls.get(), 7);
+    }
+
+    #[test]
+    fn reset() {
+        let mut evaluator = Interpreter::new();
+        eval_all(&mut evaluator, "(let x 1)").unwrap();
+        evaluator.reset();
+        assert!(eval_all(&mut evaluator, "x").is_err());
+        eval_all(&mut evaluator, "(add 1 2)").unwrap();
+
+        let mut evaluator = Interpreter::new_sandboxed();
+        evaluator.reset();
+        eval_all(&mut evaluator, "(add 1 2)").unwrap();
+        assert!(eval_all(&mut evaluator, "(println 1)").is_err());
+    }
+
+    #[test]
+    fn builtin_display() {
+        let car = Interpreter::new()
+            .global_env
+            .borrow()
+            .resolve("car")
+            .unwrap();
+        assert_eq!(car.to_string(), "<builtin car>");
+    }
+
+    #[test]
+    fn shadow_policy() {
+        // The output is shared so that it also checks that nothing is
+        // printed by default.
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let mut evaluator = Interpreter::new();
+        evaluator.set_output(output.clone());
+        eval_all(&mut evaluator, "(let car 1)").unwrap();
+
+        let mut evaluator = Interpreter::new();
+        evaluator.set_output(output.clone());
+        evaluator.set_shadow_policy(ShadowPolicy::Warn);
+        // Each call binds cdr in a new enviroment but the warning is
+        // only shown once.
+        eval_all(
+            &mut evaluator,
+            "(let f (fn (n) (let cdr n)))\n(f (f 1))\n(let car 2)",
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output.borrow().clone()).unwrap(),
+            "warning: cdr shadows a builtin at 1:0\nwarning: car shadows a builtin at 2:0\n"
+        );
+
+        let mut evaluator = Interpreter::new();
+        evaluator.set_shadow_policy(ShadowPolicy::Deny);
+        let err = eval_all(&mut evaluator, "(let car 1)").unwrap_err();
+        assert!(err.starts_with("car shadows a builtin"));
+        // Shadowing something that isn't a builtin is fine.
+        eval_all(&mut evaluator, "(let x 1) (let x 2)").unwrap();
+    }
+
+    #[test]
+    fn keyword_args() {
+        each_engine(|mut evaluator| {
+            eval_all(
+                &mut evaluator,
+                "(let point (fn (x y & rest) (cons x (cons y (cons rest ())))))",
+            )
+            .unwrap();
+            for (source, expected) in [
+                ("(point 1 2)", "(1 2 ())"),
+                ("(point :x 1 :y 2)", "(1 2 ())"),
+                ("(point :y 2 :x 1)", "(1 2 ())"),
+                ("(point :y 2 1)", "(1 2 ())"),
+                ("(point 1 :y 2 3 4)", "(1 2 (3 4))"),
+                // Keywords that don't name a parameter are values.
+                ("(point :z 1 2)", "(:z 1 (2))"),
+                ("(point 1 2 :rest 3)", "(1 2 (:rest 3))"),
+                ("((fn (k) k) :x)", ":x"),
+                (":x", ":x"),
+                ("(eq :x ':x)", "#t"),
+                ("(eq :x 'x)", "()"),
+            ] {
+                assert_eq!(
+                    shown(&mut evaluator, source),
+                    Ok(expected.to_string()),
+                    "{}",
+                    source
+                );
+            }
+            for (source, expected) in [
+                (
+                    "(point :x 1 :x 2)",
+                    "point (defined at 0:0) got more than one value for x",
+                ),
+                ("(point :y 1)", "point (defined at 0:0) got no value for x"),
+                ("(point :x 1)", "point (defined at 0:0) got no value for y"),
+            ] {
+                let e = shown(&mut evaluator, source).unwrap_err();
+                assert!(e.starts_with(expected), "{}: {}", source, e);
+            }
+        });
+        let data = crate::sexpr::parse_sexpr("(:a b)").unwrap();
+        assert_eq!(data.to_sexpr(), Ok("(:a b)".to_string()));
+        assert!(LustData::symbol(":a").to_sexpr().is_err());
+    }
+
+    #[test]
+    fn fn_arity() {
+        let params = |p: &[&str]| p.iter().map(|s| s.to_string()).collect();
+        let env = LustEnv::new();
+        let f = LustFn::new(
+            params(&["a", "&", "rest"]),
+            LustData::Number(1.0),
+            env.clone(),
+        );
+        assert!(f.is_varadic());
+        assert_eq!(f.get_min_param_count(), 1);
+        assert_eq!(
+            f.check_arity(0),
+            Err(
+                "wrong number of arguments for function call. got 0 and expected at least 1"
+                    .to_string()
+            )
+        );
+        assert!(f.check_arity(3).is_ok());
+        let f = LustFn::new(params(&["a", "b"]), LustData::Number(1.0), env);
+        assert!(!f.is_varadic());
+        assert_eq!(f.get_min_param_count(), 2);
+        assert_eq!(
+            f.check_arity(1),
+            Err("wrong number of arguments for function call. got 1 and expected 2".to_string())
+        );
+        assert_eq!(
+            f.check_arity(3),
+            Err("wrong number of arguments for function call. got 3 and expected 2".to_string())
+        );
+    }
+
+    #[test]
+    fn function_names() {
+        let mut evaluator = Interpreter::new();
+        eval_all(
+            &mut evaluator,
+            "(let id (fn (x) x))\n(let square (fn (x) (mul x x)))\n(let also square)",
+        )
+        .unwrap();
+        let err = eval_all(&mut evaluator, "(also 1 2)").unwrap_err();
+        assert!(err.starts_with(
+            "wrong number of arguments for square (defined at 1:0). got 2 and expected 1"
+        ));
+        let env = evaluator.global_env.borrow();
+        let square = env.resolve("square").unwrap();
+        assert_eq!(square.to_string(), "(fn square (x) (mul x x))");
+        // Names are ignored when comparing functions.
+        assert_eq!(env.resolve("also").unwrap(), square);
+        assert!(env.resolve("id").unwrap() != square);
+    }
+
+    #[test]
+    fn trace() {
+        let mut evaluator = Interpreter::new();
+        let output = Rc::new(RefCell::new(Vec::new()));
+        evaluator.set_output(output.clone());
+        eval_all(
+            &mut evaluator,
+            "(let inner (fn (x) (add x 1))) (let outer (fn (x) (mul 2 (inner x))))",
+        )
+        .unwrap();
+        evaluator.set_trace(true);
+        eval_all(&mut evaluator, "(outer 1)").unwrap();
+        assert_eq!(
+            String::from_utf8(output.borrow().clone()).unwrap(),
+            "(outer 1)\n  (inner 1)\n  => 2\n=> 4\n"
+        );
+    }
+
+    #[test]
+    fn expansion_limit() {
+        let mut evaluator = Interpreter::new();
+        eval_all(&mut evaluator, "(let forever (macro (x) `(forever ,x)))").unwrap();
+        let e = eval_all(&mut evaluator, "(forever 1)").unwrap_err();
+        assert!(e.starts_with("macro expansion limit exceeded"));
+
+        // Macros that expand to other macros are fine.
+        evaluator.set_max_expansions(2);
+        eval_all(
+            &mut evaluator,
+            "(let one (macro (x) `(add ,x 1))) (let two (macro (x) `(one ,x)))",
+        )
+        .unwrap();
+        eval_all(&mut evaluator, "(two 1)").unwrap();
+        evaluator.set_max_expansions(1);
+        assert!(eval_all(&mut evaluator, "(two 1)").is_err());
+    }
+
+    #[test]
+    fn expansions_are_cached() {
+        let mut evaluator = Interpreter::new();
+        let output = Rc::new(RefCell::new(Vec::new()));
+        evaluator.set_output(output.clone());
+        // The macro prints each time that it is expanded.
+        eval_all(
+            &mut evaluator,
+            "(let dec (macro (x) (if (print 1) () `(sub ,x 1))))
+             (let count (fn (n) (if (eq n 0) 0 (count (dec n)))))
+             (count 10)",
+        )
+        .unwrap();
+        assert_eq!(output.borrow().as_slice(), b"1");
+
+        // Redefining the macro is noticed.
+        eval_all(
+            &mut evaluator,
+            "(let dec (macro (x) (if (print 2) () `(sub ,x 2))))
+             (count 10)",
+        )
+        .unwrap();
+        assert_eq!(output.borrow().as_slice(), b"12");
+    }
+
+    #[test]
+    fn completions() {
+        let mut evaluator = Interpreter::new();
+        eval_all(
+            &mut evaluator,
+            "(let map-one 1) (let map-two 2) (let map-one 3)",
+        )
+        .unwrap();
+        assert_eq!(evaluator.completions("map-"), vec!["map-one", "map-two"]);
+        assert_eq!(
+            evaluator.completions("macro"),
+            vec!["macro", "macroexpand", "macroexpand-1"]
+        );
+        assert!(evaluator.completions("nothing-like-this").is_empty());
+    }
+
+    #[test]
+    fn previous_results() {
+        let mut evaluator = Interpreter::new();
+        let mut eval_print = |source: &str| {
+            let expr = Parser::new(source).parse_expr().expr.unwrap();
+            evaluator.eval_print(&expr)
+        };
+        eval_print("(add 1 2)").unwrap();
+        eval_print("(add _ 1)").unwrap();
+        eval_print("'(a)").unwrap();
+        // Errors don't replace the last result.
+        assert!(eval_print("(error \"no\")").is_err());
+        eval_print("(let res (cons *3 (cons *2 (cons *1 (cons _ ())))))").unwrap();
+        let res = evaluator.global_env.borrow().resolve("res").unwrap();
+        assert_eq!(res.to_string(), "(3 4 (a) (a))");
+        assert_eq!(evaluator.global_env.borrow().resolve("*1").unwrap(), res);
+    }
+
+    #[test]
+    fn unresolved_suggestions() {
+        let mut evaluator = Interpreter::new();
+        eval_all(&mut evaluator, "(let Total 1) (let total-a 2)").unwrap();
+        let error = |evaluator: &mut Interpreter, source: &str| {
+            let e = eval_all(evaluator, source).unwrap_err();
+            e[..e.find(TRACE_HEADER).unwrap_or(e.len())].to_string()
+        };
+        let cases = [
+            (
+                "(pritnln 1)",
+                "failed to resolve identifier pritnln. did you mean `println`?",
+            ),
+            // Names differing only in case come first.
+            (
+                "total",
+                "failed to resolve identifier total. did you mean `Total`, `iota` or `total-a`?",
+            ),
+            (
+                "((fn (x) (ad x 1)) 2)",
+                "failed to resolve identifier ad. did you mean `add`?",
+            ),
+            ("xqzzyw", "failed to resolve identifier xqzzyw"),
+            // Single characters are close to too much to suggest.
+            ("y", "failed to resolve identifier y"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(error(&mut evaluator, source), expected, "{}", source);
+        }
+        // Names bound in enclosing functions are suggested.
+        let e = error(&mut evaluator, "((fn (count-a) (counta)) 1)");
+        assert!(e.contains("`count-a`"), "{}", e);
+        // At most three names are suggested.
+        let e = error(&mut evaluator, "(taek 1)");
+        assert!(e.matches('`').count() <= 6, "{}", e);
+    }
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("abc", ""), 3);
+        assert_eq!(edit_distance("pritnln", "println"), 2);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("add", "ADD"), 3);
+    }
+
+    #[test]
+    fn list_accessors() {
+        let mut evaluator = Interpreter::new();
+        let expr = Parser::new("'(1 a \"b\")").parse_expr().expr.unwrap();
+        let list = evaluator.eval_to_data(&expr).unwrap();
+        assert_eq!(list.as_list().unwrap().into_iter().count(), 3);
+        assert_eq!(
+            list.into_list(),
+            Ok(vec![
+                LustData::Number(1.0),
+                LustData::symbol("a"),
+                LustData::from_string("b"),
+            ])
+        );
+        assert_eq!(LustData::get_empty_list().into_list(), Ok(vec![]));
+
+        let num = LustData::Number(1.0);
+        assert_eq!(
+            num.as_list().err(),
+            Some("expected list, got 1".to_string())
+        );
+        assert_eq!(
+            LustData::symbol("a").into_list(),
+            Err("expected list, got a".to_string())
+        );
+    }
+}
@@ -0,0 +1,169 @@
+/// Converts between JSON and Lust data. Lust has no map type so JSON
+/// objects become lists starting with the symbol `object` followed by
+/// a `(key value)` list for each entry. For example, `{"a": [1, 2]}`
+/// becomes `(object ("a" (1 2)))`. Booleans become `#t` and the empty
+/// list, and null becomes the empty list. The empty list is always
+/// converted back to null so empty arrays and false do not survive a
+/// round trip. Hash tables with string keys are converted to objects
+/// too.
+use serde_json::{Map, Number, Value};
+
+use crate::interpreter::LustData;
+
+/// The symbol that marks a list as a JSON object.
+const OBJECT: &str = "object";
+
+/// Parses SOURCE as JSON.
+pub fn from_json(source: &str) -> Result<LustData, String> {
+    let value: Value = serde_json::from_str(source).map_err(|e| format!("invalid JSON: {}", e))?;
+    Ok(from_value(&value))
+}
+
+/// Converts DATA to a JSON string. Errors if DATA contains values
+/// that have no JSON equivalent, like functions. The empty list
+/// becomes null.
+pub fn to_json(data: &LustData) -> Result<String, String> {
+    Ok(to_value(data)?.to_string())
+}
+
+fn from_value(value: &Value) -> LustData {
+    match value {
+        Value::Null | Value::Bool(false) => LustData::get_empty_list(),
+        Value::Bool(true) => LustData::symbol("#t"),
+        Value::Number(n) => LustData::Number(n.as_f64().unwrap_or(f64::NAN) as f32),
+        Value::String(s) => LustData::string_value(s),
+        Value::Array(items) => LustData::list(items.iter().map(from_value).collect()),
+        Value::Object(entries) => {
+            let mut res = vec![LustData::symbol(OBJECT)];
+            res.extend(entries.iter().map(|(key, val)| {
+                LustData::list(vec![LustData::string_value(key), from_value(val)])
+            }));
+            LustData::list(res)
+        }
+    }
+}
+
+fn to_value(data: &LustData) -> Result<Value, String> {
+    if let Some(s) = data.stringify() {
+        return Ok(Value::String(s));
+    }
+    Ok(match data {
+        LustData::Number(n) => {
+            let n = *n as f64;
+            if n.fract() == 0.0 && n.abs() < i64::MAX as f64 {
+                Value::Number(Number::from(n as i64))
+            } else {
+                Number::from_f64(n)
+                    .map(Value::Number)
+                    .ok_or_else(|| format!("can not convert {} to JSON", n))?
+            }
+        }
+        LustData::Symbol(_) if data.expect_symbol() == Ok("#t") => Value::Bool(true),
+        LustData::Symbol(_) => Value::String(data.expect_symbol()?.to_string()),
+        LustData::Char(c) => Value::String(c.to_string()),
+        LustData::Cons(_) if data.is_empty_list() => Value::Null,
+        LustData::Cons(_) => {
+            let items = data.clone().into_list()?;
+            if items[0].expect_symbol() == Ok(OBJECT) {
+                let mut map = Map::new();
+                for entry in &items[1..] {
+                    let (key, val) = object_entry(entry)?;
+                    map.insert(key, to_value(val)?);
+                }
+                Value::Object(map)
+            } else {
+                Value::Array(items.iter().map(to_value).collect::<Result<_, _>>()?)
+            }
+        }
+        LustData::Map(m) => {
+            let mut map = Map::new();
+            for (key, val) in &m.borrow().entries {
+                let key = key.stringify().ok_or_else(|| {
+                    format!(
+                        "can not convert a hash table with {} keys to JSON",
+                        key.type_name()
+                    )
+                })?;
+                map.insert(key, to_value(val)?);
+            }
+            Value::Object(map)
+        }
+        _ => return Err(format!("can not convert {} to JSON", data.type_name())),
+    })
+}
+
+/// Splits an entry in an object into its key and value.
+fn object_entry(entry: &LustData) -> Result<(String, &LustData), String> {
+    let pair = entry.as_list()?;
+    if pair.len() != 2 {
+        return Err(format!("invalid JSON object entry {}", entry));
+    }
+    let key = match pair[0].stringify() {
+        Some(s) => s,
+        None => pair[0].expect_symbol()?.to_string(),
+    };
+    Ok((key, &pair[1]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Map as LustMap;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn round_trip() {
+        let source = r#"{"name":"lust","tags":["lisp",1,2.5],"nested":{"ok":true,"none":null}}"#;
+        let data = from_json(source).unwrap();
+        assert_eq!(from_json(&to_json(&data).unwrap()), Ok(data.clone()));
+        assert_eq!(
+            to_json(&data).unwrap(),
+            r#"{"name":"lust","nested":{"none":null,"ok":true},"tags":["lisp",1,2.5]}"#
+        );
+    }
+
+    #[test]
+    fn hash_tables() {
+        let table = |entries: Vec<(LustData, LustData)>| {
+            LustData::Map(Rc::new(RefCell::new(LustMap {
+                entries,
+                comparator: None,
+            })))
+        };
+        let data = table(vec![
+            (LustData::string_value("b"), LustData::Number(1.0)),
+            (
+                LustData::string_value("a"),
+                LustData::list(vec![LustData::symbol("#t")]),
+            ),
+        ]);
+        let json = to_json(&data).unwrap();
+        assert_eq!(json, r#"{"a":[true],"b":1}"#);
+        // Objects are read back as lists, which convert to the same
+        // JSON.
+        assert_eq!(to_json(&from_json(&json).unwrap()), Ok(json));
+
+        let data = table(vec![(LustData::Number(1.0), LustData::Number(2.0))]);
+        assert_eq!(
+            to_json(&data),
+            Err("can not convert a hash table with number keys to JSON".to_string())
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert!(from_json("{\"a\": ")
+            .unwrap_err()
+            .starts_with("invalid JSON"));
+        let f = LustData::Builtin(crate::interpreter::BuiltinFn {
+            name: "f",
+            signature: "",
+            func: |_, _| Err(String::new()),
+        });
+        assert_eq!(
+            to_json(&f),
+            Err("can not convert builtin to JSON".to_string())
+        );
+    }
+}
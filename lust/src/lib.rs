@@ -1,60 +1,109 @@
 pub mod builtins;
+pub mod bytecode;
+pub mod compile;
 pub mod errors;
+pub mod ffi;
+pub mod intern;
 pub mod interpreter;
+pub mod json;
 pub mod location;
 pub mod lustvec;
+pub mod optimize;
 pub mod parser;
 pub mod reader;
+pub mod render;
 pub mod repl;
+pub mod sexpr;
 pub mod symboltable;
 pub mod tokenbuffer;
 pub mod tokenizer;
 
-use repl::REPLHelper;
+use repl::{CommandResult, REPLHelper};
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 
-use crate::errors::{Error, Printable};
-use crate::interpreter::Interpreter;
+use crate::errors::Error;
+use crate::interpreter::{Interpreter, LustData};
 use crate::parser::Parser;
 
 pub fn do_repl(evaluator: &mut Interpreter) {
     let mut rl = Editor::<REPLHelper>::new();
-    let helper = repl::REPLHelper::new();
+    let helper = REPLHelper::new(evaluator.global_env.clone());
     rl.set_helper(Some(helper));
 
-    let indent = rustyline::KeyEvent::new('\t', rustyline::Modifiers::NONE);
-    rl.bind_sequence(indent, rustyline::Cmd::Insert(1, "    ".to_string()));
+    let history = repl::history_path(|name| std::env::var_os(name));
+    if let Some(ref path) = history {
+        // No history file is fine, it'll be created on exit.
+        let _ = rl.load_history(path);
+    }
+
+    // Input collected so far for an expression that spans multiple
+    // lines.
+    let mut input = String::new();
+    // Everything evaluated this session. Errors are shown relative to
+    // this so that they can refer to earlier input.
+    let mut session = String::new();
 
-    loop {
-        let p = ">> ";
+    'repl: loop {
+        let p = if input.is_empty() { ">> " } else { "..> " };
         rl.helper_mut().expect("No helper").colored_prompt = format!("\x1b[1;32m{}\x1b[0m", p);
-        let readline = rl.readline(&p);
+        let readline = rl.readline(p);
         match readline {
             Ok(line) => {
+                if !input.is_empty() {
+                    input.push('\n');
+                }
+                // When stdin isn't a terminal lines arrive with their
+                // trailing newline.
+                input.push_str(line.trim_end_matches(&['\n', '\r'][..]));
+                if Parser::is_incomplete(&input) {
+                    continue;
+                }
+                let line = std::mem::take(&mut input);
                 rl.add_history_entry(line.as_str());
                 if line.trim() == "(exit)" {
                     break;
                 }
+                if line.trim_start().starts_with(':') {
+                    match repl::run_command(&line, evaluator) {
+                        CommandResult::Show(s) => println!("{}", s),
+                        CommandResult::Quit => break,
+                    }
+                    // The command may have replaced the global
+                    // enviroment so completions need to be updated.
+                    let helper = REPLHelper::new(evaluator.global_env.clone());
+                    rl.set_helper(Some(helper));
+                    continue;
+                }
+                let offset = session.lines().count();
+                session.push_str(&line);
+                session.push('\n');
+
                 let mut parser = Parser::new(&line);
                 while parser.has_more() {
                     let res = parser.parse_expr();
 
-                    for e in &res.errors {
-                        e.show(&line, "repl");
-                    }
                     if res.errors.is_empty() {
                         let expr = res.expr.unwrap();
                         if let Err(s) = evaluator.eval_print(&expr) {
-                            let error = Error::on_expr(&s, &expr);
-                            error.show(&line, "repl");
+                            if builtins::exit_status(&s).is_some() {
+                                break 'repl;
+                            }
+                            show_repl_error(Error::on_expr(&s, &expr), offset, &session);
+                        }
+                    } else {
+                        for e in res.errors {
+                            show_repl_error(e, offset, &session);
                         }
                     }
                 }
             }
             Err(ReadlineError::Interrupted) => {
-                println!("CTRL-C");
-                println!("lust: use CTRL-D or (exit) to exit");
+                if input.is_empty() {
+                    println!("CTRL-C");
+                    println!("lust: use CTRL-D or (exit) to exit");
+                }
+                input.clear();
             }
             Err(ReadlineError::Eof) => {
                 println!("CTRL-D");
@@ -66,31 +115,97 @@ pub fn do_repl(evaluator: &mut Interpreter) {
             }
         }
     }
+
+    if let Some(ref path) = history {
+        if let Err(e) = rl.save_history(path) {
+            println!("lust: failed to save history: {}", e);
+        }
+    }
+}
+
+/// Prints an error that occured in the repl. ERROR's location is
+/// relative to the input that starts OFFSET lines into SESSION.
+fn show_repl_error(mut error: Error, offset: usize, session: &str) {
+    error.offset_lines(offset);
+    print!(
+        "{}",
+        render::render(&error, session, "repl", render::use_color())
+    );
 }
 
 pub fn interpret_file(path: &str) -> Result<Interpreter, String> {
-    let contents = match std::fs::read_to_string(path).map_err(|e| e.to_string()) {
-        Ok(s) => s,
-        Err(e) => return Err(format!("failed to read file {}: {}", path, e)),
-    };
     let mut evaluator = Interpreter::new();
-    let mut parser = Parser::new(&contents);
+    interpret_file_in(path, &mut evaluator)?;
+    Ok(evaluator)
+}
+
+/// Evaluates the file at PATH using EVALUATOR. Any definitions made
+/// by the file are left in EVALUATOR's global enviroment.
+pub fn interpret_file_in(path: &str, evaluator: &mut Interpreter) -> Result<(), String> {
+    let contents = read_file(path)?;
+    interpret_source(&contents, path, evaluator)
+}
+
+fn read_file(path: &str) -> Result<String, String> {
+    std::fs::read_to_string(path).map_err(|e| format!("failed to read file {}: {}", path, e))
+}
+
+/// Evaluates every expression in SOURCE using EVALUATOR. NAME is used
+/// to refer to the source when showing errors, which are printed to
+/// stderr.
+pub fn interpret_source(
+    source: &str,
+    name: &str,
+    evaluator: &mut Interpreter,
+) -> Result<(), String> {
+    let mut parser = Parser::new(source);
 
     while parser.has_more() {
         let res = parser.parse_expr();
         if res.errors.is_empty() {
             let expr = res.expr.unwrap();
             if let Err(e) = evaluator.eval(&expr) {
-                let error = Error::on_expr(&e, &expr);
-                error.show(&contents, path);
+                if builtins::exit_status(&e).is_none() {
+                    show_error(&Error::on_expr(&e, &expr), source, name);
+                }
                 return Err(e);
             }
         } else {
             for e in &res.errors {
-                e.show(&contents, path);
+                show_error(e, source, name);
             }
             return Err("an error occured parsing the input file".to_string());
         }
     }
-    Ok(evaluator)
+    Ok(())
+}
+
+/// Prints ERROR, which occured in SOURCE, to stderr.
+fn show_error(error: &Error, source: &str, name: &str) {
+    let color = render::use_color_for(&std::io::stderr());
+    eprint!("{}", render::render(error, source, name, color));
+}
+
+/// Runs the script at PATH. ARGS are made available to the script as
+/// a list of strings bound to `*args*`. Errors are printed to stderr
+/// as well as returned.
+pub fn run_script(path: &str, args: &[String], evaluator: &mut Interpreter) -> Result<(), String> {
+    let source = read_file(path).inspect_err(|e| eprintln!("error: {}", e))?;
+    bind_args(args, evaluator);
+    interpret_source(&source, path, evaluator)
+}
+
+/// Evaluates SOURCE passed on the command line with `-e`. ARGS are
+/// bound to `*args*` as with `run_script`.
+pub fn run_expr(source: &str, args: &[String], evaluator: &mut Interpreter) -> Result<(), String> {
+    bind_args(args, evaluator);
+    interpret_source(source, "-e", evaluator)
+}
+
+fn bind_args(args: &[String], evaluator: &mut Interpreter) {
+    let args = args.iter().map(|a| LustData::string_value(a)).collect();
+    evaluator
+        .global_env
+        .borrow_mut()
+        .insert("*args*".to_string(), LustData::list(args));
 }
@@ -1,24 +1,75 @@
 use std::env;
+use std::process;
+use std::thread;
 
+use lust::builtins::exit_status;
 use lust::interpreter::Interpreter;
 
 fn show_usage() {
-    println!("usage: lust <file>?");
+    println!(
+        "usage: lust [--dump-ast] [--dump-expanded] [--compile] [--bytecode] [--allow-system] [-e <expr> | <file>] [args...]"
+    );
 }
 
+/// Stack size for the thread that runs the interpreter. Big enough
+/// that evaluation nested to the default maximum depth won't
+/// overflow, even in debug builds.
+const STACK_SIZE: usize = 256 * 1024 * 1024;
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() > 2 {
-        show_usage();
-        return;
-    }
+    let runner = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(run)
+        .expect("failed to spawn interpreter thread");
+    // Errors are reported by the interpreter thread.
+    let code = runner.join().unwrap_or(1);
+    process::exit(code);
+}
 
-    if args.len() == 2 {
-        match lust::interpret_file(&args[1]) {
-            Err(e) => println!("error: {}", e),
-            Ok(_) => (),
+/// Runs lust with the command line arguments and returns the exit
+/// code.
+fn run() -> i32 {
+    let mut evaluator = Interpreter::new();
+    let mut args = env::args().skip(1);
+
+    let res = loop {
+        match args.next().as_deref() {
+            Some("--dump-ast") => evaluator.dump_ast = true,
+            Some("--dump-expanded") => evaluator.dump_expanded = true,
+            Some("--compile") => evaluator.set_compile(true),
+            Some("--bytecode") => evaluator.use_bytecode(true),
+            // Scripts can only reach outside of the interpreter with
+            // `shell`, `getenv` and friends when asked to.
+            Some("--allow-system") => evaluator.enable_system_builtins(),
+            Some("-e") => match args.next() {
+                Some(expr) => {
+                    let rest: Vec<String> = args.collect();
+                    break lust::run_expr(&expr, &rest, &mut evaluator);
+                }
+                None => {
+                    show_usage();
+                    return 2;
+                }
+            },
+            Some(arg) if arg.starts_with('-') => {
+                show_usage();
+                return 2;
+            }
+            Some(path) => {
+                let path = path.to_string();
+                let rest: Vec<String> = args.collect();
+                break lust::run_script(&path, &rest, &mut evaluator);
+            }
+            None => {
+                lust::do_repl(&mut evaluator);
+                return 0;
+            }
         }
-    } else {
-        lust::do_repl(&mut Interpreter::new())
+    };
+
+    // Errors have already been shown where they occured.
+    match res {
+        Ok(()) => 0,
+        Err(e) => exit_status(&e).unwrap_or(1),
     }
 }
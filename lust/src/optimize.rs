@@ -0,0 +1,215 @@
+/// A constant folding pass over expanded expressions. Calls to pure
+/// builtins whose arguments are all literals are replaced with their
+/// result and `if` expressions with a literal condition are replaced
+/// with the branch that would be taken. For example,
+///
+/// ```lisp
+/// (let area (fn (r) (mul r (mul 3 (add 1 1)))))
+/// ```
+///
+/// is folded to
+///
+/// ```lisp
+/// (let area (fn (r) (mul r 6)))
+/// ```
+///
+/// Only expressions that are known to be evaluated are folded. The
+/// arguments to macros, to builtins which don't evaluate their
+/// arguments, and to anything that can't be resolved when the pass
+/// runs are left alone.
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::builtins::{collect_param_list, truthy};
+use crate::interpreter::{CallResult, LustData, LustEnv};
+
+/// Builtins without side effects that always return the same result
+/// for the same arguments.
+const PURE_BUILTINS: &[&str] = &[
+    "add", "sub", "mul", "div", "mod", "negate", "lt", "gt", "eq", "nan?", "inf?",
+];
+
+/// Builtins that the pass relies on the meaning of. Expressions that
+/// might rebind one of these are not folded.
+const SPECIAL_FORMS: &[&str] = &["if", "quote", "quaziquote", "let", "fn"];
+
+/// Folds the constants in EXPR. Names are resolved in ENV, which
+/// should be the enviroment EXPR will be evaluated in.
+pub fn fold_constants(expr: &LustData, env: &Rc<RefCell<LustEnv>>) -> LustData {
+    if rebinds_folded_name(expr, true) {
+        return expr.clone();
+    }
+    fold(expr, env, &mut Vec::new())
+}
+
+/// Determines if EXPR might rebind one of the names that the pass
+/// gives meaning to. This is conservative: any use of one of those
+/// names other than at the head of a list counts. Function parameters
+/// are tracked separately by `fold`.
+fn rebinds_folded_name(expr: &LustData, is_head: bool) -> bool {
+    match expr {
+        LustData::Symbol(_) if !is_head => {
+            let name = expr.expect_symbol().unwrap_or_default();
+            PURE_BUILTINS.contains(&name) || SPECIAL_FORMS.contains(&name)
+        }
+        LustData::Cons(c) => c
+            .into_iter()
+            .enumerate()
+            .any(|(i, item)| rebinds_folded_name(item, i == 0)),
+        _ => false,
+    }
+}
+
+fn fold(expr: &LustData, env: &Rc<RefCell<LustEnv>>, shadowed: &mut Vec<String>) -> LustData {
+    let items = match expr {
+        LustData::Cons(_) if !expr.is_empty_list() => expr.clone().into_list().unwrap(),
+        _ => return expr.clone(),
+    };
+    let head = match items[0].expect_symbol() {
+        Ok(name) if !shadowed.iter().any(|s| s == name) => name,
+        _ => return expr.clone(),
+    };
+    let builtin = match env.borrow().get(head) {
+        Some(LustData::Builtin(b)) => b,
+        // Arguments to functions are always evaluated.
+        Some(LustData::Fn(_)) => return fold_args(&items, env, shadowed),
+        _ => return expr.clone(),
+    };
+    match (builtin.name, items.len()) {
+        ("if", 4) => {
+            let cond = fold(&items[1], env, shadowed);
+            match literal_value(&cond) {
+                Some(v) => {
+                    let taken = if truthy(&v) { &items[2] } else { &items[3] };
+                    fold(taken, env, shadowed)
+                }
+                None => LustData::list(vec![
+                    items[0].clone(),
+                    cond,
+                    fold(&items[2], env, shadowed),
+                    fold(&items[3], env, shadowed),
+                ]),
+            }
+        }
+        // Only the form that binds a name. The names bound by the form
+        // with a list of bindings would need to be shadowed.
+        ("let", 3) if matches!(items[1], LustData::Symbol(_)) => LustData::list(vec![
+            items[0].clone(),
+            items[1].clone(),
+            fold(&items[2], env, shadowed),
+        ]),
+        ("fn", n) if n >= 3 => {
+            let params = match collect_param_list(&items[1]) {
+                Ok(p) => p,
+                Err(_) => return expr.clone(),
+            };
+            let outer = shadowed.len();
+            shadowed.extend(params);
+            // Folding a docstring leaves it as it is.
+            let mut res = items.clone();
+            for form in res.iter_mut().skip(2) {
+                *form = fold(form, env, shadowed);
+            }
+            shadowed.truncate(outer);
+            LustData::list(res)
+        }
+        (name, _) if PURE_BUILTINS.contains(&name) => {
+            let folded = fold_args(&items, env, shadowed);
+            let args = folded.clone().into_list().unwrap().split_off(1);
+            if !args.iter().all(|a| literal_value(a).is_some()) {
+                return folded;
+            }
+            // Builtins evaluate their arguments and literals evaluate
+            // to themselves. Errors, like adding a symbol, are left for
+            // when the expression is evaluated.
+            let args = LustData::list(args);
+            match (builtin.func)(args.as_list().unwrap(), env.clone()) {
+                Ok(CallResult::Ret(v)) => literal(v),
+                _ => folded,
+            }
+        }
+        _ => expr.clone(),
+    }
+}
+
+/// Folds every item in ITEMS except the first.
+fn fold_args(
+    items: &[LustData],
+    env: &Rc<RefCell<LustEnv>>,
+    shadowed: &mut Vec<String>,
+) -> LustData {
+    let mut res = vec![items[0].clone()];
+    res.extend(items[1..].iter().map(|item| fold(item, env, shadowed)));
+    LustData::list(res)
+}
+
+/// Gets the value of EXPR if it is a literal.
+fn literal_value(expr: &LustData) -> Option<LustData> {
+    match expr {
+        LustData::Number(_) => Some(expr.clone()),
+        LustData::Cons(_) if expr.is_empty_list() => Some(expr.clone()),
+        LustData::Cons(c) if c.len() == 2 && c[0].expect_symbol() == Ok("quote") => {
+            Some(c[1].clone())
+        }
+        _ => None,
+    }
+}
+
+/// Makes a literal expression that evaluates to VALUE.
+fn literal(value: LustData) -> LustData {
+    match value {
+        LustData::Number(_) => value,
+        _ if value.is_empty_list() => value,
+        _ => LustData::list(vec![LustData::symbol("quote"), value]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sexpr::parse_sexpr;
+
+    fn folded(source: &str) -> String {
+        let env = LustEnv::new();
+        fold_constants(&parse_sexpr(source).unwrap(), &env).to_string()
+    }
+
+    #[test]
+    fn folds_literals() {
+        assert_eq!(folded("(add 1 (mul 2 3))"), "7");
+        assert_eq!(
+            folded("(let area (fn (r) (mul r (mul 3 (add 1 1)))))"),
+            "(let area (fn (r) (mul r 6)))"
+        );
+        assert_eq!(folded("(if (lt 1 2) (negate 1) x)"), "-1");
+        assert_eq!(folded("(if (gt 1 2) x (sub 3 1))"), "2");
+        assert_eq!(folded("(eq 1 1)"), "(quote #t)");
+        assert_eq!(folded("(if x (add 1 1) y)"), "(if x 2 y)");
+        assert_eq!(
+            folded("(fn (x) \"doc\" (if 1 x y) (mul 2 3))"),
+            "(fn (x) \"doc\" x 6)"
+        );
+    }
+
+    #[test]
+    fn leaves_everything_else() {
+        // Symbols, quoted data, and impure builtins.
+        assert_eq!(folded("(add x 1)"), "(add x 1)");
+        assert_eq!(folded("(quote (add 1 2))"), "(quote (add 1 2))");
+        assert_eq!(folded("(println (add 1 2))"), "(println (add 1 2))");
+        assert_eq!(folded("(add 1 (quote a))"), "(add 1 (quote a))");
+        // Parameters shadow builtins.
+        assert_eq!(folded("(fn (add) (add 1 2))"), "(fn (add) (add 1 2))");
+        // So may bindings made while evaluating.
+        assert_eq!(
+            folded("(fn () (do (let add sub) (add 1 2)))"),
+            "(fn () (do (let add sub) (add 1 2)))"
+        );
+        assert_eq!(
+            folded("(let ((add sub)) (add 1 2))"),
+            "(let ((add sub)) (add 1 2))"
+        );
+        // Unknown functions might be macros.
+        assert_eq!(folded("(later (add 1 2))"), "(later (add 1 2))");
+    }
+}
@@ -2,8 +2,9 @@
 /// along the way.
 use crate::errors::Error;
 use crate::location::Location;
+use crate::reader::Reader;
 use crate::tokenbuffer::TokenBuffer;
-use crate::tokenizer::{Token, TokenType};
+use crate::tokenizer::{Token, TokenType, Tokenizer};
 
 /// Used internally by the parser to store information about the state
 /// of the parse.
@@ -76,6 +77,40 @@ impl Expr {
     pub fn at_loc(loc: Location, val: ExprVal) -> Self {
         Self { loc, val }
     }
+
+    /// Renders the expression as an indented tree with one node per
+    /// line. Used by the `--dump-ast` flag to show what the parser
+    /// produced, for example:
+    ///
+    /// ```text
+    /// List 0:0
+    ///   Id quote 0:1
+    ///   Number 1 0:2
+    /// ```
+    pub fn dump(&self) -> String {
+        let mut res = String::new();
+        self.dump_into(&mut res, 0);
+        res
+    }
+
+    fn dump_into(&self, res: &mut String, depth: usize) {
+        for _ in 0..depth {
+            res.push_str("  ");
+        }
+        let start = self.loc.start;
+        match &self.val {
+            ExprVal::Number(f) => res.push_str(&format!("Number {}", f)),
+            ExprVal::String(s) => res.push_str(&format!("String {:?}", s)),
+            ExprVal::Id(s) => res.push_str(&format!("Id {}", s)),
+            ExprVal::List(_) => res.push_str("List"),
+        }
+        res.push_str(&format!(" {}:{}\n", start.line, start.col));
+        if let ExprVal::List(l) = &self.val {
+            for e in l {
+                e.dump_into(res, depth + 1);
+            }
+        }
+    }
 }
 
 impl ParseResult {
@@ -155,6 +190,66 @@ impl<'a> Parser<'a> {
         res
     }
 
+    /// Determines if SOURCE ends partway through an expression. This
+    /// is the case if a list or string is left open or if a quote has
+    /// nothing following it. Used by the repl to decide if it should
+    /// read more input before parsing.
+    pub fn is_incomplete(source: &str) -> bool {
+        let mut tokenizer = Tokenizer::new(source);
+        let mut depth = 0;
+        let mut last = None;
+        while let Some(tok) = tokenizer.next_token() {
+            match tok.ttype {
+                TokenType::Oparen => depth += 1,
+                TokenType::Cparen => depth -= 1,
+                _ => (),
+            }
+            last = Some(tok.ttype);
+        }
+        match last {
+            Some(TokenType::Quote)
+            | Some(TokenType::Quaziquote)
+            | Some(TokenType::Comma)
+            | Some(TokenType::Negate) => true,
+            // The tokenizer reads strings until their closing quote
+            // or the end of input.
+            Some(TokenType::Unrecognized(_, ref t)) if matches!(**t, TokenType::String(_)) => {
+                !source.trim_end().ends_with('"')
+            }
+            _ => depth > 0,
+        }
+    }
+
+    /// Returns the number of characters in SOURCE up to the end of
+    /// its first expression.
+    pub fn first_expr_len(source: &str) -> usize {
+        let mut tokenizer = Tokenizer::new(source);
+        let mut depth = 0;
+        while let Some(tok) = tokenizer.next_token() {
+            match tok.ttype {
+                TokenType::Oparen => depth += 1,
+                TokenType::Cparen => depth -= 1,
+                // These apply to the expression that follows them.
+                TokenType::Quote | TokenType::Quaziquote | TokenType::Comma | TokenType::Negate => {
+                    continue
+                }
+                _ => (),
+            }
+            if depth <= 0 {
+                break;
+            }
+        }
+        // Locations are visual so walk a reader over the source to
+        // find how many characters were used.
+        let end = tokenizer.loc().start;
+        let mut reader = Reader::new(source);
+        let mut len = 0;
+        while reader.loc() != end && reader.next().is_some() {
+            len += 1;
+        }
+        len
+    }
+
     pub fn has_more(&self) -> bool {
         self.tokbuffer.has_next()
     }
@@ -315,4 +410,27 @@ mod tests {
         // tracks a better way to handle this.
         assert_eq!(res.errors[0].what, "unbalanced parenthesis".to_string());
     }
+
+    #[test]
+    fn incomplete() {
+        assert!(Parser::is_incomplete("(let x"));
+        assert!(Parser::is_incomplete("(fn (x)\n  (add x"));
+        assert!(Parser::is_incomplete("(print \"hello"));
+        assert!(Parser::is_incomplete("'"));
+        assert!(!Parser::is_incomplete("(let x 1)"));
+        assert!(!Parser::is_incomplete("(print \"(\") ; (\n"));
+        assert!(!Parser::is_incomplete("())"));
+        assert!(!Parser::is_incomplete(""));
+    }
+
+    #[test]
+    fn dump_quote() {
+        let src = "'(1 \"a\")";
+        let mut parser = Parser::new(src);
+        let res = parser.parse_expr();
+        assert_eq!(
+            res.expr.unwrap().dump(),
+            "List 0:0\n  Id quote 0:0\n  List 0:1\n    Number 1 0:2\n    String \"a\" 0:4\n"
+        );
+    }
 }
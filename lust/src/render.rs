@@ -0,0 +1,180 @@
+/// Renders errors as text for the repl. Unlike `Printable::show` this
+/// builds a string rather than printing so that the output can be
+/// tested without a terminal.
+use std::io::IsTerminal;
+
+use crate::errors::Error;
+
+const RED: &str = "\x1b[31m";
+const BLUE: &str = "\x1b[34m";
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+/// Decides if rendered errors should be colored. Color is used when
+/// stdout is a terminal and the `NO_COLOR` enviroment variable is not
+/// set.
+pub fn use_color() -> bool {
+    use_color_for(&std::io::stdout())
+}
+
+/// Same as `use_color` for errors written to STREAM.
+pub fn use_color_for(stream: &impl IsTerminal) -> bool {
+    std::env::var_os("NO_COLOR").is_none() && stream.is_terminal()
+}
+
+/// Wraps S in the escape sequence CODE if COLOR is set.
+fn paint(s: &str, code: &str, color: bool) -> String {
+    if color {
+        format!("{}{}{}", code, s, RESET)
+    } else {
+        s.to_string()
+    }
+}
+
+/// Renders ERROR which occured in SOURCE. For example:
+///
+/// ```text
+/// (add 1 x)
+///        ^
+///        |  error: failed to resolve identifier x
+///        *--> repl:0:7
+/// ```
+///
+/// Errors that span multiple lines also show the line that they end
+/// on and any stack trace is shown below the message.
+pub fn render(error: &Error, source: &str, file: &str, color: bool) -> String {
+    let lines: Vec<_> = source.lines().collect();
+    let start = error.loc.start;
+    let end = error.loc.end;
+    let mut res = String::new();
+
+    let startline = lines.get(start.line).copied().unwrap_or("");
+    res.push_str(startline);
+    res.push('\n');
+    let underline_end = if start.line == end.line {
+        end.col
+    } else {
+        startline.len()
+    };
+    underline(&mut res, start.col, underline_end);
+    message(
+        &mut res,
+        start.col,
+        &paint("error", RED, color),
+        &error.what,
+        color,
+    );
+    location(&mut res, file, start.line, start.col, color);
+
+    if start.line != end.line {
+        if let Some(endline) = lines.get(end.line) {
+            // Start the underline at the first non-whitespace
+            // character.
+            let col = endline
+                .find(|c: char| !c.is_ascii_whitespace())
+                .unwrap_or(0);
+            res.push('\n');
+            res.push_str(endline);
+            res.push('\n');
+            underline(&mut res, col, end.col);
+            message(
+                &mut res,
+                col,
+                &paint("note", BLUE, color),
+                "error ends here",
+                false,
+            );
+            location(&mut res, file, end.line, col, color);
+        }
+    }
+
+    if let Some(ref s) = error.suggestion {
+        message(
+            &mut res,
+            s.loc.start.col,
+            &paint("suggestion", BLUE, color),
+            &s.what,
+            false,
+        );
+    }
+
+    if !error.trace.is_empty() {
+        res.push_str(&paint("stack trace:", DIM, color));
+        res.push('\n');
+        for frame in &error.trace {
+            res.push_str(&paint(&format!("  {}", frame), DIM, color));
+            res.push('\n');
+        }
+    }
+    res
+}
+
+/// Writes an underline in the range [start, end).
+fn underline(res: &mut String, start: usize, end: usize) {
+    res.push_str(&" ".repeat(start));
+    res.push('^');
+    for _ in (start + 1)..end {
+        res.push('-');
+    }
+    res.push('\n');
+}
+
+/// Writes a labeled message indented by COL. The message is red if
+/// COLOR is set.
+fn message(res: &mut String, col: usize, label: &str, what: &str, color: bool) {
+    res.push_str(&" ".repeat(col));
+    res.push_str(&format!("|  {}: {}\n", label, paint(what, RED, color)));
+}
+
+/// Writes the file and position that an error occured at.
+fn location(res: &mut String, file: &str, line: usize, col: usize, color: bool) {
+    res.push_str(&" ".repeat(col));
+    let pos = format!("{}:{}:{}", file, line, col);
+    res.push_str(&format!("*--> {}\n", paint(&pos, BLUE, color)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::location::Location;
+    use crate::reader;
+
+    fn loc(s_line: usize, s_col: usize, e_line: usize, e_col: usize) -> Location {
+        Location {
+            start: reader::Location::from_raw(s_line, s_col),
+            end: reader::Location::from_raw(e_line, e_col),
+        }
+    }
+
+    #[test]
+    fn single_line() {
+        let error = Error::at_loc("oops", &loc(0, 7, 0, 8));
+        assert_eq!(
+            render(&error, "(add 1 x)", "repl", false),
+            "(add 1 x)\n       ^\n       |  error: oops\n       *--> repl:0:7\n"
+        );
+    }
+
+    #[test]
+    fn colored_with_trace() {
+        let mut error = Error::at_loc("oops", &loc(0, 0, 0, 3));
+        error.trace = vec!["in g (0 arguments)".to_string()];
+        assert_eq!(
+            render(&error, "(f)", "repl", true),
+            "(f)\n^--\n|  \x1b[31merror\x1b[0m: \x1b[31moops\x1b[0m\n\
+             *--> \x1b[34mrepl:0:0\x1b[0m\n\
+             \x1b[2mstack trace:\x1b[0m\n\x1b[2m  in g (0 arguments)\x1b[0m\n"
+        );
+    }
+
+    #[test]
+    fn multi_line_in_earlier_input() {
+        let session = "(let x 1)\n(f 1\n   2)\n(g)";
+        let mut error = Error::at_loc("oops", &loc(0, 0, 1, 5));
+        error.offset_lines(1);
+        assert_eq!(
+            render(&error, session, "repl", false),
+            "(f 1\n^---\n|  error: oops\n*--> repl:1:0\n\n   2)\n   ^-\n   |  note: error ends here\n   *--> repl:2:3\n"
+        );
+    }
+}
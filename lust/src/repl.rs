@@ -1,28 +1,49 @@
 use std::borrow::Cow::{self, Borrowed, Owned};
+use std::cell::RefCell;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::rc::Rc;
 
+use rustyline::completion::Completer;
 use rustyline::highlight::{Highlighter, MatchingBracketHighlighter};
-use rustyline::validate::{self, MatchingBracketValidator, Validator};
-use rustyline_derive::{Completer, Helper, Hinter};
+use rustyline::Context;
+use rustyline_derive::{Helper, Hinter, Validator};
 
-#[derive(Helper, Completer, Hinter)]
+use crate::interpreter::{Interpreter, LustData, LustEnv};
+use crate::parser::Parser;
+
+// Multi-line input is handled by the repl loop rather than the
+// validator so that continuation lines get their own prompt.
+#[derive(Helper, Hinter, Validator)]
 pub struct REPLHelper {
     highlighter: MatchingBracketHighlighter,
-    validator: MatchingBracketValidator,
+    /// The enviroment that symbol names are completed from.
+    env: Rc<RefCell<LustEnv>>,
     pub colored_prompt: String,
 }
 
-// impl Completer for REPLHelper {
-//     type Candidate = Pair;
+impl Completer for REPLHelper {
+    type Candidate = String;
 
-//     fn complete(
-//         &self,
-//         line: &str,
-//         pos: usize,
-//         ctx: &Context<'_>,
-//     ) -> Result<(usize, Vec<Pair>), ReadlineError> {
-//         self.completer.complete(line, pos, ctx)
-//     }
-// }
+    /// Completes the symbol that ends at POS with the names bound in
+    /// the repl's enviroment. If there is no symbol to complete the
+    /// line is indented instead.
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos]
+            .rfind(|c: char| c.is_whitespace() || "()'`,".contains(c))
+            .map_or(0, |i| i + 1);
+        let prefix = &line[start..pos];
+        if prefix.is_empty() {
+            return Ok((pos, vec!["    ".to_string()]));
+        }
+        Ok((start, self.env.borrow().completions(prefix)))
+    }
+}
 
 impl Highlighter for REPLHelper {
     fn highlight_prompt<'b, 's: 'b, 'p: 'b>(
@@ -50,25 +71,190 @@ impl Highlighter for REPLHelper {
     }
 }
 
-impl Validator for REPLHelper {
-    fn validate(
-        &self,
-        ctx: &mut validate::ValidationContext,
-    ) -> rustyline::Result<validate::ValidationResult> {
-        self.validator.validate(ctx)
+impl REPLHelper {
+    /// Makes a new helper which completes symbols bound in ENV.
+    pub fn new(env: Rc<RefCell<LustEnv>>) -> Self {
+        Self {
+            highlighter: MatchingBracketHighlighter::new(),
+            colored_prompt: "".to_owned(),
+            env,
+        }
     }
+}
 
-    fn validate_while_typing(&self) -> bool {
-        false
+/// Gets the path of the file that repl history is saved to. This is
+/// `$LUST_HISTORY` if set, otherwise `lust_history` in
+/// `$XDG_STATE_HOME`, and finally `~/.lust_history`. Enviroment
+/// variables are looked up with VAR, which is normally
+/// `std::env::var_os`.
+pub fn history_path(var: impl Fn(&str) -> Option<OsString>) -> Option<PathBuf> {
+    if let Some(path) = var("LUST_HISTORY") {
+        return Some(PathBuf::from(path));
+    }
+    if let Some(dir) = var("XDG_STATE_HOME") {
+        return Some(PathBuf::from(dir).join("lust_history"));
     }
+    var("HOME").map(|home| PathBuf::from(home).join(".lust_history"))
 }
 
-impl REPLHelper {
-    pub fn new() -> Self {
-        Self {
-            highlighter: MatchingBracketHighlighter::new(),
-            colored_prompt: "".to_owned(),
-            validator: MatchingBracketValidator::new(),
+/// What the repl should do after running a meta-command.
+#[derive(Debug, PartialEq)]
+pub enum CommandResult {
+    /// Display the text and continue reading input.
+    Show(String),
+    /// Exit the repl.
+    Quit,
+}
+
+const COMMANDS: &str = "available commands:
+  :env          show global bindings
+  :load <path>  evaluate a file in this session
+  :type <expr>  evaluate an expression and show its type
+  :reset        forget everything defined in this session
+  :quit         exit the repl";
+
+/// Runs a repl meta-command. COMMAND is a line of input starting with
+/// a `:` character.
+pub fn run_command(command: &str, evaluator: &mut Interpreter) -> CommandResult {
+    let command = command.trim();
+    let (name, arg) = match command.find(char::is_whitespace) {
+        Some(i) => (&command[..i], command[i..].trim()),
+        None => (command, ""),
+    };
+    CommandResult::Show(match name {
+        ":quit" => return CommandResult::Quit,
+        ":env" => show_env(evaluator),
+        ":load" => match crate::interpret_file_in(arg, evaluator) {
+            Ok(()) => format!("loaded {}", arg),
+            Err(e) => format!("error: {}", e),
+        },
+        ":type" => show_type(arg, evaluator),
+        ":reset" => {
+            evaluator.reset();
+            "enviroment reset".to_string()
+        }
+        _ => format!("unknown command {}\n{}", name, COMMANDS),
+    })
+}
+
+/// Lists the non-builtin bindings in the global enviroment.
+fn show_env(evaluator: &Interpreter) -> String {
+    let bindings: Vec<String> = evaluator
+        .global_env
+        .borrow()
+        .bindings()
+        .into_iter()
+        .filter(|(_, val)| !matches!(val, LustData::Builtin(_) | LustData::NativeFn(_)))
+        .map(|(name, val)| format!("{} = {}", name, val))
+        .collect();
+    if bindings.is_empty() {
+        "no bindings".to_string()
+    } else {
+        bindings.join("\n")
+    }
+}
+
+/// Evaluates SOURCE and describes the type of the result.
+fn show_type(source: &str, evaluator: &mut Interpreter) -> String {
+    let mut parser = Parser::new(source);
+    let res = parser.parse_expr();
+    match res.expr {
+        Some(expr) if res.errors.is_empty() => match evaluator.eval_to_data(&expr) {
+            Ok(val) => val.type_name().to_string(),
+            Err(e) => format!("error: {}", e),
+        },
+        _ => "usage: :type <expr>".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn show(command: &str, evaluator: &mut Interpreter) -> String {
+        match run_command(command, evaluator) {
+            CommandResult::Show(s) => s,
+            CommandResult::Quit => panic!("unexpected quit"),
+        }
+    }
+
+    #[test]
+    fn history_path_lookup() {
+        fn vars<'a>(set: &'a [(&str, &str)]) -> impl Fn(&str) -> Option<OsString> + 'a {
+            move |name| {
+                set.iter()
+                    .find(|(n, _)| *n == name)
+                    .map(|(_, v)| OsString::from(v))
+            }
         }
+        let all = [
+            ("LUST_HISTORY", "/tmp/lust_test_history"),
+            ("XDG_STATE_HOME", "/state"),
+            ("HOME", "/home/lust"),
+        ];
+        assert_eq!(
+            history_path(vars(&all)),
+            Some(PathBuf::from("/tmp/lust_test_history"))
+        );
+        assert_eq!(
+            history_path(vars(&all[1..])),
+            Some(PathBuf::from("/state/lust_history"))
+        );
+        assert_eq!(
+            history_path(vars(&all[2..])),
+            Some(PathBuf::from("/home/lust/.lust_history"))
+        );
+        assert_eq!(history_path(vars(&[])), None);
+    }
+
+    #[test]
+    fn quit() {
+        assert_eq!(
+            run_command(":quit", &mut Interpreter::new()),
+            CommandResult::Quit
+        );
+    }
+
+    #[test]
+    fn env_and_reset() {
+        let mut evaluator = Interpreter::new();
+        assert_eq!(show(":env", &mut evaluator), "no bindings");
+        show(":type (let x 1)", &mut evaluator);
+        show(":type (let x 2)", &mut evaluator);
+        assert_eq!(show(":env", &mut evaluator), "x = 2");
+        show(":reset", &mut evaluator);
+        assert_eq!(show(":env", &mut evaluator), "no bindings");
+    }
+
+    #[test]
+    fn type_of() {
+        let mut evaluator = Interpreter::new();
+        assert_eq!(show(":type (add 1 2)", &mut evaluator), "number");
+        assert_eq!(show(":type '(1 2)", &mut evaluator), "list");
+        assert_eq!(show(":type \"hi\"", &mut evaluator), "string");
+        assert_eq!(show(":type car", &mut evaluator), "builtin");
+        assert_eq!(show(":type", &mut evaluator), "usage: :type <expr>");
+    }
+
+    #[test]
+    fn load() {
+        let path = std::env::temp_dir().join("lust_repl_load.lisp");
+        std::fs::write(&path, "(let loaded 42)").unwrap();
+        let path = path.to_str().unwrap();
+
+        let mut evaluator = Interpreter::new();
+        assert_eq!(
+            show(&format!(":load {}", path), &mut evaluator),
+            format!("loaded {}", path)
+        );
+        assert_eq!(show(":env", &mut evaluator), "loaded = 42");
+        assert!(show(":load /no/such/file.lisp", &mut evaluator).starts_with("error: "));
+    }
+
+    #[test]
+    fn unknown() {
+        let res = show(":frobnicate", &mut Interpreter::new());
+        assert!(res.starts_with("unknown command :frobnicate"));
+        assert!(res.contains(":load <path>"));
     }
 }
@@ -0,0 +1,160 @@
+/// Converts Lust data to and from a canonical S-expression string.
+/// Unlike `Display` the output is always parseable and reading it
+/// back with `parse_sexpr` gives data equal to the original.
+use crate::interpreter::LustData;
+use crate::tokenbuffer::TokenBuffer;
+use crate::tokenizer::{TokenType, Tokenizer};
+
+impl LustData {
+    /// Serializes some data to its canonical S-expression. Errors if
+    /// the data contains something that can not be read back in like
+    /// a function or a lone character.
+    pub fn to_sexpr(&self) -> Result<String, String> {
+        let mut res = String::new();
+        write_sexpr(self, &mut res)?;
+        Ok(res)
+    }
+}
+
+fn write_sexpr(data: &LustData, res: &mut String) -> Result<(), String> {
+    if let Some(s) = data.stringify() {
+        res.push('"');
+        for c in s.chars() {
+            match c {
+                '\n' => res.push_str("\\n"),
+                '\t' => res.push_str("\\t"),
+                '"' => res.push_str("\\\""),
+                '\\' => res.push_str("\\\\"),
+                c => res.push(c),
+            }
+        }
+        res.push('"');
+        return Ok(());
+    }
+    match data {
+        LustData::Number(n) if n.is_finite() => res.push_str(&n.to_string()),
+        LustData::Number(n) => return Err(format!("can not serialize {}", n)),
+        LustData::Symbol(_) => {
+            let name = data.expect_symbol()?;
+            if !is_plain_symbol(name) {
+                return Err(format!("can not serialize symbol {:?}", name));
+            }
+            res.push_str(name);
+        }
+        LustData::Keyword(_) => res.push_str(&data.to_string()),
+        LustData::Cons(c) => {
+            res.push('(');
+            for (i, item) in c.into_iter().enumerate() {
+                if i != 0 {
+                    res.push(' ');
+                }
+                write_sexpr(item, res)?;
+            }
+            res.push(')');
+        }
+        _ => return Err(format!("can not serialize {}", data.type_name())),
+    }
+    Ok(())
+}
+
+/// Determines if NAME reads back in as a single symbol. Symbols made
+/// with `string->symbol` may contain spaces or look like numbers or
+/// keywords.
+fn is_plain_symbol(name: &str) -> bool {
+    if !matches!(LustData::from_id(name), LustData::Symbol(_)) {
+        return false;
+    }
+    let mut tokenizer = Tokenizer::new(name);
+    match tokenizer.next_token() {
+        Some(tok) => {
+            tok.ttype == TokenType::Id(name.to_string()) && tokenizer.next_token().is_none()
+        }
+        None => false,
+    }
+}
+
+/// Parses the S-expression in SOURCE. Strings are read as the list of
+/// characters they evaluate to rather than as a quoted string literal
+/// so that the output of `LustData::to_sexpr` reads back in unchanged.
+pub fn parse_sexpr(source: &str) -> Result<LustData, String> {
+    let mut buffer = TokenBuffer::new(source);
+    let res = read_sexpr(&mut buffer)?;
+    if buffer.has_next() {
+        return Err("unexpected input after expression".to_string());
+    }
+    Ok(res)
+}
+
+fn next_type(buffer: &mut TokenBuffer) -> Option<TokenType> {
+    buffer.peek_token().map(|(_, b)| b.advance().ttype)
+}
+
+fn read_sexpr(buffer: &mut TokenBuffer) -> Result<LustData, String> {
+    let ttype = next_type(buffer)
+        .ok_or_else(|| "unexpected end of input parsing expression".to_string())?;
+    match ttype {
+        TokenType::Number(n) => Ok(LustData::Number(n)),
+        TokenType::String(s) => Ok(LustData::string_value(&s)),
+        TokenType::Id(s) => Ok(LustData::from_id(&s)),
+        TokenType::Negate => match next_type(buffer) {
+            Some(TokenType::Number(n)) => Ok(LustData::Number(-n)),
+            _ => Err("expected number after -".to_string()),
+        },
+        TokenType::Quote => Ok(LustData::list(vec![
+            LustData::symbol("quote"),
+            read_sexpr(buffer)?,
+        ])),
+        TokenType::Oparen => {
+            let mut items = Vec::new();
+            loop {
+                match buffer.peek_token() {
+                    Some((tok, b)) if tok.ttype == TokenType::Cparen => {
+                        b.advance();
+                        break;
+                    }
+                    Some(_) => items.push(read_sexpr(buffer)?),
+                    None => return Err("unbalanced parenthesis".to_string()),
+                }
+            }
+            Ok(LustData::list(items))
+        }
+        TokenType::Cparen => Err("unexpected closing paren".to_string()),
+        TokenType::Quaziquote | TokenType::Comma => {
+            Err("quasiquotes can not be read as data".to_string())
+        }
+        TokenType::Unrecognized(s, _) => Err(format!("malformed token: {}", s)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let data = LustData::list(vec![
+            LustData::symbol("define"),
+            LustData::Number(-1.5),
+            LustData::string_value("say \"hi\"\n\\"),
+            LustData::list(vec![
+                LustData::get_empty_list(),
+                LustData::list(vec![LustData::Number(1e-3), LustData::symbol("x")]),
+            ]),
+        ]);
+        let s = data.to_sexpr().unwrap();
+        assert_eq!(s, "(define -1.5 \"say \\\"hi\\\"\\n\\\\\" (() (0.001 x)))");
+        assert_eq!(parse_sexpr(&s), Ok(data));
+    }
+
+    #[test]
+    fn unserializable() {
+        assert_eq!(
+            LustData::Char('a').to_sexpr(),
+            Err("can not serialize char".to_string())
+        );
+        assert!(LustData::symbol("two words").to_sexpr().is_err());
+        assert!(LustData::symbol("12").to_sexpr().is_err());
+        assert!(parse_sexpr("(1 2").is_err());
+        assert!(parse_sexpr("1 2").is_err());
+    }
+}
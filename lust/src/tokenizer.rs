@@ -8,8 +8,8 @@ pub enum TokenType {
     Number(f32),
     /// A string. Strings are made up of a sequence of non-newline
     /// characters that begin and end with '"'. The enclosed string
-    /// does not contain the opening and closing quotes. The \n and \t
-    /// escape sequences are supported.
+    /// does not contain the opening and closing quotes. The \n, \t,
+    /// \" and \\ escape sequences are supported.
     String(String),
     /// Opening parenthesis.
     Oparen,
@@ -52,9 +52,12 @@ impl<'a> Tokenizer<'a> {
     /// Makes a new tokenizer that starts at the beginning of the
     /// input string.
     pub fn new(input: &'a str) -> Self {
-        Self {
-            reader: Reader::new(input),
+        let mut reader = Reader::new(input);
+        // Skip the shebang line at the start of a script.
+        if input.starts_with("#!") {
+            reader.skip_line();
         }
+        Self { reader }
     }
 
     /// Gets the current location of the tokenizer in the source
@@ -181,6 +184,7 @@ impl<'a> Tokenizer<'a> {
                             'n' => res.push('\n'),
                             't' => res.push('\t'),
                             '"' => res.push('"'),
+                            '\\' => res.push('\\'),
                             c => {
                                 valid = false;
                                 res.push_str(&format!("\\{}", c).to_string());
@@ -14,8 +14,8 @@
 				       (do
 					(let s (car split))
 					(when s (print s))
-					(let a (car args))
+					(let a (when args (car args)))
 					(when a (print a))
-					(helper (cdr split) (cdr args))))))
+					(helper (cdr split) (when args (cdr args)))))))
 		    (helper sections args)
 		    (print "\n")))))
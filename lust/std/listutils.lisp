@@ -4,8 +4,8 @@
 (let starts-with (fn (list pred)
 		      (cond
 		       ((eq pred ()) #t)
-		       ((not (eq (car pred) (car list))) #f)
 		       ((gt (len pred) (len list)) #f)
+		       ((not (eq (car pred) (car list))) #f)
 		       (#t (starts-with (cdr list) (cdr pred))))))
 
 ;; Removes the first N items from LST
@@ -19,7 +19,8 @@
 ;; until WEDGE and the second containing all the items in LST after
 ;; WEDGE.
 (let split-until (fn (lst wedge)
-		      (when lst
+		      (if (eq lst ())
+			  (list () ())
 			(if (starts-with lst wedge)
 			    (list () (remove-n lst (len wedge)))
 			  (do
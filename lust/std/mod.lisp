@@ -16,7 +16,3 @@
 
 ;; Absolute value
 (let abs (fn (n) (if (lt n 0) (sub 0 n) n)))
-
-;; Modulo
-(let mod (fn (a b)
-	      (sub a (mul b (floor (div a b))))))
@@ -107,9 +107,10 @@
 
 ;; Returns the last item in a list.
 (let last (fn (l)
-	       (if (eq (cdr l) ())
-		   (car l)
-		 (last (cdr l)))))
+	       (cond
+		((eq l ()) ())
+		((eq (cdr l) ()) (car l))
+		(#t (last (cdr l))))))
 
 ;; Evaluates each argument in order and then returns the result of
 ;; evaluating the last one.
@@ -152,13 +153,11 @@
 		  `(condlist ,cases)))
 
 (let condlist (macro (items)
-		      (do
-		       (let rest (cdr items))
-		       (if items
-			   `(if ,(car (car items))
-				,(car (cdr (car items)))
-			      (condlist ,rest))
-			()))))
+		      (if items
+			  `(if ,(car (car items))
+			       ,(car (cdr (car items)))
+			     (condlist ,(cdr items)))
+			())))
 
 ;; `define` special form with the same form as scheme's. Allows for
 ;; defining functions and variables as follows:
@@ -167,12 +166,15 @@
 ;;   => 1
 ;;   lust> (define I (x) x)
 ;;   => (fn (x) x)
+;;   lust> (define K (x y) "Returns X." x)
+;;   => (fn (x y) x)
 ;;
 ;; Demonstrates use of the quaziquote form, cond, and errors.
 (let define (macro (symbol & args)
      	     (cond
 		((eq (len args) 1) `(letq ,symbol ,(car args)))
 		((eq (len args) 2) `(letq ,symbol ,`(fn ,(car args) ,(car (cdr args)))))
+		((eq (len args) 3) `(letq ,symbol ,`(fn ,(car args) ,(car (cdr args)) ,(car (cdr (cdr args))))))
 		(#t (error "wrong number of arguments for define macro")))))
 
 (letq char (macro (s)
@@ -0,0 +1,54 @@
+(import 'std)
+
+;; Streams are lazy lists. A stream is either the empty list or a
+;; list of its first item and a promise for the rest of the stream.
+
+;; Builds a stream from HEAD and TAIL. TAIL is an expression for the
+;; rest of the stream and is not evaluated until it is needed.
+(let stream-cons (macro (head tail)
+			`(list ,head (delay ,tail))))
+
+;; The name SICP uses for stream-cons.
+(let cons-stream stream-cons)
+
+;; Returns true if S is the empty stream.
+(let stream-null? (fn (s) (eq s ())))
+
+;; Returns the first item in S.
+(let stream-car (fn (s) (force (car s))))
+
+;; Returns the rest of S, evaluating it if needed.
+(let stream-cdr (fn (s) (force (car (cdr s)))))
+
+;; Returns a list of the first N items in S or all of them if S has
+;; fewer than N items.
+(let stream->list (fn (s n)
+		       (if (or (stream-null? s) (eq n 0))
+			   ()
+			 (cons (stream-car s) (stream->list (stream-cdr s) (sub n 1))))))
+
+;; Returns a stream of the first N items in S or all of them if S has
+;; fewer than N items. Items are only evaluated as they are needed.
+(let stream-take (fn (s n)
+		      (if (or (stream-null? s) (eq n 0))
+			  ()
+			(cons-stream (stream-car s) (stream-take (stream-cdr s) (sub n 1))))))
+
+;; Returns a stream which is the result of calling OP on each item in
+;; S.
+(let stream-map (fn (op s)
+		     (if (stream-null? s)
+			 ()
+		       (stream-cons (op (stream-car s)) (stream-map op (stream-cdr s))))))
+
+;; Returns a stream of the items in S that PRED returns true for.
+(let stream-filter (fn (pred s)
+			(cond
+			 ((stream-null? s) ())
+			 ((pred (stream-car s))
+			  (stream-cons (stream-car s) (stream-filter pred (stream-cdr s))))
+			 (#t (stream-filter pred (stream-cdr s))))))
+
+;; Returns the infinite stream of numbers counting up from N.
+(let stream-from (fn (n)
+		      (stream-cons n (stream-from (add n 1)))))
@@ -0,0 +1,59 @@
+// Counts the allocations made by the interpreter. This is its own test
+// binary as it replaces the global allocator.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::rc::Rc;
+
+use lust::interpreter::{Interpreter, LustData};
+use lust::sexpr::parse_sexpr;
+
+struct Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|a| a.set(a.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// The number of allocations made on this thread so far.
+fn allocations() -> usize {
+    ALLOCATIONS.with(Cell::get)
+}
+
+#[test]
+fn macroexpand_leaves_other_forms_alone() {
+    let mut evaluator = Interpreter::new();
+    lust::interpret_source("(let m (macro (x) (quote (add 1 2))))", "m", &mut evaluator).unwrap();
+    let env = evaluator.global_env.clone();
+    let form = parse_sexpr("(add (mul 2 3) (car (quote (1 2 3 4 5 6 7 8))))").unwrap();
+
+    let before = allocations();
+    for _ in 0..1000 {
+        let expanded = Interpreter::macroexpand(form.clone(), env.clone()).unwrap();
+        match (&expanded, &form) {
+            (LustData::Cons(a), LustData::Cons(b)) => assert!(Rc::ptr_eq(a, b)),
+            _ => panic!("expected a list"),
+        }
+    }
+    assert_eq!(allocations() - before, 0);
+
+    // Expanding a macro call does allocate so the count means
+    // something.
+    let call = parse_sexpr("(m 1)").unwrap();
+    let before = allocations();
+    Interpreter::macroexpand(call, env).unwrap();
+    assert!(allocations() > before);
+}
@@ -0,0 +1,59 @@
+// Compares resolving a builtin in the global enviroment, whose
+// bindings are in a hash map, to resolving it by scanning a vector of
+// the same bindings from the back, which is how enviroments used to
+// store them. Run with
+//
+//   cargo test --release --test bindings -- --ignored --nocapture
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use lust::interpreter::{Interpreter, LustData};
+
+const LOOKUPS: usize = 100_000;
+
+/// An enviroment's bindings in the order they were defined.
+struct VecEnv {
+    data: Vec<(String, LustData)>,
+}
+
+impl VecEnv {
+    fn resolve(&self, name: &str) -> Result<LustData, String> {
+        self.data
+            .iter()
+            .rev()
+            .find(|(n, _)| n == name)
+            .map(|(_, data)| data.clone())
+            .ok_or_else(|| format!("failed to resolve identifier {}", name))
+    }
+}
+
+fn time(f: impl Fn()) -> Duration {
+    let start = Instant::now();
+    for _ in 0..LOOKUPS {
+        f();
+    }
+    start.elapsed()
+}
+
+#[test]
+#[ignore = "benchmark"]
+fn hashing_is_faster_than_scanning() {
+    let evaluator = Interpreter::new();
+    let env = evaluator.global_env.clone();
+    let scanned = VecEnv {
+        data: env.borrow().bindings(),
+    };
+    // One of the first builtins installed, so the scan has to pass
+    // most of the others to find it.
+    let name = "quote";
+
+    let hashed = time(|| {
+        black_box(env.borrow().resolve(black_box(name)).unwrap());
+    });
+    let scanned = time(|| {
+        black_box(scanned.resolve(black_box(name)).unwrap());
+    });
+    println!("hashed: {:?}, scanned: {:?}", hashed, scanned);
+    assert!(hashed < scanned);
+}
@@ -0,0 +1,46 @@
+// Compares finding the function a call names by resolving its symbol
+// directly to finding it by evaluating the symbol like any other
+// expression, which is how calls used to find it. Run with
+//
+//   cargo test --release --test calls -- --ignored --nocapture
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use lust::intern::intern;
+use lust::interpreter::{Interpreter, LustData};
+use lust::parser::Parser;
+
+const CALLS: usize = 100_000;
+
+fn time(f: impl Fn()) -> Duration {
+    let start = Instant::now();
+    for _ in 0..CALLS {
+        f();
+    }
+    start.elapsed()
+}
+
+#[test]
+#[ignore = "benchmark"]
+fn resolving_callee_is_faster() {
+    let mut evaluator = Interpreter::new();
+    let expr =
+        Parser::new("(let count (fn (n acc) (if (eq n 0) acc (count (sub n 1) (add acc 1)))))")
+            .parse_expr()
+            .expr
+            .unwrap();
+    evaluator.eval_to_data(&expr).unwrap();
+    let env = evaluator.global_env.clone();
+    let id = intern("count");
+    let callee = LustData::Symbol(id);
+
+    let resolved = time(|| {
+        black_box(env.borrow().resolve_symbol(black_box(id)).unwrap());
+    });
+    let evaluated = time(|| {
+        black_box(Interpreter::eval_in_env(black_box(&callee), env.clone()).unwrap());
+    });
+    println!("resolved: {:?}, evaluated: {:?}", resolved, evaluated);
+    assert!(resolved < evaluated);
+}
@@ -0,0 +1,37 @@
+// Compares running fib(25) interpreted to running it compiled with
+// the closure compiler. Run with
+//
+//   cargo test --release --test compiler -- --ignored --nocapture
+
+use std::time::{Duration, Instant};
+
+use lust::interpreter::{Interpreter, LustData};
+use lust::parser::Parser;
+
+const FIB: &str = "(let fib (fn (n) (if (lt n 2) n (add (fib (sub n 1)) (fib (sub n 2))))))";
+
+/// Defines fib in an interpreter that compiles if COMPILE is set and
+/// returns how long calling it took.
+fn time_fib(compile: bool) -> Duration {
+    let mut evaluator = Interpreter::new();
+    evaluator.set_compile(compile);
+    let mut eval = |source: &str| {
+        let expr = Parser::new(source).parse_expr().expr.unwrap();
+        evaluator.eval_to_data(&expr).unwrap()
+    };
+    eval(FIB);
+    let start = Instant::now();
+    let res = eval("(fib 25)");
+    let elapsed = start.elapsed();
+    assert_eq!(res, LustData::Number(75025.0));
+    elapsed
+}
+
+#[test]
+#[ignore = "benchmark"]
+fn compiling_is_faster() {
+    let interpreted = time_fib(false);
+    let compiled = time_fib(true);
+    println!("interpreted: {:?}, compiled: {:?}", interpreted, compiled);
+    assert!(compiled < interpreted);
+}
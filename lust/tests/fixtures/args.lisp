@@ -0,0 +1,3 @@
+#!/usr/bin/env lust
+;; Binds the arguments passed to the script.
+(let script-args *args*)
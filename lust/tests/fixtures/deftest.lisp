@@ -0,0 +1,14 @@
+(let double (fn (x) (mul x 2)))
+
+(deftest double-positive
+  (assert-eq (double 2) 4))
+
+(deftest double-negative
+  (assert-eq (double -3) -6)
+  (assert (lt (double -1) 0)))
+
+(deftest "double is wrong"
+  (assert-eq (double 1) 3))
+
+(deftest undefined
+  (double missing))
@@ -87,6 +87,30 @@
 
 (do-test "char" (list (char "🦢")) "🦢")
 
+(import 'stream)
+
+(let naturals (stream-from 0))
+
+(do-test "stream->list" (stream->list naturals 5) '(0 1 2 3 4))
+
+(do-test "stream-null?" (stream-null? (stream-cdr (stream-cons 1 ()))) #t)
+
+(do-test "stream-map"
+	 (stream->list (stream-map (fn (n) (mul n n)) naturals) 4)
+	 '(0 1 4 9))
+
+(do-test "stream-filter"
+	 (stream->list (stream-filter (fn (n) (lt 2 n)) naturals) 3)
+	 '(3 4 5))
+
+(let fibs (fn (a b) (cons-stream a (fibs b (add a b)))))
+
+(do-test "stream-take" (stream->list (stream-take (fibs 0 1) 6) 10) '(0 1 1 2 3 5))
+
+(do-test "stream-take laziness"
+	 (stream-car (stream-take (cons-stream 1 (error "forced")) 2))
+	 1)
+
 (print "\nRan ")
 (print (add passed failed))
 (println " tests.")
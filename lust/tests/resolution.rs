@@ -0,0 +1,87 @@
+// Compares resolving symbols in enviroments keyed by interned ids to
+// resolving them in enviroments keyed by name, which is how
+// enviroments used to work. Run with
+//
+//   cargo test --release --test resolution -- --ignored --nocapture
+
+use std::cell::RefCell;
+use std::hint::black_box;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use indexmap::IndexMap;
+use lust::intern::intern;
+use lust::interpreter::{LustData, LustEnv};
+use rustc_hash::FxBuildHasher;
+
+/// How many enviroments deep the symbol is resolved from.
+const DEPTH: usize = 200;
+const LOOKUPS: usize = 20_000;
+
+/// An enviroment keyed by name.
+struct NamedEnv {
+    data: IndexMap<String, LustData, FxBuildHasher>,
+    outer: Option<Rc<RefCell<NamedEnv>>>,
+}
+
+impl NamedEnv {
+    fn resolve(&self, name: &str) -> Result<LustData, String> {
+        match self.data.get(name) {
+            Some(data) => Ok(data.clone()),
+            None => match self.outer {
+                Some(ref outer) => outer.borrow().resolve(name),
+                None => Err(format!("failed to resolve identifier {}", name)),
+            },
+        }
+    }
+}
+
+fn time(f: impl Fn()) -> Duration {
+    let start = Instant::now();
+    for _ in 0..LOOKUPS {
+        f();
+    }
+    start.elapsed()
+}
+
+#[test]
+#[ignore = "benchmark"]
+fn resolving_by_id_is_faster() {
+    let name = "a-reasonably-long-symbol-name";
+    let mut named = Rc::new(RefCell::new(NamedEnv {
+        data: IndexMap::default(),
+        outer: None,
+    }));
+    named
+        .borrow_mut()
+        .data
+        .insert(name.to_string(), LustData::Number(1.0));
+    let mut env = LustEnv::new();
+    env.borrow_mut()
+        .insert(name.to_string(), LustData::Number(1.0));
+    // Each level binds a few other names, as a function's parameters
+    // would.
+    for level in 0..DEPTH {
+        let mut data = IndexMap::default();
+        env = LustEnv::new_child(env);
+        for param in ["n", "acc", "items"] {
+            let param = format!("{}-{}", param, level);
+            data.insert(param.clone(), LustData::Number(0.0));
+            env.borrow_mut().insert(param, LustData::Number(0.0));
+        }
+        named = Rc::new(RefCell::new(NamedEnv {
+            data,
+            outer: Some(named),
+        }));
+    }
+
+    let id = intern(name);
+    let by_id = time(|| {
+        black_box(env.borrow().resolve_symbol(black_box(id)).unwrap());
+    });
+    let by_name = time(|| {
+        black_box(named.borrow().resolve(black_box(name)).unwrap());
+    });
+    println!("by id: {:?}, by name: {:?}", by_id, by_name);
+    assert!(by_id < by_name);
+}
@@ -0,0 +1,206 @@
+use std::cell::RefCell;
+use std::process::Command;
+use std::rc::Rc;
+use std::sync::Once;
+
+use lust::interpreter::{Interpreter, LustData};
+use lust::parser::Parser;
+
+fn resolve(evaluator: &Interpreter, name: &str) -> LustData {
+    evaluator.global_env.borrow().resolve(name).unwrap()
+}
+
+#[test]
+fn script_args() {
+    let mut evaluator = Interpreter::new();
+    let args = vec!["one".to_string(), "two".to_string()];
+    lust::run_script("tests/fixtures/args.lisp", &args, &mut evaluator).unwrap();
+    assert_eq!(
+        resolve(&evaluator, "script-args"),
+        LustData::list(vec![
+            LustData::string_value("one"),
+            LustData::string_value("two")
+        ])
+    );
+}
+
+#[test]
+fn script_error() {
+    let mut evaluator = Interpreter::new();
+    let res = lust::run_script("tests/fixtures/error.lisp", &[], &mut evaluator);
+    assert_eq!(
+        res,
+        Err(
+            "failed to resolve identifier undefined\nstack trace:\n  in add (2 arguments)"
+                .to_string()
+        )
+    );
+    assert_eq!(resolve(&evaluator, "x"), LustData::Number(1.0));
+}
+
+#[test]
+fn script_error_shown_once() {
+    let out = Command::new(env!("CARGO_BIN_EXE_lust"))
+        .arg("tests/fixtures/error.lisp")
+        .env("NO_COLOR", "1")
+        .output()
+        .unwrap();
+    assert_eq!(out.status.code(), Some(1));
+    assert!(out.stdout.is_empty());
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert_eq!(
+        stderr
+            .matches("failed to resolve identifier undefined")
+            .count(),
+        1,
+        "{}",
+        stderr
+    );
+    assert_eq!(stderr.matches("in add (2 arguments)").count(), 1);
+}
+
+#[test]
+fn system_builtins_need_flag() {
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_lust"))
+            .args(args)
+            .env("NO_COLOR", "1")
+            .output()
+            .unwrap()
+    };
+    let out = run(&["-e", "(shell \"true\")"]);
+    assert_eq!(out.status.code(), Some(1));
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(
+        stderr.contains("failed to resolve identifier shell"),
+        "{}",
+        stderr
+    );
+
+    let out = run(&["--allow-system", "-e", "(exit 3)"]);
+    assert_eq!(out.status.code(), Some(3));
+}
+
+#[test]
+fn missing_script() {
+    let res = lust::run_script("tests/fixtures/missing.lisp", &[], &mut Interpreter::new());
+    assert!(res.unwrap_err().starts_with("failed to read file"));
+}
+
+#[test]
+fn one_liner() {
+    let mut evaluator = Interpreter::new();
+    lust::run_expr("(let sum (add 1 2))", &[], &mut evaluator).unwrap();
+    assert_eq!(resolve(&evaluator, "sum"), LustData::Number(3.0));
+}
+
+#[test]
+fn assert_example() {
+    lust::run_script("lust-programs/assert.lisp", &[], &mut Interpreter::new()).unwrap();
+}
+
+#[test]
+fn deftest() {
+    let mut evaluator = Interpreter::new();
+    let output = Rc::new(RefCell::new(Vec::new()));
+    evaluator.set_output(output.clone());
+    lust::run_script("tests/fixtures/deftest.lisp", &[], &mut evaluator).unwrap();
+
+    let failures = |evaluator: &mut Interpreter, source: &str| {
+        let expr = Parser::new(source).parse_expr().expr.unwrap();
+        evaluator.eval_to_data(&expr).unwrap()
+    };
+    assert_eq!(
+        failures(&mut evaluator, "(run-tests)"),
+        LustData::Number(2.0)
+    );
+    let output = String::from_utf8(output.borrow().clone()).unwrap();
+    assert!(output.contains("test double-positive ... ok\n"));
+    assert!(output.contains("test double is wrong ... FAILED: assertion failed"));
+    assert!(output.ends_with("2 passed, 2 failed\n"));
+
+    assert_eq!(
+        failures(&mut evaluator, "(run-tests \"double\")"),
+        LustData::Number(1.0)
+    );
+    evaluator.clear_tests();
+    assert_eq!(
+        failures(&mut evaluator, "(run-tests)"),
+        LustData::Number(0.0)
+    );
+}
+
+/// Points LUSTPATH at the stdlib so the programs can import it. This
+/// is done once for the whole test binary, as tests run in parallel
+/// and changing the enviroment while another thread reads it is a
+/// race.
+fn use_std_path() {
+    static STD_PATH: Once = Once::new();
+    STD_PATH.call_once(|| std::env::set_var("LUSTPATH", "std/"));
+}
+
+/// Runs the script at PATH with an interpreter set up by SETUP and
+/// returns what it printed.
+fn run_captured(path: &str, setup: impl Fn(&mut Interpreter)) -> String {
+    let mut evaluator = Interpreter::new();
+    setup(&mut evaluator);
+    let output = Rc::new(RefCell::new(Vec::new()));
+    evaluator.set_output(output.clone());
+    lust::run_script(path, &[], &mut evaluator).unwrap();
+    let res = String::from_utf8(output.borrow().clone()).unwrap();
+    res
+}
+
+#[test]
+fn constant_folding_keeps_semantics() {
+    use_std_path();
+    for path in ["tests/main.lisp", "lust-programs/fib.lisp"] {
+        let expected = run_captured(path, |_| ());
+        assert!(!expected.is_empty(), "{}", path);
+        let folded = run_captured(path, |e| e.set_optimize(true));
+        assert_eq!(folded, expected, "{}", path);
+    }
+}
+
+/// Lists tests/main.lisp and every program in lust-programs.
+fn program_paths() -> Vec<String> {
+    let mut paths = vec!["tests/main.lisp".to_string()];
+    for entry in std::fs::read_dir("lust-programs").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|e| e == "lisp") {
+            paths.push(path.to_string_lossy().into_owned());
+        }
+    }
+    paths
+}
+
+/// Checks that every program prints the same with an interpreter set
+/// up by SETUP as it does by default.
+fn programs_match(setup: fn(&mut Interpreter)) {
+    use_std_path();
+    let paths = program_paths();
+    // Some of the programs nest deeper than the default test thread's
+    // stack allows, so they are run on a thread with a stack as big
+    // as the one lust runs on.
+    let runner = std::thread::Builder::new()
+        .stack_size(256 * 1024 * 1024)
+        .spawn(move || {
+            for path in paths {
+                let expected = run_captured(&path, |_| ());
+                let res = run_captured(&path, setup);
+                assert_eq!(res, expected, "{}", path);
+            }
+        })
+        .unwrap();
+    runner.join().unwrap();
+}
+
+#[test]
+fn compiling_keeps_semantics() {
+    programs_match(|e| e.set_compile(true));
+}
+
+#[test]
+fn bytecode_keeps_semantics() {
+    programs_match(|e| e.use_bytecode(true));
+}
@@ -0,0 +1,46 @@
+// Compares cloning a function, which is shared behind an Rc, to
+// copying it into a new Box, which is how functions used to be
+// cloned. Functions are cloned every time they are resolved. Run with
+//
+//   cargo test --release --test sharing -- --ignored --nocapture
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use lust::interpreter::{Interpreter, LustData, LustFn};
+use lust::parser::Parser;
+
+const CLONES: usize = 100_000;
+
+fn time(f: impl Fn()) -> Duration {
+    let start = Instant::now();
+    for _ in 0..CLONES {
+        f();
+    }
+    start.elapsed()
+}
+
+#[test]
+#[ignore = "benchmark"]
+fn sharing_is_faster_than_copying() {
+    let mut evaluator = Interpreter::new();
+    let source = "(let walk (fn (items count)
+                    \"Counts the items in a list, one call for each.\"
+                    (if (eq items ()) count (walk (cdr items) (add count 1)))))";
+    let expr = Parser::new(source).parse_expr().expr.unwrap();
+    evaluator.eval_to_data(&expr).unwrap();
+    let func = evaluator.global_env.borrow().resolve("walk").unwrap();
+    let inner = match func {
+        LustData::Fn(ref f) => f.clone(),
+        _ => panic!("walk isn't a function"),
+    };
+
+    let shared = time(|| {
+        black_box(black_box(&func).clone());
+    });
+    let copied = time(|| {
+        black_box(Box::new(LustFn::clone(black_box(&inner))));
+    });
+    println!("shared: {:?}, copied: {:?}", shared, copied);
+    assert!(shared < copied);
+}